@@ -1,10 +1,10 @@
 mod utils;
 mod strategies;
 
-use strategies::{sniper::Sniper, grid::Grid, trend::Trend};
-use utils::{wallet::Wallet, telegram::{TelegramBot, BotCommand}, trade_log::TradeLog, price_feed};
+use strategies::{sniper::Sniper, grid::Grid, grid_state::GridSpec, trend::Trend, dca::Dca, stop_loss::StopLoss, script::{Script, ScriptHandle, ScriptStrategy}, handle::StrategyHandle};
+use utils::{wallet::Wallet, telegram::{TelegramBot, BotCommand}, trade_log::TradeLog, price_feed, price_guard::PriceGuard, pool_events::{PoolEventBus, LaunchEventBus}, pump_fun, pool_registry::PoolRegistry, slot_clock::SlotClock, token_metadata::{TokenMetadataCache, resolve_metadata, symbol_for}};
 use tokio::sync::mpsc;
-use std::collections::HashMap;
+use std::collections::{HashMap, hash_map::Entry};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -12,110 +12,253 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let telegram = TelegramBot::new();
     let trade_log = TradeLog::new()?;
     let wallet = Wallet::new(telegram.clone(), trade_log.clone());
+    let slot_clock = SlotClock::start()?;
+    let price_guard = PriceGuard::new(slot_clock.clone());
+    let pool_registry = PoolRegistry::init()?;
+    log::info!("Loaded {} cached pools", pool_registry.len());
     let tokens = vec![
         "A3eME5Ceth4uKS29V4a3eS7Znx2H99v3Hkw3M49eN7jR".to_string(), // PENG
         "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263".to_string(), // BONK
         "EKpQGSJtjMFqKZ9u4uhkkR3eFfrk7unuZHKtvsH7BVvb".to_string(), // WIF
         "So11111111111111111111111111111111111111112".to_string(), // SOL
     ];
+    let metadata_cache = TokenMetadataCache::init()?;
+    for token in &tokens {
+        metadata_cache.trust(token);
+        match resolve_metadata(token).await {
+            Ok(metadata) => log::info!("Trading {} ({})", metadata.symbol, token),
+            Err(e) => log::warn!("No metadata for {}: {}", token, e),
+        }
+    }
+
+    let resumed = StopLoss::resume_all(&wallet, &telegram, &price_guard)?;
+    log::info!("Resumed {} open stop-losses", resumed);
 
     let (tx, mut rx) = mpsc::channel::<BotCommand>(100);
-    let (pool_tx, pool_rx) = mpsc::channel::<String>(100);
-    let mut strategies: HashMap<String, (Sniper, Grid, Trend)> = HashMap::new();
-    let mut active_tokens: Vec<String> = vec![];
+    tokio::spawn({
+        let telegram = telegram.clone();
+        let trade_log = trade_log.clone();
+        async move { telegram.start(tx, trade_log).await }
+    });
+    let pool_bus = PoolEventBus::new(1024);
+    let launch_bus = LaunchEventBus::new(4096);
+    let mut strategies: HashMap<String, (Sniper, Grid, Trend, Dca)> = HashMap::new();
+    // Strategy tasks running for each started token, by strategy name
+    let mut running: HashMap<String, HashMap<&str, StrategyHandle>> = HashMap::new();
+    // Strategies loaded from files, by name
+    let mut scripts: HashMap<String, ScriptHandle> = HashMap::new();
+    // Snipes any new pool passing its entry filters, controlled with the "new" token
+    let mut launch_sniper = Sniper::new(wallet.clone(), telegram.clone(), price_guard.clone(), slot_clock.clone());
 
     for token in &tokens {
         strategies.insert(
             token.clone(),
             (
-                Sniper::new(wallet.clone(), telegram.clone()),
+                Sniper::new(wallet.clone(), telegram.clone(), price_guard.clone(), slot_clock.clone()),
                 Grid::new(
                     wallet.clone(),
                     telegram.clone(),
+                    price_guard.clone(),
                     token.clone(),
                     vec![0.000018, 0.000019, 0.00002, 0.000021],
                     1000.0,
                 ),
-                Trend::new(wallet.clone(), telegram.clone(), token.clone(), 14),
+                Trend::new(wallet.clone(), telegram.clone(), price_guard.clone(), token.clone(), 14),
+                Dca::new(wallet.clone(), telegram.clone(), price_guard.clone(), token.clone()),
             ),
         );
     }
 
-    tokio::spawn(async move {
-        price_feed::monitor_new_pools(&telegram, pool_tx).await.unwrap();
+    tokio::spawn({
+        let telegram = telegram.clone();
+        let pool_bus = pool_bus.clone();
+        async move {
+            if let Err(e) = price_feed::monitor_new_pools(&telegram, pool_bus).await {
+                log::error!("Pool monitor stopped: {}", e);
+            }
+        }
+    });
+
+    tokio::spawn({
+        let telegram = telegram.clone();
+        let launch_bus = launch_bus.clone();
+        let pools = pool_bus.subscribe("pump_fun");
+        async move {
+            if let Err(e) = pump_fun::monitor_pump_fun(&telegram, launch_bus, pools).await {
+                log::error!("pump.fun monitor stopped: {}", e);
+            }
+        }
+    });
+
+    // Keep the pool registry current from new-pool events
+    tokio::spawn({
+        let mut pools = pool_bus.subscribe("pool_registry");
+        async move {
+            while let Some(event) = pools.recv().await {
+                if let Err(e) = pool_registry.insert_new_pool(&event) {
+                    log::error!("Failed to cache pool {}: {}", event.pool_id, e);
+                }
+            }
+        }
+    });
+
+    // Log every detected pool independently of the snipers
+    tokio::spawn({
+        let mut pools = pool_bus.subscribe("logger");
+        async move {
+            while let Some(event) = pools.recv().await {
+                log::info!(
+                    "New pool {} base={} quote={} open_time={} slot={} tx={}",
+                    event.pool_id, event.base_mint, event.quote_mint, event.open_time, event.slot, event.signature
+                );
+            }
+        }
     });
 
     // Handle commands
     while let Some(command) = rx.recv().await {
         match command {
+            BotCommand::Start(token) if token == "new" => {
+                if let Entry::Vacant(entry) = running.entry(token) {
+                    let pools = pool_bus.subscribe("sniper:new");
+                    let launches = launch_bus.subscribe("sniper:new");
+                    entry.insert(HashMap::from([("sniper", launch_sniper.spawn(None, pools, launches))]));
+                    telegram.send_message("Started sniping new pools").await?;
+                }
+            }
             BotCommand::Start(token) => {
-                if tokens.contains(&token) && !active_tokens.contains(&token) {
-                    active_tokens.push(token.clone());
-                    let (sniper, grid, trend) = strategies.get(&token).unwrap();
-                    let pool_rx_sniper = pool_rx.clone();
-                    tokio::spawn({
-                        let token = token.clone();
-                        let sniper = sniper.clone();
-                        async move { sniper.start(token, pool_rx_sniper).await.unwrap() }
-                    });
-                    tokio::spawn({
-                        let token = token.clone();
-                        let grid = grid.clone();
-                        async move { grid.start().await.unwrap() }
-                    });
-                    tokio::spawn({
-                        let token = token.clone();
-                        let trend = trend.clone();
-                        async move { trend.start().await.unwrap() }
-                    });
+                if tokens.contains(&token) && !running.contains_key(&token) {
+                    let (sniper, grid, trend, dca) = strategies.get(&token).unwrap();
+                    let pools = pool_bus.subscribe(&format!("sniper:{}", token));
+                    let launches = launch_bus.subscribe(&format!("sniper:{}", token));
+                    running.insert(
+                        token.clone(),
+                        HashMap::from([
+                            ("sniper", sniper.spawn(Some(token.clone()), pools, launches)),
+                            ("grid", grid.spawn()),
+                            ("trend", trend.spawn()),
+                            ("dca", dca.spawn()),
+                        ]),
+                    );
                     telegram.send_message(&format!("Started strategies for {}", token)).await?;
                 }
             }
             BotCommand::Stop(token) => {
-                if let Some(pos) = active_tokens.iter().position(|t| t == &token) {
-                    active_tokens.remove(pos);
+                // Open positions keep their stop-losses, which a later start picks up again
+                if let Some(handles) = running.remove(&token) {
+                    for handle in handles.into_values() {
+                        handle.stop().await;
+                    }
                     telegram.send_message(&format!("Stopped strategies for {}", token)).await?;
                 }
             }
+            BotCommand::Resume(token) => {
+                price_guard.resume(&token);
+                telegram.send_message(&format!("Resumed price feed for {}", token)).await?;
+            }
             BotCommand::Balance(token) => {
-                wallet.get_balance(&token).await?;
+                let balance = wallet.get_balance(&token).await?;
+                let price = price_feed::get_price(&token, "SOL", &telegram).await?;
+                let sol_usd = price_feed::get_sol_usd_price().await?;
+                let value = balance * price;
+                telegram
+                    .send_message(&format!(
+                        "Value of {} {}: {:.6} SOL (${:.2})",
+                        balance, token, value, value * sol_usd
+                    ))
+                    .await?;
             }
             BotCommand::Status => {
-                let status = if active_tokens.is_empty() {
+                let status = if running.is_empty() {
                     "No active strategies".to_string()
                 } else {
-                    format!("Active tokens: {:?}", active_tokens)
+                    let mut active: Vec<String> = running.keys().map(|t| symbol_for(t)).collect();
+                    active.sort();
+                    format!("Active tokens: {}", active.join(", "))
                 };
                 telegram.send_message(&status).await?;
             }
+            BotCommand::SetParams(token, strategy, key, value) if token == "new" && strategy == "sniper" => {
+                if let Err(e) = launch_sniper.set_filter(&key, &value).await {
+                    telegram.send_message(&format!("Invalid sniper filter: {}", e)).await?;
+                }
+            }
             BotCommand::SetParams(token, strategy, key, value) => {
-                if let Some((sniper, grid, trend)) = strategies.get_mut(&token) {
+                if let Some((sniper, grid, trend, dca)) = strategies.get_mut(&token) {
                     match strategy.as_str() {
                         "sniper" => {
                             if key == "profit_target" {
                                 if let Ok(target) = value.parse::<f64>() {
-                                    sniper.set_profit_target(target);
+                                    sniper.set_profit_target(target).await?;
+                                }
+                            } else if key == "min_safety_score" {
+                                if let Ok(score) = value.parse::<u32>() {
+                                    sniper.set_min_safety_score(score).await?;
                                 }
+                            } else if key == "take_profit" {
+                                if let Err(e) = sniper.set_take_profit(&value).await {
+                                    telegram.send_message(&format!("Invalid take-profit ladder: {}", e)).await?;
+                                }
+                            } else if key == "break_even" {
+                                if let Ok(enabled) = value.parse::<bool>() {
+                                    sniper.set_break_even(enabled).await?;
+                                }
+                            } else if let Err(e) = sniper.set_filter(&key, &value).await {
+                                telegram.send_message(&format!("Invalid sniper filter: {}", e)).await?;
                             }
                         }
                         "grid" => {
-                            if key == "grid_levels" {
-                                let levels: Vec<f64> = value
+                            let result = match key.as_str() {
+                                "grid_levels" => match value
                                     .split(",")
-                                    .map(|v| v.parse::<f64>().unwrap())
-                                    .collect();
-                                grid.set_grid_levels(levels);
+                                    .map(|v| v.parse::<f64>())
+                                    .collect::<Result<Vec<f64>, _>>()
+                                {
+                                    Ok(levels) => grid.set_grid_levels(levels).await,
+                                    Err(_) => Err("Levels must be comma-separated numbers".into()),
+                                },
+                                "range" => match GridSpec::parse(&value) {
+                                    Ok(spec) => grid.set_grid_range(spec).await,
+                                    Err(e) => Err(e.into()),
+                                },
+                                _ => grid.set_dynamic(&key, &value).await,
+                            };
+                            if let Err(e) = result {
+                                telegram.send_message(&format!("Grid not changed: {}", e)).await?;
                             }
                         }
                         "trend" => {
-                            if key == "rsi_threshold" {
-                                if let Ok(threshold) = value.parse::<f64>() {
-                                    trend.set_rsi_threshold(threshold);
-                                }
-                            } else if key == "use_ai" {
-                                if let Ok(use_ai) = value.parse::<bool>() {
-                                    trend.set_use_ai(use_ai);
+                            let result = match key.as_str() {
+                                "rsi_threshold" => match value.parse::<f64>() {
+                                    Ok(threshold) => trend.set_rsi_threshold(threshold).await,
+                                    Err(_) => Err("RSI threshold must be a number".into()),
+                                },
+                                "use_ai" => match value.parse::<bool>() {
+                                    Ok(use_ai) => trend.set_use_ai(use_ai).await,
+                                    Err(_) => Err("use_ai must be true or false".into()),
+                                },
+                                _ => trend.set_param(&key, &value).await,
+                            };
+                            if let Err(e) = result {
+                                telegram.send_message(&format!("Trend not changed: {}", e)).await?;
+                            } else if let Some(handles) = running.get_mut(&token)
+                                && let Some(handle) = handles.remove("trend")
+                            {
+                                // The running copy was cloned at start, so restart it with the new settings
+                                handle.stop().await;
+                                handles.insert("trend", trend.spawn());
+                            }
+                        }
+                        "dca" => {
+                            if let Err(e) = dca.set_param(&key, &value).await {
+                                telegram.send_message(&format!("DCA not changed: {}", e)).await?;
+                            } else if let Some(handles) = running.get_mut(&token) {
+                                // The running copy was cloned at start, so restart it with the new settings.
+                                // A plan that already finished is started again too, e.g. after a budget raise
+                                if let Some(handle) = handles.remove("dca") {
+                                    handle.stop().await;
                                 }
+                                handles.insert("dca", dca.spawn());
                             }
                         }
                         _ => {
@@ -124,13 +267,39 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     }
                 }
             }
+            BotCommand::LoadStrategy(name) => match Script::load(&name) {
+                Ok(script) => {
+                    // A reload replaces the running copy; an open position stays with its stop-loss
+                    // and is picked up again by the new copy, along with the sell rules already fired
+                    let fired = match scripts.remove(&name) {
+                        Some(handle) => handle.stop().await,
+                        None => Default::default(),
+                    };
+                    let runner = ScriptStrategy::new(script, wallet.clone(), telegram.clone(), price_guard.clone());
+                    scripts.insert(name.clone(), runner.spawn(fired));
+                    telegram.send_message(&format!("Loaded strategy {}", name)).await?;
+                }
+                Err(e) => {
+                    telegram.send_message(&format!("Strategy {} not loaded: {}", name, e)).await?;
+                }
+            },
+            BotCommand::UnloadStrategy(name) => {
+                match scripts.remove(&name) {
+                    Some(handle) => {
+                        handle.stop().await;
+                        telegram.send_message(&format!("Unloaded strategy {}; any open position keeps its stop-loss", name)).await?;
+                    }
+                    None => telegram.send_message(&format!("Strategy {} is not loaded", name)).await?,
+                }
+            }
             BotCommand::Profit(token) => {
                 let current_price = price_feed::get_price(&token, "SOL", &telegram).await?;
-                let (profit, percentage) = trade_log.calculate_profit(&token, current_price)?;
+                let sol_usd = price_feed::get_sol_usd_price().await?;
+                let profit = trade_log.calculate_profit(&token, current_price, sol_usd)?;
                 telegram
                     .send_message(&format!(
-                        "Profit for {}: ${:.2} ({:.2}%)",
-                        token, profit, percentage
+                        "Profit for {}: {:.6} SOL ({:.2}%) / ${:.2} ({:.2}%)",
+                        token, profit.sol, profit.sol_percentage, profit.usd, profit.usd_percentage
                     ))
                     .await?;
             }
@@ -0,0 +1,343 @@
+use crate::utils::{wallet::Wallet, price_guard::PriceGuard, price_feed::get_checked_quote, liquidity::cap_order_size};
+use crate::utils::candles::{fetch_candles, parse_timeframe};
+use crate::utils::schedule::CronSchedule;
+use crate::utils::telegram::TelegramBot;
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use crate::utils::price_feed::sol_to_lamports;
+use std::env;
+use std::sync::Mutex;
+use crate::strategies::handle::{sleep_or_stop, StrategyHandle};
+use tokio::sync::watch;
+use tokio::time::Duration;
+
+/// Budget left below this is treated as spent.
+const MIN_BUY_SOL: f64 = 0.001;
+
+/// Parses `below:multiplier` pairs, e.g. `0.05:1.5,0.15:2` buys 1.5x when price is
+/// at least 5% below its moving average and 2x at 15% below.
+pub fn parse_dip_boost(value: &str) -> Result<Vec<(f64, f64)>, String> {
+    if value.trim().is_empty() || value == "off" {
+        return Ok(vec![]);
+    }
+    let mut boosts = value
+        .split(',')
+        .map(|pair| {
+            let (below, multiplier) = pair.trim().split_once(':').ok_or_else(|| format!("Expected below:multiplier, got {}", pair))?;
+            let below = below.parse::<f64>().map_err(|_| format!("Invalid dip {}", below))?;
+            let multiplier = multiplier.parse::<f64>().map_err(|_| format!("Invalid multiplier {}", multiplier))?;
+            if below <= 0.0 || below >= 1.0 || multiplier <= 0.0 {
+                return Err(format!("Boost {} needs a dip in (0, 1) and a positive multiplier", pair));
+            }
+            Ok((below, multiplier))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    boosts.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    Ok(boosts)
+}
+
+/// Parses an end date as `YYYY-MM-DD` or RFC 3339 into an exclusive bound. A date
+/// includes its whole day, so it ends at the start of the next day (UTC).
+fn parse_end(value: &str) -> Result<Option<DateTime<Utc>>, String> {
+    if value == "none" {
+        return Ok(None);
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.succ_opt().and_then(|next| next.and_hms_opt(0, 0, 0)).map(|t| t.and_utc()));
+    }
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| Some(t.with_timezone(&Utc)))
+        .map_err(|_| format!("Invalid end date {}, use YYYY-MM-DD", value))
+}
+
+/// Buys a fixed SOL amount of a token on a schedule until its budget is spent or
+/// its end date passes, buying more when price dips below its moving average.
+/// Totals are kept in `trades.db`, so the budget holds across restarts.
+pub struct Dca {
+    wallet: Wallet,
+    telegram: TelegramBot,
+    price_guard: PriceGuard,
+    token_mint: String,
+    /// SOL per scheduled buy before dip boosts; 0 disables DCA.
+    amount_sol: f64,
+    schedule: CronSchedule,
+    budget_sol: f64,
+    end: Option<DateTime<Utc>>,
+    ma_period: usize,
+    ma_timeframe: String,
+    /// `(below, multiplier)` sorted by dip; the deepest dip reached applies.
+    dip_boost: Vec<(f64, f64)>,
+}
+
+impl Dca {
+    pub fn new(wallet: Wallet, telegram: TelegramBot, price_guard: PriceGuard, token_mint: String) -> Self {
+        let schedule = env::var("DCA_SCHEDULE")
+            .ok()
+            .and_then(|v| CronSchedule::parse(&v).map_err(|e| log::warn!("Ignoring DCA_SCHEDULE: {}", e)).ok())
+            .unwrap_or_else(|| CronSchedule::parse("@daily").unwrap());
+        Dca {
+            wallet,
+            telegram,
+            price_guard,
+            token_mint,
+            amount_sol: env::var("DCA_AMOUNT_SOL").ok().and_then(|v| v.parse().ok()).unwrap_or(0.0),
+            schedule,
+            budget_sol: env::var("DCA_BUDGET_SOL").ok().and_then(|v| v.parse().ok()).unwrap_or(0.0),
+            end: env::var("DCA_END").ok().and_then(|v| parse_end(&v).ok()).flatten(),
+            ma_period: env::var("DCA_MA_PERIOD").ok().and_then(|v| v.parse().ok()).unwrap_or(20),
+            ma_timeframe: env::var("DCA_MA_TIMEFRAME").unwrap_or_else(|_| "1d".to_string()),
+            dip_boost: env::var("DCA_DIP_BOOST").ok().and_then(|v| parse_dip_boost(&v).ok()).unwrap_or_default(),
+        }
+    }
+
+    /// Sets `amount`, `schedule`, `budget`, `end`, `ma_period`, `ma_timeframe` or
+    /// `dip_boost`; `reset` clears the amount already spent.
+    pub async fn set_param(&mut self, key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let positive = |name: &str| -> Result<f64, String> {
+            match value.parse::<f64>() {
+                Ok(amount) if amount >= 0.0 => Ok(amount),
+                _ => Err(format!("{} must be a non-negative number", name)),
+            }
+        };
+        match key {
+            "amount" => self.amount_sol = positive("amount")?,
+            "budget" => self.budget_sol = positive("budget")?,
+            "schedule" => self.schedule = CronSchedule::parse(value)?,
+            "end" => self.end = parse_end(value)?,
+            "ma_period" => match value.parse::<usize>() {
+                Ok(period) if period > 0 => self.ma_period = period,
+                _ => return Err("ma_period must be a positive number of candles".into()),
+            },
+            "ma_timeframe" => {
+                parse_timeframe(value)?;
+                self.ma_timeframe = value.to_string();
+            }
+            "dip_boost" => self.dip_boost = parse_dip_boost(value)?,
+            "reset" => DcaStore::new()?.reset(&self.token_mint)?,
+            _ => return Err(format!("Unknown DCA parameter {}", key).into()),
+        }
+        self.telegram
+            .send_message(&format!("Set DCA {} for {} to {}", key, self.token_mint, value))
+            .await?;
+        Ok(())
+    }
+
+    /// Runs the plan in its own task until it finishes or `StrategyHandle::stop`.
+    pub fn spawn(&self) -> StrategyHandle {
+        let dca = self.clone();
+        StrategyHandle::spawn(move |stopped| async move {
+            if let Err(e) = dca.start(stopped).await {
+                log::error!("DCA for {} stopped: {}", dca.token_mint, e);
+            }
+        })
+    }
+
+    /// Runs until the budget is spent, the end date passes or it is stopped. Does nothing
+    /// unless both an amount and a budget are set. Runs missed while stopped are skipped.
+    async fn start(&self, mut stopped: watch::Receiver<bool>) -> Result<(), Box<dyn std::error::Error>> {
+        if self.amount_sol <= 0.0 || self.budget_sol <= 0.0 {
+            log::info!("DCA for {} has no amount or budget set", self.token_mint);
+            return Ok(());
+        }
+        let store = DcaStore::new()?;
+        self.telegram
+            .send_message(&format!(
+                "DCA for {}: {} SOL on '{}', budget {} SOL ({} SOL spent){}",
+                self.token_mint, self.amount_sol, self.schedule, self.budget_sol,
+                store.load(&self.token_mint)?.spent_sol,
+                self.end.map(|end| format!(", until {}", end.to_rfc3339())).unwrap_or_default()
+            ))
+            .await?;
+        loop {
+            let now = Utc::now();
+            let next = match self.schedule.next_after(now) {
+                Some(next) if self.end.map(|end| next < end).unwrap_or(true) => next,
+                _ => {
+                    self.finish(&store, "its end date has passed").await?;
+                    return Ok(());
+                }
+            };
+            if sleep_or_stop((next - now).to_std().unwrap_or(Duration::ZERO), &mut stopped).await {
+                return Ok(());
+            }
+
+            let totals = store.load(&self.token_mint)?;
+            let remaining = self.budget_sol - totals.spent_sol;
+            if remaining < MIN_BUY_SOL {
+                self.finish(&store, "its budget is spent").await?;
+                return Ok(());
+            }
+            if let Err(e) = self.buy(&store, remaining).await.map_err(|e| e.to_string()) {
+                log::warn!("DCA buy for {} failed: {}", self.token_mint, e);
+                let message = format!("DCA buy for {} skipped: {}", self.token_mint, e);
+                if let Err(e) = self.telegram.send_message(&message).await.map_err(|e| e.to_string()) {
+                    log::warn!("Failed to report skipped DCA buy for {}: {}", self.token_mint, e);
+                }
+            }
+        }
+    }
+
+    async fn buy(&self, store: &DcaStore, remaining: f64) -> Result<(), Box<dyn std::error::Error>> {
+        let price = self.price_guard.get_price(&self.token_mint, "SOL", &self.telegram).await?;
+        let (multiplier, dip) = self.boost(price).await;
+        let sol = (self.amount_sol * multiplier).min(remaining);
+        let amount = cap_order_size(&self.token_mint, "buy", sol / price, price, &self.telegram).await?;
+        let quote = get_checked_quote("SOL", &self.token_mint, sol_to_lamports(price * amount), &self.telegram).await?;
+        self.wallet.swap(&quote, &self.token_mint, "buy", price, amount).await?;
+        let totals = store.record_buy(&self.token_mint, price * amount, amount)?;
+        // The buy is recorded either way, so a failed notification must not report it as failed
+        let message = format!(
+            "DCA buy {} for {}: {:.4} SOL at {}{}. {:.4} of {} SOL spent, average price {}",
+            totals.buys, self.token_mint, price * amount, price,
+            dip.map(|d| format!(" ({:.1}% below MA, x{})", d * 100.0, multiplier)).unwrap_or_default(),
+            totals.spent_sol, self.budget_sol, totals.spent_sol / totals.tokens
+        );
+        if let Err(e) = self.telegram.send_message(&message).await.map_err(|e| e.to_string()) {
+            log::warn!("Failed to report DCA buy for {}: {}", self.token_mint, e);
+        }
+        Ok(())
+    }
+
+    /// The dip-boost multiplier for `price` and how far below the moving average it is.
+    /// Without candles the plain amount is bought.
+    async fn boost(&self, price: f64) -> (f64, Option<f64>) {
+        if self.dip_boost.is_empty() {
+            return (1.0, None);
+        }
+        let candles = match fetch_candles(&self.token_mint, &self.ma_timeframe, self.ma_period, &self.telegram).await {
+            Ok(candles) if candles.len() >= self.ma_period => candles,
+            Ok(_) => return (1.0, None),
+            Err(e) => {
+                log::warn!("No moving average for DCA on {}: {}", self.token_mint, e);
+                return (1.0, None);
+            }
+        };
+        let average = candles.iter().map(|c| c.close).sum::<f64>() / candles.len() as f64;
+        let dip = 1.0 - price / average;
+        let multiplier = self
+            .dip_boost
+            .iter()
+            .filter(|(below, _)| dip >= *below)
+            .map(|(_, multiplier)| *multiplier)
+            .next_back()
+            .unwrap_or(1.0);
+        (multiplier, (dip > 0.0).then_some(dip))
+    }
+
+    async fn finish(&self, store: &DcaStore, reason: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let totals = store.load(&self.token_mint)?;
+        let average = if totals.tokens > 0.0 { totals.spent_sol / totals.tokens } else { 0.0 };
+        self.telegram
+            .send_message(&format!(
+                "DCA for {} finished because {}: {} buys, {:.4} SOL for {} tokens, average price {}",
+                self.token_mint, reason, totals.buys, totals.spent_sol, totals.tokens, average
+            ))
+            .await?;
+        Ok(())
+    }
+
+    pub fn clone(&self) -> Self {
+        Dca {
+            wallet: self.wallet.clone(),
+            telegram: TelegramBot::new(),
+            price_guard: self.price_guard.clone(),
+            token_mint: self.token_mint.clone(),
+            amount_sol: self.amount_sol,
+            schedule: self.schedule.clone(),
+            budget_sol: self.budget_sol,
+            end: self.end,
+            ma_period: self.ma_period,
+            ma_timeframe: self.ma_timeframe.clone(),
+            dip_boost: self.dip_boost.clone(),
+        }
+    }
+}
+
+/// What a DCA plan has bought so far.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DcaTotals {
+    pub spent_sol: f64,
+    pub tokens: f64,
+    pub buys: u32,
+}
+
+/// DCA totals per token in SQLite.
+pub struct DcaStore {
+    conn: Mutex<Connection>,
+}
+
+impl DcaStore {
+    pub fn new() -> rusqlite::Result<Self> {
+        let conn = Connection::open("trades.db")?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS dca_plans (
+                token_mint TEXT PRIMARY KEY,
+                spent_sol REAL NOT NULL,
+                tokens REAL NOT NULL,
+                buys INTEGER NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+            []
+        )?;
+        Ok(DcaStore { conn: Mutex::new(conn) })
+    }
+
+    pub fn load(&self, token_mint: &str) -> rusqlite::Result<DcaTotals> {
+        let totals = self.conn
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT spent_sol, tokens, buys FROM dca_plans WHERE token_mint = ?",
+                params![token_mint],
+                |row| Ok(DcaTotals { spent_sol: row.get(0)?, tokens: row.get(1)?, buys: row.get(2)? })
+            )
+            .optional()?;
+        Ok(totals.unwrap_or_default())
+    }
+
+    /// Adds a buy and returns the new totals.
+    pub fn record_buy(&self, token_mint: &str, sol: f64, tokens: f64) -> rusqlite::Result<DcaTotals> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO dca_plans (token_mint, spent_sol, tokens, buys, updated_at) VALUES (?, ?, ?, 1, ?)
+             ON CONFLICT(token_mint) DO UPDATE SET spent_sol = spent_sol + excluded.spent_sol,
+                tokens = tokens + excluded.tokens, buys = buys + 1, updated_at = excluded.updated_at",
+            params![token_mint, sol, tokens, Utc::now().to_rfc3339()]
+        )?;
+        self.load(token_mint)
+    }
+
+    pub fn reset(&self, token_mint: &str) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute("DELETE FROM dca_plans WHERE token_mint = ?", params![token_mint])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn end_dates_include_the_whole_day() {
+        let end = parse_end("2026-12-31").unwrap().unwrap();
+        assert_eq!(end, Utc.with_ymd_and_hms(2027, 1, 1, 0, 0, 0).unwrap());
+        let last_run = Utc.with_ymd_and_hms(2026, 12, 31, 18, 0, 0).unwrap();
+        assert!(last_run < end);
+    }
+
+    #[test]
+    fn parses_timestamps_and_none() {
+        let end = parse_end("2026-12-31T12:00:00Z").unwrap().unwrap();
+        assert_eq!(end, Utc.with_ymd_and_hms(2026, 12, 31, 12, 0, 0).unwrap());
+        assert!(parse_end("none").unwrap().is_none());
+        assert!(parse_end("31/12/2026").is_err());
+    }
+
+    #[test]
+    fn parses_and_sorts_dip_boosts() {
+        assert_eq!(parse_dip_boost("0.15:2, 0.05:1.5").unwrap(), vec![(0.05, 1.5), (0.15, 2.0)]);
+        assert!(parse_dip_boost("off").unwrap().is_empty());
+        assert!(parse_dip_boost("0.05").is_err());
+        assert!(parse_dip_boost("1.5:2").is_err());
+    }
+}
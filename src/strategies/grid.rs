@@ -1,87 +1,466 @@
-use crate::utils::{wallet::Wallet, price_feed::get_price};
+use crate::utils::{wallet::Wallet, price_guard::PriceGuard, price_feed::{get_checked_quote, token_unit}, liquidity::cap_order_size, honeypot::{check_honeypot, max_round_trip_tax, allow_unsimulated}};
 use crate::strategies::stop_loss::StopLoss;
+use crate::strategies::grid_state::{DynamicGridConfig, GridSpec, GridState, GridStore, OrderSizing, RecenterMode, RungAction, VolatilitySource};
+use crate::utils::candles::{atr, fetch_candles, realized_volatility};
 use crate::utils::telegram::TelegramBot;
-use solana_sdk::instruction::Instruction;
-use tokio::time::{sleep, Duration};
+use crate::utils::price_feed::sol_to_lamports;
+use crate::strategies::handle::{sleep_or_stop, StrategyHandle};
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
 
 pub struct Grid {
     wallet: Wallet,
     telegram: TelegramBot,
+    price_guard: PriceGuard,
     token_mint: String,
     grid_levels: Vec<f64>,
     amount_per_order: f64,
+    /// Total SOL for grids generated from a range, split across levels.
+    capital: Option<f64>,
+    /// Size spacing from volatility and re-center when price leaves the range.
+    dynamic: bool,
+    dynamic_config: DynamicGridConfig,
+    poll_interval: Duration,
 }
 
 impl Grid {
-    pub fn new(wallet: Wallet, telegram: TelegramBot, token_mint: String, grid_levels: Vec<f64>, amount_per_order: f64) -> Self {
+    pub fn new(wallet: Wallet, telegram: TelegramBot, price_guard: PriceGuard, token_mint: String, grid_levels: Vec<f64>, amount_per_order: f64) -> Self {
         Grid {
             wallet,
             telegram,
+            price_guard,
             token_mint,
             grid_levels,
             amount_per_order,
+            capital: None,
+            dynamic: false,
+            dynamic_config: DynamicGridConfig::default(),
+            poll_interval: Duration::from_secs(
+                std::env::var("GRID_POLL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30)
+            ),
         }
     }
 
-    pub fn set_grid_levels(&mut self, levels: Vec<f64>) {
-        self.grid_levels = levels;
+    /// Enables (`atr` or `realized`) or disables (`off`) the dynamic grid, or updates one
+    /// of its settings; see `DynamicGridConfig::set` for the keys.
+    pub async fn set_dynamic(&mut self, key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if key == "dynamic" {
+            if value != "off" {
+                self.dynamic_config.set("volatility", value)?;
+            }
+            self.dynamic = value != "off";
+        } else {
+            self.dynamic_config.set(key, value)?;
+        }
         self.telegram
-            .send_message(&format!("Set grid levels for {}: {:?}", self.token_mint, self.grid_levels))
-            .await.unwrap();
+            .send_message(&format!("Set grid {} for {} to {}", key, self.token_mint, value))
+            .await?;
+        Ok(())
     }
 
-    pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let current_price = get_price(&self.token_mint, "SOL", &self.telegram).await?;
-        for &level in &self.grid_levels {
-            if current_price <= level {
-                let instruction = Instruction {
-                    program_id: solana_sdk::pubkey::Pubkey::from_str("RAY...").unwrap(),
-                    accounts: vec![],
-                    data: vec![],
-                };
-                self.wallet.send_transaction(instruction, &self.token_mint, "buy", level, self.amount_per_order).await?;
+    /// Sets explicit levels. They are sorted; at least two positive levels are required.
+    pub async fn set_grid_levels(&mut self, levels: Vec<f64>) -> Result<(), Box<dyn std::error::Error>> {
+        let state = GridState::new(&self.token_mint, &levels, OrderSizing::Tokens(self.amount_per_order));
+        if state.rungs.is_empty() {
+            return Err("A grid needs at least two distinct positive levels".into());
+        }
+        self.grid_levels = state.levels();
+        self.capital = None;
+        self.telegram
+            .send_message(&format!(
+                "Set grid levels for {}: {:?}; a running grid keeps its levels until it is restarted",
+                self.token_mint, self.grid_levels
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Generates levels from a range and splits `capital` SOL across them. The range must
+    /// contain the current price; the expected profit per step net of fees is previewed.
+    /// A grid that is already running keeps its levels until it is started again.
+    pub async fn set_grid_range(&mut self, spec: GridSpec) -> Result<(), Box<dyn std::error::Error>> {
+        let price = self.price_guard.get_price(&self.token_mint, "SOL", &self.telegram).await?;
+        spec.validate(price)?;
+
+        let fee_bps = std::env::var("GRID_FEE_BPS").ok().and_then(|v| v.parse().ok()).unwrap_or(25.0);
+        let returns = spec.step_returns(fee_bps);
+        let per_rung = spec.capital / returns.len() as f64;
+        let min = returns.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = returns.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        if max <= 0.0 {
+            return Err(format!(
+                "Grid steps are too narrow: {:.2}% per step after {} bps fees on each swap",
+                max * 100.0, fee_bps
+            ).into());
+        }
+
+        self.grid_levels = spec.levels();
+        self.capital = Some(spec.capital);
+        self.telegram
+            .send_message(&format!(
+                "Grid preview for {}: {} {:?} levels from {} to {}, {:.4} SOL per level. \
+                 Profit per step after fees: {:.2}% to {:.2}% ({:.6} to {:.6} SOL){}. \
+                 Checked against the current price {}; a running grid keeps its levels until it is restarted",
+                self.token_mint, spec.count, spec.spacing, spec.lower, spec.upper, per_rung,
+                min * 100.0, max * 100.0, per_rung * min, per_rung * max,
+                if min <= 0.0 { ". Warning: the narrowest steps lose money after fees" } else { "" },
+                price
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Loads the saved grid for this token, or builds a new one from the configured levels.
+    /// A saved grid still holding inventory is kept even if the levels have changed.
+    async fn load_state(&self, store: &GridStore, price: f64) -> Result<GridState, Box<dyn std::error::Error>> {
+        let saved = store.load(&self.token_mint)?;
+        if self.dynamic {
+            if let Some(saved) = saved.filter(|s| s.anchor > 0.0 || s.inventory() > 0.0) {
                 self.telegram
-                    .send_message(&format!("Placed buy order for {} at {}", self.token_mint, level))
+                    .send_message(&format!(
+                        "Resumed dynamic grid for {} on levels {:?}: {} tokens held, {:.6} SOL realized over {} cycles",
+                        self.token_mint, saved.levels(), saved.inventory(), saved.realized_profit, saved.cycles
+                    ))
                     .await?;
-            } else if current_price >= level {
-                let instruction = Instruction {
-                    program_id: solana_sdk::pubkey::Pubkey::from_str_const("RAY...").unwrap(),
-                    accounts: vec![],
-                    data: vec![],
-                };
-                self.wallet.send_transaction(instruction, &self.token_mint, "sell", level, self.amount_per_order).await?;
+                return Ok(saved);
+            }
+            let mut state = self.build_dynamic(price).await?;
+            state.anchor = price;
+            store.save(&state)?;
+            self.telegram
+                .send_message(&format!("Built dynamic grid for {} around {} on levels {:?}", self.token_mint, price, state.levels()))
+                .await?;
+            return Ok(state);
+        }
+
+        let configured = GridState::new(&self.token_mint, &self.grid_levels, self.sizing());
+        match saved {
+            Some(saved) if saved.levels() == configured.levels() => {
                 self.telegram
-                    .send_message(&format!("Placed sell order for {} at {}", self.token_mint, level))
+                    .send_message(&format!(
+                        "Resumed grid for {}: {} tokens held, {:.6} SOL realized over {} cycles",
+                        self.token_mint, saved.inventory(), saved.realized_profit, saved.cycles
+                    ))
                     .await?;
+                Ok(saved)
             }
+            Some(saved) if saved.inventory() > 0.0 => {
+                self.telegram
+                    .send_message(&format!(
+                        "Grid for {} still holds {} tokens on levels {:?}; resuming it instead of the new levels",
+                        self.token_mint, saved.inventory(), saved.levels()
+                    ))
+                    .await?;
+                Ok(saved)
+            }
+            _ => {
+                store.save(&configured)?;
+                Ok(configured)
+            }
+        }
+    }
+
+    /// Runs the grid continuously. Each rung buys when price falls to its lower
+    /// level and sells that inventory when price rises to the level above; state is
+    /// saved after every fill.
+    /// Runs the grid in its own task until `StrategyHandle::stop`.
+    pub fn spawn(&self) -> StrategyHandle {
+        let grid = self.clone();
+        StrategyHandle::spawn(move |stopped| async move {
+            if let Err(e) = grid.start(stopped).await {
+                log::error!("Grid for {} stopped: {}", grid.token_mint, e);
+            }
+        })
+    }
+
+    /// Trades the grid until stopped. Stops are only honoured between ticks.
+    async fn start(&self, mut stopped: watch::Receiver<bool>) -> Result<(), Box<dyn std::error::Error>> {
+        let honeypot = check_honeypot(&self.wallet, &self.token_mint, &self.telegram).await?;
+        if honeypot.is_blocked(max_round_trip_tax(), allow_unsimulated()) {
+            self.telegram
+                .send_message(&format!("Grid for {} blocked by honeypot check", self.token_mint))
+                .await?;
+            return Ok(());
         }
 
+        let current_price = self.price_guard.get_price(&self.token_mint, "SOL", &self.telegram).await?;
+        let store = GridStore::new()?;
+        let mut state = self.load_state(&store, current_price).await?;
+        if state.rungs.is_empty() {
+            return Err(format!("Grid for {} needs at least two levels", self.token_mint).into());
+        }
+
+        // The grid buys dips by design, so the stop sits below its lowest level rather
+        // than below the current price, and trailing is only used if GRID_TRAILING_* asks for it
         let stop_loss = StopLoss::new(
             self.token_mint.clone(),
-            current_price,
-            0.05,
+            state.levels()[0],
+            state.inventory(),
             0.05,
+            1.0,
             self.wallet.clone(),
             self.telegram.clone(),
-        );
-        tokio::spawn(async move {
-            loop {
-                if stop_loss.check().await.unwrap() {
-                    break;
+            self.price_guard.clone()
+        )
+        .with_env("GRID")
+        .with_id(self.stop_id());
+        stop_loss.spawn();
+
+        let mut out_of_range_since: Option<Instant> = None;
+        loop {
+            let price = match self.price_guard.get_price(&self.token_mint, "SOL", &self.telegram).await.map_err(|e| e.to_string()) {
+                Ok(price) => price,
+                Err(e) => {
+                    log::warn!("Grid for {} skipped a tick: {}", self.token_mint, e);
+                    if sleep_or_stop(self.poll_interval, &mut stopped).await {
+                        return Ok(());
+                    }
+                    continue;
+                }
+            };
+            if !StopLoss::is_open(&self.stop_id())? {
+                // The guard sold the grid's inventory, so the rungs are flat at this price
+                for index in 0..state.rungs.len() {
+                    if let Some(held) = state.rungs[index].holding {
+                        state.liquidate(index, held, price);
+                    }
+                }
+                store.save(&state)?;
+                self.telegram
+                    .send_message(&format!(
+                        "Grid for {} stopped by its stop-loss, {:.6} SOL realized over {} cycles",
+                        self.token_mint, state.realized_profit, state.cycles
+                    ))
+                    .await?;
+                return Ok(());
+            }
+            if self.dynamic && state.out_of_range(price) {
+                let since = *out_of_range_since.get_or_insert_with(Instant::now);
+                if since.elapsed() >= Duration::from_secs(self.dynamic_config.out_of_range_secs) {
+                    out_of_range_since = None;
+                    self.recenter(&mut state, price).await?;
+                    store.save(&state)?;
                 }
-                sleep(Duration::from_secs(60)).await;
+            } else {
+                out_of_range_since = None;
             }
-        });
+            for index in 0..state.rungs.len() {
+                let rung = state.rungs[index].clone();
+                let (action, level, size) = match state.action(index, price) {
+                    // Grids saved before per-level sizing fall back to the flat order size
+                    Some(RungAction::Buy) if rung.order_size > 0.0 => ("buy", rung.buy_price, rung.order_size),
+                    Some(RungAction::Buy) => ("buy", rung.buy_price, self.amount_per_order),
+                    Some(RungAction::Sell(held)) => ("sell", rung.sell_price, held),
+                    None => continue,
+                };
+                // A failed order is retried on the next tick, like a failed price read
+                let fill = match self.place_order(action, level, size).await.map_err(|e| e.to_string()) {
+                    Ok(fill) => fill,
+                    Err(e) => {
+                        log::warn!("Grid {} for {} at {} failed, skipping the tick: {}", action, self.token_mint, level, e);
+                        break;
+                    }
+                };
+                let Some((amount, fill_price)) = fill else { continue };
+                if action == "buy" {
+                    state.fill_buy(index, amount, fill_price);
+                    store.save(&state)?;
+                    continue;
+                }
+                let cycles = state.cycles;
+                let profit = state.fill_sell(index, amount, fill_price);
+                store.save(&state)?;
+                if state.cycles > cycles {
+                    self.telegram
+                        .send_message(&format!(
+                            "Grid cycle for {} {} -> {}: {:.6} SOL profit, {:.6} SOL realized over {} cycles",
+                            self.token_mint, rung.buy_price, rung.sell_price, profit,
+                            state.realized_profit, state.cycles
+                        ))
+                        .await?;
+                }
+            }
+            if sleep_or_stop(self.poll_interval, &mut stopped).await {
+                return Ok(());
+            }
+        }
+    }
+
+    fn sizing(&self) -> OrderSizing {
+        match self.capital {
+            Some(capital) => OrderSizing::Capital(capital),
+            None => OrderSizing::Tokens(self.amount_per_order),
+        }
+    }
+
+    /// Grid step from recent volatility: a multiple of ATR, or of one-candle realized
+    /// volatility scaled to the current price.
+    async fn volatility_step(&self, price: f64) -> Result<f64, Box<dyn std::error::Error>> {
+        let config = &self.dynamic_config;
+        let candles = fetch_candles(&self.token_mint, &config.timeframe, config.lookback + 1, &self.telegram).await?;
+        let volatility = match config.source {
+            VolatilitySource::Atr => atr(&candles, config.lookback),
+            VolatilitySource::Realized => realized_volatility(&candles).map(|v| v * price),
+        };
+        let step = volatility.ok_or_else(|| format!("Not enough {} candles for {}", config.timeframe, self.token_mint))?;
+        Ok(step * config.multiplier)
+    }
+
+    /// Builds a grid centered on `price` with volatility-based spacing and the configured
+    /// level count. Fails if volatility gives no usable spacing, e.g. on flat candles.
+    async fn build_dynamic(&self, price: f64) -> Result<GridState, Box<dyn std::error::Error>> {
+        let step = self.volatility_step(price).await?;
+        if !(step.is_finite() && step > 0.0) {
+            return Err(format!("Volatility for {} gives a grid step of {}", self.token_mint, step).into());
+        }
+        let levels = GridState::centered_levels(price, step, self.grid_levels.len().max(2));
+        let state = GridState::new(&self.token_mint, &levels, self.sizing());
+        if state.rungs.is_empty() {
+            return Err(format!(
+                "A {:.9} SOL step around {} leaves fewer than two positive levels for {}",
+                step, price, self.token_mint
+            ).into());
+        }
+        Ok(state)
+    }
+
+    /// Re-centers a grid that price has left, by shifting it or by selling its
+    /// inventory and rebuilding. Refuses when the new center drifts too far from where
+    /// the grid started or the loss would exceed the re-centering budget.
+    async fn recenter(&self, state: &mut GridState, price: f64) -> Result<(), Box<dyn std::error::Error>> {
+        let config = &self.dynamic_config;
+        let drift = state.drift(price);
+        if drift > config.max_drift {
+            self.telegram
+                .send_message(&format!(
+                    "Grid for {} not re-centered: price {} is {:.1}% from the starting center {}, limit {:.1}%",
+                    self.token_mint, price, drift * 100.0, state.anchor, config.max_drift * 100.0
+                ))
+                .await?;
+            return Ok(());
+        }
+        // Inventory bought above the current price is the loss a re-center locks in
+        let loss = state.unrealized_loss(price);
+        if state.recenter_loss + loss > config.max_loss {
+            self.telegram
+                .send_message(&format!(
+                    "Grid for {} not re-centered: {:.6} SOL loss would exceed the {:.6} SOL limit ({:.6} SOL used)",
+                    self.token_mint, loss, config.max_loss, state.recenter_loss
+                ))
+                .await?;
+            return Ok(());
+        }
+
+        match config.mode {
+            RecenterMode::Shift => {
+                let delta = price - state.center();
+                if !state.shift(delta) {
+                    self.telegram
+                        .send_message(&format!("Grid for {} not re-centered: shifted levels would not be positive", self.token_mint))
+                        .await?;
+                    return Ok(());
+                }
+                state.recenter_loss += loss;
+                self.telegram
+                    .send_message(&format!(
+                        "Shifted grid for {} by {:.9} SOL to levels {:?}",
+                        self.token_mint, delta, state.levels()
+                    ))
+                    .await?;
+            }
+            RecenterMode::Rebuild => {
+                // Build first so a grid without usable spacing is kept rather than closed
+                let rebuilt = self.build_dynamic(price).await.map_err(|e| e.to_string());
+                let mut rebuilt = match rebuilt {
+                    Ok(rebuilt) => rebuilt,
+                    Err(e) => {
+                        self.telegram
+                            .send_message(&format!("Grid for {} not re-centered: {}", self.token_mint, e))
+                            .await?;
+                        return Ok(());
+                    }
+                };
+                let mut pnl = 0.0;
+                for index in 0..state.rungs.len() {
+                    let Some(held) = state.rungs[index].holding else { continue };
+                    // A rung that fails to sell stays held and postpones the rebuild below
+                    match self.place_order("sell", price, held).await.map_err(|e| e.to_string()) {
+                        Ok(Some((amount, fill_price))) => pnl += state.liquidate(index, amount, fill_price),
+                        Ok(None) => {}
+                        Err(e) => log::warn!("Grid rebuild sell for {} at {} failed: {}", self.token_mint, price, e),
+                    }
+                }
+                if state.inventory() > 0.0 {
+                    self.telegram
+                        .send_message(&format!(
+                            "Grid rebuild for {} postponed: {} tokens could not be sold",
+                            self.token_mint, state.inventory()
+                        ))
+                        .await?;
+                    return Ok(());
+                }
+                rebuilt.anchor = state.anchor;
+                rebuilt.realized_profit = state.realized_profit;
+                rebuilt.cycles = state.cycles;
+                rebuilt.recenter_loss = state.recenter_loss + (-pnl).max(0.0);
+                *state = rebuilt;
+                self.telegram
+                    .send_message(&format!(
+                        "Rebuilt grid for {} around {} on levels {:?} after closing inventory for {:.6} SOL",
+                        self.token_mint, price, state.levels(), pnl
+                    ))
+                    .await?;
+            }
+        }
         Ok(())
     }
 
+    /// Executes one grid order at `price`, capped by depth and route impact. Returns the
+    /// filled amount and the price it filled at, or `None` if the route was rejected.
+    async fn place_order(&self, action: &str, price: f64, desired: f64) -> Result<Option<(f64, f64)>, Box<dyn std::error::Error>> {
+        let amount = cap_order_size(&self.token_mint, action, desired, price, &self.telegram).await?;
+        let unit = token_unit(&self.token_mint).await?;
+        let quote = if action == "buy" {
+            get_checked_quote("SOL", &self.token_mint, sol_to_lamports(price * amount), &self.telegram).await
+        } else {
+            get_checked_quote(&self.token_mint, "SOL", (amount * unit) as u64, &self.telegram).await
+        };
+        let Some(quote) = quote.ok() else { return Ok(None) };
+        // SOL per token on the quoted route, which is what the order actually fills at
+        let (lamports, tokens) = if action == "buy" {
+            (quote.in_amount, quote.out_amount)
+        } else {
+            (quote.out_amount, quote.in_amount)
+        };
+        if tokens == 0 {
+            return Ok(None);
+        }
+        let fill_price = lamports as f64 / 1e9 / (tokens as f64 / unit);
+        self.wallet.swap(&quote, &self.token_mint, action, fill_price, amount).await?;
+        StopLoss::adjust_position(&self.stop_id(), if action == "buy" { amount } else { -amount })?;
+        self.telegram
+            .send_message(&format!("Filled grid {} for {} at {} (level {})", action, self.token_mint, fill_price, price))
+            .await?;
+        Ok(Some((amount, fill_price)))
+    }
+
+    /// Id of the stop-loss guarding this grid's inventory.
+    fn stop_id(&self) -> String {
+        format!("grid:{}", self.token_mint)
+    }
+
     pub fn clone(&self) -> Self {
         Grid {
             wallet: self.wallet.clone(),
             telegram: TelegramBot::new(),
+            price_guard: self.price_guard.clone(),
             token_mint: self.token_mint.clone(),
             grid_levels: self.grid_levels.clone(),
             amount_per_order: self.amount_per_order,
+            capital: self.capital,
+            dynamic: self.dynamic,
+            dynamic_config: self.dynamic_config.clone(),
+            poll_interval: self.poll_interval,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,579 @@
+use rusqlite::{ params, Connection, OptionalExtension, Result };
+use serde::{ Deserialize, Serialize };
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/// One step of the grid, between two adjacent levels. Without inventory it waits
+/// to buy at `buy_price`; holding inventory it waits to sell at `sell_price`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GridRung {
+    pub buy_price: f64,
+    pub sell_price: f64,
+    /// Tokens bought at `buy_price` and not yet sold.
+    pub holding: Option<f64>,
+    /// Tokens to buy at `buy_price`; 0 for grids saved before per-level sizing.
+    #[serde(default)]
+    pub order_size: f64,
+    /// Price paid for `holding`, which differs from `buy_price` once the grid has shifted.
+    #[serde(default)]
+    pub cost_price: f64,
+}
+
+impl GridRung {
+    fn cost(&self) -> f64 {
+        if self.cost_price > 0.0 { self.cost_price } else { self.buy_price }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VolatilitySource {
+    Atr,
+    /// Standard deviation of close-to-close log returns.
+    Realized,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecenterMode {
+    /// Move every level by the same amount, keeping inventory and its cost.
+    Shift,
+    /// Sell all inventory and build a fresh grid around the current price.
+    Rebuild,
+}
+
+/// Settings for a grid whose spacing follows volatility and which re-centers
+/// after price has stayed out of range.
+#[derive(Debug, Clone)]
+pub struct DynamicGridConfig {
+    pub source: VolatilitySource,
+    pub timeframe: String,
+    /// Candles used for the volatility estimate.
+    pub lookback: usize,
+    /// Grid step as a multiple of ATR or of one-candle realized volatility.
+    pub multiplier: f64,
+    pub out_of_range_secs: u64,
+    pub mode: RecenterMode,
+    /// Largest move of the grid center from where it started, as a fraction.
+    pub max_drift: f64,
+    /// Largest total SOL loss accepted from re-centering.
+    pub max_loss: f64,
+}
+
+impl Default for DynamicGridConfig {
+    fn default() -> Self {
+        DynamicGridConfig {
+            source: VolatilitySource::Atr,
+            timeframe: "15m".to_string(),
+            lookback: 14,
+            multiplier: 1.0,
+            out_of_range_secs: 3600,
+            mode: RecenterMode::Shift,
+            max_drift: 0.5,
+            max_loss: 0.1,
+        }
+    }
+}
+
+impl DynamicGridConfig {
+    /// Updates one setting from a `/set_params <token> grid <key> <value>` command.
+    pub fn set(&mut self, key: &str, value: &str) -> std::result::Result<(), String> {
+        let number = |v: &str| v.parse::<f64>().map_err(|_| format!("Invalid number {}", v));
+        match key {
+            "volatility" => {
+                self.source = match value {
+                    "atr" => VolatilitySource::Atr,
+                    "realized" => VolatilitySource::Realized,
+                    _ => return Err("Volatility must be atr or realized".to_string()),
+                }
+            }
+            "timeframe" => self.timeframe = value.to_string(),
+            "lookback" => self.lookback = value.parse().map_err(|_| format!("Invalid lookback {}", value))?,
+            "spacing_multiplier" => self.multiplier = number(value)?,
+            "recenter_after" => self.out_of_range_secs = value.parse().map_err(|_| format!("Invalid seconds {}", value))?,
+            "recenter_mode" => {
+                self.mode = match value {
+                    "shift" => RecenterMode::Shift,
+                    "rebuild" => RecenterMode::Rebuild,
+                    _ => return Err("Recenter mode must be shift or rebuild".to_string()),
+                }
+            }
+            "max_drift" => self.max_drift = number(value)?,
+            "max_recenter_loss" => self.max_loss = number(value)?,
+            _ => return Err(format!("Unknown grid parameter {}", key)),
+        }
+        Ok(())
+    }
+}
+
+/// How much each rung buys.
+#[derive(Debug, Clone, Copy)]
+pub enum OrderSizing {
+    /// The same token amount on every rung.
+    Tokens(f64),
+    /// Total SOL split evenly across rungs.
+    Capital(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Spacing {
+    /// Constant price difference between levels.
+    Arithmetic,
+    /// Constant ratio between levels.
+    Geometric,
+}
+
+impl FromStr for Spacing {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, String> {
+        match s {
+            "arithmetic" => Ok(Spacing::Arithmetic),
+            "geometric" => Ok(Spacing::Geometric),
+            _ => Err(format!("Unknown spacing {}, use arithmetic or geometric", s)),
+        }
+    }
+}
+
+/// A grid described by its range, level count and total capital.
+#[derive(Debug, Clone, Copy)]
+pub struct GridSpec {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: usize,
+    pub spacing: Spacing,
+    /// SOL committed across all rungs.
+    pub capital: f64,
+}
+
+impl GridSpec {
+    /// Parses `lower,upper,count,spacing,capital`, e.g. `0.000018,0.000022,9,geometric,1`.
+    pub fn parse(value: &str) -> std::result::Result<Self, String> {
+        let parts: Vec<&str> = value.split(',').map(|p| p.trim()).collect();
+        let [lower, upper, count, spacing, capital] = parts.as_slice() else {
+            return Err("Expected lower,upper,count,spacing,capital".to_string());
+        };
+        let number = |v: &str| v.parse::<f64>().map_err(|_| format!("Invalid number {}", v));
+        let spec = GridSpec {
+            lower: number(lower)?,
+            upper: number(upper)?,
+            count: count.parse().map_err(|_| format!("Invalid level count {}", count))?,
+            spacing: spacing.parse()?,
+            capital: number(capital)?,
+        };
+        if !(spec.lower > 0.0 && spec.lower < spec.upper) {
+            return Err(format!("Lower bound {} must be positive and below upper bound {}", spec.lower, spec.upper));
+        }
+        if spec.count < 2 {
+            return Err("A grid needs at least two levels".to_string());
+        }
+        if spec.capital <= 0.0 {
+            return Err("Capital must be positive".to_string());
+        }
+        Ok(spec)
+    }
+
+    /// Checks that the range straddles `price`, so the grid has both buys and sells.
+    pub fn validate(&self, price: f64) -> std::result::Result<(), String> {
+        if price <= self.lower || price >= self.upper {
+            return Err(format!("Range {} - {} does not contain the current price {}", self.lower, self.upper, price));
+        }
+        Ok(())
+    }
+
+    pub fn levels(&self) -> Vec<f64> {
+        let steps = (self.count - 1) as f64;
+        (0..self.count)
+            .map(|i| match self.spacing {
+                Spacing::Arithmetic => self.lower + (self.upper - self.lower) * i as f64 / steps,
+                Spacing::Geometric => self.lower * (self.upper / self.lower).powf(i as f64 / steps),
+            })
+            .collect()
+    }
+
+    /// Net return of one buy-low/sell-high step on each rung, after `fee_bps` on both swaps.
+    pub fn step_returns(&self, fee_bps: f64) -> Vec<f64> {
+        let keep = 1.0 - fee_bps / 10_000.0;
+        self.levels()
+            .windows(2)
+            .map(|pair| pair[1] / pair[0] * keep * keep - 1.0)
+            .collect()
+    }
+}
+
+/// What a rung should do at the current price.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RungAction {
+    Buy,
+    Sell(f64),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GridState {
+    pub token_mint: String,
+    pub rungs: Vec<GridRung>,
+    /// SOL realized by completed and partial sells.
+    pub realized_profit: f64,
+    pub cycles: u64,
+    /// Center price a dynamic grid was first built around, 0 for fixed grids.
+    #[serde(default)]
+    pub anchor: f64,
+    /// SOL lost to re-centering so far.
+    #[serde(default)]
+    pub recenter_loss: f64,
+}
+
+impl GridState {
+    /// Builds an empty grid. Levels are sorted and deduplicated.
+    pub fn new(token_mint: &str, levels: &[f64], sizing: OrderSizing) -> Self {
+        let mut levels: Vec<f64> = levels.iter().copied().filter(|l| l.is_finite() && *l > 0.0).collect();
+        levels.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        levels.dedup();
+        GridState {
+            token_mint: token_mint.to_string(),
+            rungs: levels
+                .windows(2)
+                .map(|pair| GridRung {
+                    buy_price: pair[0],
+                    sell_price: pair[1],
+                    holding: None,
+                    order_size: match sizing {
+                        OrderSizing::Tokens(amount) => amount,
+                        OrderSizing::Capital(capital) => capital / (levels.len() - 1) as f64 / pair[0],
+                    },
+                    cost_price: 0.0,
+                })
+                .collect(),
+            realized_profit: 0.0,
+            cycles: 0,
+            anchor: 0.0,
+            recenter_loss: 0.0,
+        }
+    }
+
+    /// Levels `step` apart, centered on `center`. Levels that would not be positive are dropped.
+    pub fn centered_levels(center: f64, step: f64, count: usize) -> Vec<f64> {
+        let middle = (count as f64 - 1.0) / 2.0;
+        (0..count)
+            .map(|i| center + (i as f64 - middle) * step)
+            .filter(|level| *level > 0.0)
+            .collect()
+    }
+
+    pub fn center(&self) -> f64 {
+        let levels = self.levels();
+        (levels[0] + levels[levels.len() - 1]) / 2.0
+    }
+
+    /// Whether `price` lies outside the lowest and highest level.
+    pub fn out_of_range(&self, price: f64) -> bool {
+        let levels = self.levels();
+        price < levels[0] || price > levels[levels.len() - 1]
+    }
+
+    /// How far a new center would be from the anchor, as a fraction of the anchor.
+    pub fn drift(&self, center: f64) -> f64 {
+        if self.anchor > 0.0 { (center - self.anchor).abs() / self.anchor } else { 0.0 }
+    }
+
+    /// SOL that would be lost if held inventory were sold at `price`.
+    pub fn unrealized_loss(&self, price: f64) -> f64 {
+        self.rungs
+            .iter()
+            .filter_map(|r| r.holding.map(|held| (r.cost() - price).max(0.0) * held))
+            .sum()
+    }
+
+    /// Moves every level by `delta`. Inventory keeps its cost, so a sell below cost is
+    /// recorded as a loss when it fills. Returns false if a level would not stay positive.
+    pub fn shift(&mut self, delta: f64) -> bool {
+        if self.rungs.iter().any(|r| r.buy_price + delta <= 0.0) {
+            return false;
+        }
+        for rung in &mut self.rungs {
+            rung.cost_price = rung.cost();
+            rung.buy_price += delta;
+            rung.sell_price += delta;
+        }
+        true
+    }
+
+    pub fn levels(&self) -> Vec<f64> {
+        let mut levels: Vec<f64> = self.rungs.iter().map(|r| r.buy_price).collect();
+        levels.extend(self.rungs.last().map(|r| r.sell_price));
+        levels
+    }
+
+    /// Tokens currently held across all rungs.
+    pub fn inventory(&self) -> f64 {
+        self.rungs.iter().filter_map(|r| r.holding).sum()
+    }
+
+    /// The order armed on rung `index`, if `price` has reached it.
+    pub fn action(&self, index: usize, price: f64) -> Option<RungAction> {
+        let rung = &self.rungs[index];
+        match rung.holding {
+            None if price <= rung.buy_price => Some(RungAction::Buy),
+            Some(amount) if price >= rung.sell_price => Some(RungAction::Sell(amount)),
+            _ => None,
+        }
+    }
+
+    /// Records a buy of `amount` on rung `index` filled at `price`, arming its sell one level up.
+    pub fn fill_buy(&mut self, index: usize, amount: f64, price: f64) {
+        let rung = &mut self.rungs[index];
+        let held = rung.holding.unwrap_or(0.0);
+        rung.cost_price = (rung.cost() * held + price * amount) / (held + amount);
+        rung.holding = Some(held + amount);
+    }
+
+    /// Records a sell of `amount` on rung `index` filled at `price` and returns the
+    /// realized profit. Once the rung is flat its buy is armed again and the cycle counts.
+    pub fn fill_sell(&mut self, index: usize, amount: f64, price: f64) -> f64 {
+        let (profit, flat) = self.record_sell(index, amount, price);
+        if flat {
+            self.cycles += 1;
+        }
+        profit
+    }
+
+    /// Records inventory on rung `index` sold at `price` outside the grid, e.g. when
+    /// closing it for a rebuild. Returns the realized profit.
+    pub fn liquidate(&mut self, index: usize, amount: f64, price: f64) -> f64 {
+        self.record_sell(index, amount, price).0
+    }
+
+    fn record_sell(&mut self, index: usize, amount: f64, price: f64) -> (f64, bool) {
+        let rung = &mut self.rungs[index];
+        let held = rung.holding.unwrap_or(0.0);
+        let sold = amount.min(held);
+        let profit = (price - rung.cost()) * sold;
+        // Treat dust left by a capped sell as flat
+        rung.holding = if held - sold > held * 1e-6 { Some(held - sold) } else { None };
+        if rung.holding.is_none() {
+            rung.cost_price = 0.0;
+        }
+        self.realized_profit += profit;
+        (profit, rung.holding.is_none())
+    }
+}
+
+/// Persists grid state per token so a restart resumes the same grid.
+pub struct GridStore {
+    conn: Mutex<Connection>,
+}
+
+impl GridStore {
+    pub fn new() -> Result<Self> {
+        Self::with_connection(Connection::open("trades.db")?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS grid_states (
+                token_mint TEXT PRIMARY KEY,
+                state TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+            []
+        )?;
+        Ok(GridStore { conn: Mutex::new(conn) })
+    }
+
+    pub fn load(&self, token_mint: &str) -> Result<Option<GridState>> {
+        let state: Option<String> = self.conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT state FROM grid_states WHERE token_mint = ?", params![token_mint], |row| row.get(0))
+            .optional()?;
+        // An unreadable row is treated as no saved grid
+        Ok(state.and_then(|s| serde_json::from_str(&s).ok()))
+    }
+
+    pub fn save(&self, state: &GridState) -> Result<()> {
+        let json = serde_json::to_string(state).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO grid_states (token_mint, state, updated_at) VALUES (?, ?, ?)",
+            params![state.token_mint, json, chrono::Utc::now().to_rfc3339()]
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len(), "{:?} vs {:?}", actual, expected);
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-12, "{:?} vs {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn parses_a_range() {
+        let spec = GridSpec::parse("0.000018, 0.000022, 9, geometric, 1").unwrap();
+        assert_eq!(spec.lower, 0.000018);
+        assert_eq!(spec.upper, 0.000022);
+        assert_eq!(spec.count, 9);
+        assert_eq!(spec.spacing, Spacing::Geometric);
+        assert_eq!(spec.capital, 1.0);
+    }
+
+    #[test]
+    fn rejects_invalid_ranges() {
+        assert!(GridSpec::parse("1,2,3,arithmetic").is_err());
+        assert!(GridSpec::parse("1,x,3,arithmetic,1").is_err());
+        assert!(GridSpec::parse("1,2,3,linear,1").is_err());
+        assert!(GridSpec::parse("2,1,3,arithmetic,1").is_err());
+        assert!(GridSpec::parse("0,1,3,arithmetic,1").is_err());
+        assert!(GridSpec::parse("1,2,1,arithmetic,1").is_err());
+        assert!(GridSpec::parse("1,2,3,arithmetic,0").is_err());
+    }
+
+    #[test]
+    fn validates_the_range_contains_the_price() {
+        let spec = GridSpec::parse("1,2,3,arithmetic,1").unwrap();
+        assert!(spec.validate(1.5).is_ok());
+        assert!(spec.validate(1.0).is_err());
+        assert!(spec.validate(2.5).is_err());
+    }
+
+    #[test]
+    fn spaces_arithmetic_levels_evenly() {
+        let spec = GridSpec::parse("1,2,5,arithmetic,1").unwrap();
+        assert_close(&spec.levels(), &[1.0, 1.25, 1.5, 1.75, 2.0]);
+    }
+
+    #[test]
+    fn spaces_geometric_levels_by_ratio() {
+        let spec = GridSpec::parse("1,8,4,geometric,1").unwrap();
+        assert_close(&spec.levels(), &[1.0, 2.0, 4.0, 8.0]);
+    }
+
+    #[test]
+    fn step_returns_net_fees_on_both_swaps() {
+        let spec = GridSpec::parse("1,8,4,geometric,1").unwrap();
+        assert_close(&spec.step_returns(0.0), &[1.0, 1.0, 1.0]);
+        // 100 bps on each swap: 2 * 0.99^2 - 1
+        assert_close(&spec.step_returns(100.0), &[0.9602, 0.9602, 0.9602]);
+
+        let spec = GridSpec::parse("1,2,3,arithmetic,1").unwrap();
+        assert_close(&spec.step_returns(0.0), &[0.5, 1.0 / 3.0]);
+    }
+
+    #[test]
+    fn narrow_steps_lose_money_after_fees() {
+        let spec = GridSpec::parse("1,1.001,2,arithmetic,1").unwrap();
+        assert!(spec.step_returns(25.0)[0] < 0.0);
+    }
+
+    #[test]
+    fn zero_step_leaves_no_rungs() {
+        let levels = GridState::centered_levels(1.0, 0.0, 4);
+        assert!(GridState::new("MINT", &levels, OrderSizing::Tokens(1.0)).rungs.is_empty());
+
+        let levels = GridState::centered_levels(1.0, 0.25, 4);
+        assert_close(&levels, &[0.625, 0.875, 1.125, 1.375]);
+        assert_eq!(GridState::new("MINT", &levels, OrderSizing::Tokens(1.0)).rungs.len(), 3);
+    }
+
+    fn grid() -> GridState {
+        GridState::new("mint", &[1.0, 2.0, 3.0], OrderSizing::Tokens(10.0))
+    }
+
+    #[test]
+    fn arms_buys_below_and_sells_above() {
+        let mut state = grid();
+        assert!(state.action(0, 1.5).is_none());
+        assert!(matches!(state.action(0, 1.0), Some(RungAction::Buy)));
+        assert!(matches!(state.action(1, 1.0), Some(RungAction::Buy)));
+
+        state.fill_buy(0, 10.0, 1.0);
+        // A held rung waits for its sell level instead of buying again
+        assert!(state.action(0, 0.5).is_none());
+        assert!(matches!(state.action(0, 2.0), Some(RungAction::Sell(held)) if held == 10.0));
+    }
+
+    #[test]
+    fn averages_the_cost_of_repeated_buys() {
+        let mut state = grid();
+        state.fill_buy(0, 10.0, 1.0);
+        state.fill_buy(0, 10.0, 0.8);
+        assert_eq!(state.rungs[0].holding, Some(20.0));
+        assert!((state.rungs[0].cost_price - 0.9).abs() < 1e-12);
+        assert_eq!(state.inventory(), 20.0);
+    }
+
+    #[test]
+    fn counts_a_cycle_once_the_rung_is_flat() {
+        let mut state = grid();
+        state.fill_buy(0, 10.0, 1.0);
+
+        let profit = state.fill_sell(0, 4.0, 2.0);
+        assert!((profit - 4.0).abs() < 1e-12);
+        assert_eq!(state.rungs[0].holding, Some(6.0));
+        assert_eq!(state.cycles, 0);
+
+        // Selling more than held only realizes what was held
+        let profit = state.fill_sell(0, 10.0, 2.5);
+        assert!((profit - 9.0).abs() < 1e-12);
+        assert_eq!(state.rungs[0].holding, None);
+        assert_eq!(state.rungs[0].cost_price, 0.0);
+        assert_eq!(state.cycles, 1);
+        assert!((state.realized_profit - 13.0).abs() < 1e-12);
+        assert!(matches!(state.action(0, 1.0), Some(RungAction::Buy)));
+    }
+
+    #[test]
+    fn liquidation_does_not_count_a_cycle() {
+        let mut state = grid();
+        state.fill_buy(1, 10.0, 2.0);
+        let profit = state.liquidate(1, 10.0, 1.5);
+        assert!((profit + 5.0).abs() < 1e-12);
+        assert_eq!(state.rungs[1].holding, None);
+        assert_eq!(state.cycles, 0);
+    }
+
+    #[test]
+    fn store_round_trips_the_grid() {
+        let store = GridStore::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        assert!(store.load("mint").unwrap().is_none());
+
+        let mut state = grid();
+        state.fill_buy(0, 10.0, 0.9);
+        state.fill_sell(0, 10.0, 2.0);
+        state.fill_buy(1, 5.0, 2.0);
+        state.anchor = 2.0;
+        store.save(&state).unwrap();
+        // Saving again replaces the row for the token
+        state.recenter_loss = 0.5;
+        store.save(&state).unwrap();
+
+        let loaded = store.load("mint").unwrap().unwrap();
+        assert_close(&loaded.levels(), &[1.0, 2.0, 3.0]);
+        assert_eq!(loaded.rungs[0].holding, None);
+        assert_eq!(loaded.rungs[1].holding, Some(5.0));
+        assert_eq!(loaded.rungs[1].cost_price, 2.0);
+        assert_eq!(loaded.rungs[1].order_size, 10.0);
+        assert_eq!(loaded.cycles, 1);
+        assert!((loaded.realized_profit - 11.0).abs() < 1e-12);
+        assert_eq!(loaded.anchor, 2.0);
+        assert_eq!(loaded.recenter_loss, 0.5);
+        assert!(store.load("other").unwrap().is_none());
+    }
+
+    #[test]
+    fn loads_grids_saved_before_per_level_sizing() {
+        let store = GridStore::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        let saved = r#"{"token_mint":"mint","rungs":[{"buy_price":1.0,"sell_price":2.0,"holding":3.0}],"realized_profit":0.0,"cycles":0}"#;
+        store.conn.lock().unwrap().execute(
+            "INSERT INTO grid_states (token_mint, state, updated_at) VALUES ('mint', ?, '')",
+            params![saved]
+        ).unwrap();
+        let loaded = store.load("mint").unwrap().unwrap();
+        assert_eq!(loaded.rungs[0].order_size, 0.0);
+        assert_eq!(loaded.rungs[0].holding, Some(3.0));
+        // Without a recorded cost the buy level is the cost
+        assert!((loaded.unrealized_loss(0.5) - 1.5).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,37 @@
+use std::future::Future;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{ sleep, Duration };
+
+/// A strategy running in its own task. The task watches its stop channel between
+/// steps, so stopping never cuts a trade off halfway.
+pub struct StrategyHandle {
+    stop: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl StrategyHandle {
+    /// Spawns the future built by `run` from the receiver it must watch to stop.
+    pub fn spawn<F, Fut>(run: F) -> Self
+    where
+        F: FnOnce(watch::Receiver<bool>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (stop, stopped) = watch::channel(false);
+        StrategyHandle { stop, task: tokio::spawn(run(stopped)) }
+    }
+
+    /// Stops the strategy once any step in progress has finished.
+    pub async fn stop(self) {
+        let _ = self.stop.send(true);
+        let _ = self.task.await;
+    }
+}
+
+/// Sleeps for `duration` unless the strategy is stopped first. Returns true if it was stopped.
+pub async fn sleep_or_stop(duration: Duration, stopped: &mut watch::Receiver<bool>) -> bool {
+    tokio::select! {
+        _ = sleep(duration) => *stopped.borrow(),
+        _ = stopped.changed() => true,
+    }
+}
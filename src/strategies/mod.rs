@@ -1,4 +1,11 @@
 pub mod sniper;
 pub mod grid;
 pub mod trend;
-pub mod stop_loss;
\ No newline at end of file
+pub mod stop_loss;
+pub mod sniper_filters;
+pub mod grid_state;
+pub mod take_profit;
+pub mod trend_rules;
+pub mod script;
+pub mod dca;
+pub mod handle;
\ No newline at end of file
@@ -0,0 +1,625 @@
+use crate::utils::{wallet::Wallet, price_guard::PriceGuard, price_feed::{get_checked_quote, token_unit}, liquidity::cap_order_size};
+use crate::utils::candles::parse_timeframe;
+use crate::utils::telegram::TelegramBot;
+use crate::utils::token_metadata::resolve_token;
+use crate::strategies::stop_loss::StopLoss;
+use crate::strategies::take_profit::{parse_rungs, TakeProfit};
+use crate::strategies::trend_rules::{tokenize, Market, ParseError, Parser, Rule, Token, TokenKind};
+use crate::utils::price_feed::sol_to_lamports;
+use std::collections::HashSet;
+use std::{env, fmt, fs};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration};
+
+/// A problem in a strategy file, at a 1-based line and column.
+#[derive(Debug)]
+pub struct ScriptError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Buy { sol: f64 },
+    /// Sell this fraction of the open position.
+    Sell { fraction: f64 },
+}
+
+/// `when <rule> then <action>`, with the line it was written on.
+#[derive(Debug, Clone)]
+pub struct When {
+    pub rule: Rule,
+    pub action: Action,
+    pub line: usize,
+}
+
+impl When {
+    /// Identifies the rule across reloads of its file.
+    fn key(&self) -> String {
+        format!("{} then {:?}", self.rule, self.action)
+    }
+}
+
+/// Stop-loss settings applied to every position the strategy opens.
+#[derive(Debug, Clone)]
+pub struct StopParams {
+    pub fixed: f64,
+    pub trailing: f64,
+    pub max_hold_secs: Option<i64>,
+    pub min_profit_to_hold: Option<f64>,
+    pub take_profit: Vec<(f64, f64)>,
+}
+
+impl Default for StopParams {
+    fn default() -> Self {
+        StopParams { fixed: 0.05, trailing: 0.05, max_hold_secs: None, min_profit_to_hold: None, take_profit: vec![] }
+    }
+}
+
+/// A strategy parsed from a file such as:
+///
+/// ```text
+/// token BONK
+/// timeframe 15m
+/// every 1m
+/// when rsi(14,5m) < 25 and price > ema(50,1h) then buy 0.1 SOL
+/// when rsi(14,5m) > 70 then sell 50%
+/// when price crosses_below sma(20) then sell all
+/// stop_loss 5%
+/// trailing_stop 8%
+/// max_hold 2h
+/// ```
+///
+/// Indicators without a timeframe use `timeframe`; `price` is the live price, except
+/// in crossovers, which compare the last two candle closes.
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub name: String,
+    pub token_mint: String,
+    pub timeframe: String,
+    /// Seconds between evaluations.
+    pub check_secs: u64,
+    /// Candles fetched per timeframe.
+    pub lookback: usize,
+    pub rules: Vec<When>,
+    pub stop: StopParams,
+}
+
+impl Script {
+    /// Reads `<STRATEGY_DIR>/<name>.strategy` (default directory `strategies`).
+    pub fn load(name: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            return Err(format!("Invalid strategy name {}, use letters, digits, '_' and '-'", name).into());
+        }
+        let dir = env::var("STRATEGY_DIR").unwrap_or_else(|_| "strategies".to_string());
+        let source = fs::read_to_string(format!("{}/{}.strategy", dir, name))?;
+        Ok(Script::parse(name, &source)?)
+    }
+
+    pub fn parse(name: &str, source: &str) -> Result<Self, ScriptError> {
+        let mut token_mint = None;
+        let mut timeframe = None;
+        let mut check_secs = None;
+        let mut lookback = None;
+        let mut stop = StopParams::default();
+        let mut seen = HashSet::new();
+        let mut rules = Vec::new();
+
+        for (index, raw) in source.lines().enumerate() {
+            let line = index + 1;
+            let text = raw.split('#').next().unwrap_or("");
+            let at = |e: ParseError| ScriptError { line, column: e.column, message: e.message };
+            let mut parser = Parser::new(tokenize(text).map_err(at)?);
+            let Some(first) = parser.advance() else { continue };
+            let (column, offset) = (first.column, first.offset);
+            let keyword = match &first.kind {
+                TokenKind::Word(word) => word.clone(),
+                other => return Err(ScriptError { line, column, message: format!("Expected a statement but found {}", other) }),
+            };
+            if keyword != "when" && !seen.insert(keyword.clone()) {
+                return Err(ScriptError { line, column, message: format!("{} is set twice", keyword) });
+            }
+            // Statements like `token` take the raw text after the keyword
+            let rest = &text[offset + keyword.len()..];
+            let value_column = column + keyword.len() + rest.chars().take_while(|c| c.is_whitespace()).count();
+            let raw_value = rest.trim();
+
+            match keyword.as_str() {
+                "token" => {
+                    // Mints are case-sensitive, so they are taken from the raw text
+                    let mint = resolve_token(raw_value).map_err(|message| ScriptError { line, column: value_column, message })?;
+                    token_mint = Some(mint);
+                    continue;
+                }
+                "take_profit" => {
+                    stop.take_profit = parse_rungs(raw_value).map_err(|message| ScriptError { line, column: value_column, message })?;
+                    continue;
+                }
+                "timeframe" => {
+                    let (value, column) = duration_token(&mut parser).map_err(at)?;
+                    parse_timeframe(&value).map_err(|message| ScriptError { line, column, message })?;
+                    timeframe = Some(value);
+                }
+                "every" => {
+                    let secs = seconds(&mut parser).map_err(at)?;
+                    check_secs = Some(secs.max(1) as u64);
+                }
+                "lookback" => {
+                    let (value, column) = number(&mut parser).map_err(at)?;
+                    if value < 1.0 || value.fract() != 0.0 {
+                        return Err(ScriptError { line, column, message: "lookback must be a whole number of candles".to_string() });
+                    }
+                    lookback = Some(value as usize);
+                }
+                "stop_loss" | "trailing_stop" => {
+                    let (value, column) = fraction(&mut parser).map_err(at)?;
+                    if value <= 0.0 || value >= 1.0 {
+                        return Err(ScriptError { line, column, message: format!("{} must be between 0% and 100%", keyword) });
+                    }
+                    if keyword == "stop_loss" {
+                        stop.fixed = value;
+                    } else {
+                        stop.trailing = value;
+                    }
+                }
+                "max_hold" => stop.max_hold_secs = Some(seconds(&mut parser).map_err(at)?),
+                "min_profit_to_hold" => stop.min_profit_to_hold = Some(fraction(&mut parser).map_err(at)?.0),
+                "when" => {
+                    let rule = parser.rule().map_err(at)?;
+                    if !parser.next_is_word("then") {
+                        let column = parser.peek().map(|t| t.column).unwrap_or_else(|| parser.end_column());
+                        return Err(ScriptError { line, column, message: "Expected 'then' after the condition".to_string() });
+                    }
+                    parser.advance();
+                    let action = action(&mut parser).map_err(at)?;
+                    rules.push(When { rule, action, line });
+                }
+                _ => {
+                    return Err(ScriptError { line, column, message: format!("Unknown statement {}", keyword) });
+                }
+            }
+            if let Some(token) = parser.peek() {
+                return Err(ScriptError { line, column: token.column, message: format!("Unexpected {}", token.kind) });
+            }
+        }
+
+        let end = source.lines().count().max(1);
+        let missing = |message: &str| ScriptError { line: end, column: 1, message: message.to_string() };
+        let token_mint = token_mint.ok_or_else(|| missing("Missing a 'token' line"))?;
+        if !rules.iter().any(|r| matches!(r.action, Action::Buy { .. })) {
+            return Err(missing("A strategy needs at least one 'when ... then buy' rule"));
+        }
+        if stop.min_profit_to_hold.is_some() && stop.max_hold_secs.is_none() {
+            return Err(missing("min_profit_to_hold needs a max_hold"));
+        }
+        Ok(Script {
+            name: name.to_string(),
+            token_mint,
+            timeframe: timeframe.unwrap_or_else(|| "15m".to_string()),
+            check_secs: check_secs.unwrap_or(60),
+            lookback: lookback.unwrap_or(200),
+            rules,
+            stop,
+        })
+    }
+}
+
+fn number(parser: &mut Parser) -> Result<(f64, usize), ParseError> {
+    let end = parser.end_column();
+    match parser.advance() {
+        Some(token) => match token.kind {
+            TokenKind::Number(value) => Ok((value, token.column)),
+            ref other => Err(ParseError::new(token.column, format!("Expected a number but found {}", other))),
+        },
+        None => Err(ParseError::new(end, "Expected a number".to_string())),
+    }
+}
+
+/// A number, as a fraction; `5%` and `0.05` are the same.
+fn fraction(parser: &mut Parser) -> Result<(f64, usize), ParseError> {
+    let (value, column) = number(parser)?;
+    if parser.next_is(&TokenKind::Symbol("%")) {
+        parser.advance();
+        return Ok((value / 100.0, column));
+    }
+    Ok((value, column))
+}
+
+fn duration_token(parser: &mut Parser) -> Result<(String, usize), ParseError> {
+    let end = parser.end_column();
+    match parser.advance() {
+        Some(token) => match &token.kind {
+            TokenKind::Timeframe(value) => Ok((value.clone(), token.column)),
+            other => Err(ParseError::new(token.column, format!("Expected a duration such as 5m but found {}", other))),
+        },
+        None => Err(ParseError::new(end, "Expected a duration such as 5m".to_string())),
+    }
+}
+
+/// Seconds, written plainly or with an `s`, `m`, `h` or `d` unit.
+fn seconds(parser: &mut Parser) -> Result<i64, ParseError> {
+    if parser.peek().map(|t| matches!(t.kind, TokenKind::Number(_))).unwrap_or(false) {
+        let (value, column) = number(parser)?;
+        if value <= 0.0 {
+            return Err(ParseError::new(column, "Durations must be positive".to_string()));
+        }
+        return Ok(value as i64);
+    }
+    let (value, column) = duration_token(parser)?;
+    let split = value.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(value.len());
+    let amount = value[..split].parse::<f64>().map_err(|_| ParseError::new(column, format!("Invalid duration {}", value)))?;
+    let unit = match &value[split..] {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86_400.0,
+        other => return Err(ParseError::new(column, format!("Unknown duration unit {}, use s, m, h or d", other))),
+    };
+    Ok((amount * unit) as i64)
+}
+
+/// `buy <amount> SOL`, `sell <percent>%` or `sell all`.
+fn action(parser: &mut Parser) -> Result<Action, ParseError> {
+    let end = parser.end_column();
+    let (verb, column) = match parser.advance() {
+        Some(Token { kind: TokenKind::Word(word), column, .. }) => (word.clone(), *column),
+        Some(token) => return Err(ParseError::new(token.column, format!("Expected buy or sell but found {}", token.kind))),
+        None => return Err(ParseError::new(end, "Expected buy or sell".to_string())),
+    };
+    match verb.as_str() {
+        "buy" => {
+            let (sol, column) = number(parser)?;
+            if sol <= 0.0 {
+                return Err(ParseError::new(column, "Buy amount must be positive".to_string()));
+            }
+            if !parser.next_is_word("sol") {
+                let column = parser.peek().map(|t| t.column).unwrap_or_else(|| parser.end_column());
+                return Err(ParseError::new(column, "Buy amounts are in SOL, e.g. buy 0.1 SOL".to_string()));
+            }
+            parser.advance();
+            Ok(Action::Buy { sol })
+        }
+        "sell" => {
+            if parser.next_is_word("all") {
+                parser.advance();
+                return Ok(Action::Sell { fraction: 1.0 });
+            }
+            let (fraction, column) = fraction(parser)?;
+            if fraction <= 0.0 || fraction > 1.0 {
+                return Err(ParseError::new(column, "Sell size must be between 0% and 100%".to_string()));
+            }
+            Ok(Action::Sell { fraction })
+        }
+        _ => Err(ParseError::new(column, format!("Expected buy or sell but found '{}'", verb))),
+    }
+}
+
+/// A running `ScriptStrategy`.
+pub struct ScriptHandle {
+    stop: watch::Sender<bool>,
+    task: JoinHandle<HashSet<String>>,
+}
+
+impl ScriptHandle {
+    /// Stops the strategy once any check in progress has finished, and returns the
+    /// sell rules fired on its open position.
+    pub async fn stop(self) -> HashSet<String> {
+        let _ = self.stop.send(true);
+        self.task.await.unwrap_or_default()
+    }
+}
+
+/// Runs a loaded `Script`: evaluates its rules on every check and trades one
+/// position at a time, guarded by a stop-loss built from its stop settings.
+pub struct ScriptStrategy {
+    script: Script,
+    wallet: Wallet,
+    telegram: TelegramBot,
+    price_guard: PriceGuard,
+}
+
+impl ScriptStrategy {
+    pub fn new(script: Script, wallet: Wallet, telegram: TelegramBot, price_guard: PriceGuard) -> Self {
+        ScriptStrategy { script, wallet, telegram, price_guard }
+    }
+
+    /// Runs the strategy in its own task until `ScriptHandle::stop`. `fired` holds the
+    /// sell rules already fired on the open position, carried over from a copy being replaced.
+    pub fn spawn(self, fired: HashSet<String>) -> ScriptHandle {
+        let (stop, stopped) = watch::channel(false);
+        let task = tokio::spawn(async move { self.run(fired, stopped).await });
+        ScriptHandle { stop, task }
+    }
+
+    async fn run(&self, mut fired: HashSet<String>, mut stopped: watch::Receiver<bool>) -> HashSet<String> {
+        let message = format!(
+            "Strategy {} running on {} with {} rules, checked every {}s",
+            self.script.name, self.script.token_mint, self.script.rules.len(), self.script.check_secs
+        );
+        if let Err(e) = self.telegram.send_message(&message).await.map_err(|e| e.to_string()) {
+            log::warn!("Failed to report strategy {}: {}", self.script.name, e);
+        }
+        loop {
+            // Only stopped between checks, so a buy is never cut off before its stop-loss is spawned
+            if let Err(e) = self.check(&mut fired).await.map_err(|e| e.to_string()) {
+                log::warn!("Strategy {} skipped a check: {}", self.script.name, e);
+            }
+            tokio::select! {
+                _ = sleep(Duration::from_secs(self.script.check_secs)) => {}
+                _ = stopped.changed() => return fired,
+            }
+        }
+    }
+
+    /// Sell rules fire once per position, so a condition that stays true does not sell
+    /// repeatedly. They are keyed by rule rather than line, so edits around them keep the key.
+    async fn check(&self, fired: &mut HashSet<String>) -> Result<(), Box<dyn std::error::Error>> {
+        let script = &self.script;
+        let price = self.price_guard.get_price(&script.token_mint, "SOL", &self.telegram).await?;
+        let rules: Vec<&Rule> = script.rules.iter().map(|r| &r.rule).collect();
+        let market = Market::fetch(&script.token_mint, &script.timeframe, &rules, script.lookback, Some(price), &self.telegram).await?;
+        let held = StopLoss::position(&self.stop_id())?;
+        if held.is_none() {
+            fired.clear();
+        }
+        for when in &script.rules {
+            match (when.action, held) {
+                (Action::Buy { sol }, None) if when.rule.evaluate(&market) => {
+                    return self.buy(sol, price, when).await;
+                }
+                (Action::Sell { fraction }, Some(quantity)) if !fired.contains(&when.key()) && when.rule.evaluate(&market) => {
+                    // A sell that failed is retried on the next check, so the rule only counts once it sold
+                    self.sell(quantity, fraction, price, when).await?;
+                    fired.insert(when.key());
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    async fn buy(&self, sol: f64, price: f64, when: &When) -> Result<(), Box<dyn std::error::Error>> {
+        let mint = &self.script.token_mint;
+        let amount = cap_order_size(mint, "buy", sol / price, price, &self.telegram).await?;
+        let quote = get_checked_quote("SOL", mint, sol_to_lamports(price * amount), &self.telegram).await?;
+        self.wallet.swap(&quote, mint, "buy", price, amount).await?;
+        self.telegram
+            .send_message(&format!(
+                "Strategy {} bought {} of {} at {} (line {}: {})",
+                self.script.name, amount, mint, price, when.line, when.rule
+            ))
+            .await?;
+
+        let stop = &self.script.stop;
+        let mut stop_loss = StopLoss::new(
+            mint.clone(),
+            price,
+            amount,
+            stop.fixed,
+            stop.trailing,
+            self.wallet.clone(),
+            self.telegram.clone(),
+            self.price_guard.clone()
+        )
+        .with_id(self.stop_id());
+        if let Some(secs) = stop.max_hold_secs {
+            stop_loss = stop_loss.with_max_hold(secs, stop.min_profit_to_hold);
+        }
+        if !stop.take_profit.is_empty() {
+            stop_loss.attach_take_profit(
+                TakeProfit::new(mint.clone(), price, amount, &stop.take_profit, self.wallet.clone(), self.telegram.clone())
+            );
+        }
+        stop_loss.spawn();
+        Ok(())
+    }
+
+    async fn sell(&self, quantity: f64, fraction: f64, price: f64, when: &When) -> Result<(), Box<dyn std::error::Error>> {
+        let mint = &self.script.token_mint;
+        let amount = cap_order_size(mint, "sell", quantity * fraction, price, &self.telegram).await?;
+        let unit = token_unit(mint).await?;
+        let quote = get_checked_quote(mint, "SOL", (amount * unit) as u64, &self.telegram).await?;
+        self.wallet.swap(&quote, mint, "sell", price, amount).await?;
+        if amount >= quantity {
+            StopLoss::release(&self.stop_id())?;
+        } else {
+            StopLoss::adjust_position(&self.stop_id(), -amount)?;
+        }
+        let message = format!(
+            "Strategy {} sold {} of {} at {} (line {}: {})",
+            self.script.name, amount, mint, price, when.line, when.rule
+        );
+        if let Err(e) = self.telegram.send_message(&message).await.map_err(|e| e.to_string()) {
+            log::warn!("Failed to report sell of strategy {}: {}", self.script.name, e);
+        }
+        Ok(())
+    }
+
+    /// Id of the stop-loss guarding this strategy's position.
+    fn stop_id(&self) -> String {
+        format!("script:{}", self.script.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BONK: &str = "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263";
+
+    fn parse(source: &str) -> Result<Script, ScriptError> {
+        Script::parse("test", source)
+    }
+
+    /// Line, column and message of the error `source` fails with.
+    fn error(source: &str) -> (usize, usize, String) {
+        let e = parse(source).unwrap_err();
+        (e.line, e.column, e.message)
+    }
+
+    fn with_token(body: &str) -> String {
+        format!("token {}\n{}", BONK, body)
+    }
+
+    #[test]
+    fn parses_the_example_file() {
+        let source = format!(
+            "token {}
+timeframe 15m
+every 1m
+when rsi(14,5m) < 25 and price > ema(50,1h) then buy 0.1 SOL
+when rsi(14,5m) > 70 then sell 50%
+when price crosses_below sma(20) then sell all
+stop_loss 5%
+trailing_stop 8%
+max_hold 2h
+",
+            BONK
+        );
+        let script = parse(&source).unwrap();
+        assert_eq!(script.token_mint, BONK);
+        assert_eq!(script.timeframe, "15m");
+        assert_eq!(script.check_secs, 60);
+        assert_eq!(script.lookback, 200);
+        assert_eq!(script.rules.len(), 3);
+        assert_eq!(script.rules[0].action, Action::Buy { sol: 0.1 });
+        assert_eq!(script.rules[0].line, 4);
+        assert_eq!(script.rules[0].rule.to_string(), "rsi(14,5m) < 25 and price > ema(50,1h)");
+        assert_eq!(script.rules[1].action, Action::Sell { fraction: 0.5 });
+        assert_eq!(script.rules[2].action, Action::Sell { fraction: 1.0 });
+        assert_eq!(script.rules[2].rule.to_string(), "price crosses_below sma(20)");
+        assert_eq!(script.stop.fixed, 0.05);
+        assert_eq!(script.stop.trailing, 0.08);
+        assert_eq!(script.stop.max_hold_secs, Some(7200));
+    }
+
+    #[test]
+    fn skips_comments_and_blank_lines() {
+        let script = parse(&format!(
+            "# entry on oversold\n\ntoken {} # BONK\nwhen rsi(14) < 25 then buy 0.1 sol\n   \n",
+            BONK
+        ))
+        .unwrap();
+        assert_eq!(script.rules[0].line, 4);
+    }
+
+    #[test]
+    fn reads_take_profit_and_time_settings() {
+        let script = parse(&with_token(
+            "when price > 1 then buy 1 SOL\ntake_profit 0.3:0.25, 1.0:0.5\nmax_hold 90\nmin_profit_to_hold 10%\nlookback 50\nevery 30s",
+        ))
+        .unwrap();
+        assert_eq!(script.stop.take_profit, vec![(0.3, 0.25), (1.0, 0.5)]);
+        assert_eq!(script.stop.max_hold_secs, Some(90));
+        assert_eq!(script.stop.min_profit_to_hold, Some(0.1));
+        assert_eq!(script.lookback, 50);
+        assert_eq!(script.check_secs, 30);
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let script = parse(&with_token("when price > 1 or price > 2 and price > 3 then buy 1 SOL")).unwrap();
+        assert_eq!(script.rules[0].rule.to_string(), "price > 1 or (price > 2 and price > 3)");
+        let script = parse(&with_token("when (price > 1 or price > 2) and price > 3 then buy 1 SOL")).unwrap();
+        assert_eq!(script.rules[0].rule.to_string(), "(price > 1 or price > 2) and price > 3");
+    }
+
+    #[test]
+    fn reports_statement_errors_with_line_and_column() {
+        assert_eq!(error("5m"), (1, 1, "Expected a statement but found '5m'".to_string()));
+        assert_eq!(error(&with_token("every 1m $")), (2, 10, "Unexpected character '$'".to_string()));
+        assert_eq!(error(&with_token("hold 5m")), (2, 1, "Unknown statement hold".to_string()));
+        assert_eq!(error(&with_token("every 1m\nevery 5m")), (3, 1, "every is set twice".to_string()));
+        assert_eq!(error(&with_token("every 1m 5")), (2, 10, "Unexpected 5".to_string()));
+    }
+
+    #[test]
+    fn reports_value_errors_with_line_and_column() {
+        assert_eq!(error("token   NOPE"), (1, 9, "Unknown token NOPE".to_string()));
+        assert_eq!(
+            error(&with_token("take_profit 0.3")),
+            (2, 13, "Expected gain:fraction, got 0.3".to_string())
+        );
+        assert_eq!(error(&with_token("timeframe 15")), (2, 11, "Expected a duration such as 5m but found 15".to_string()));
+        let (line, column, message) = error(&with_token("timeframe 2m"));
+        assert_eq!((line, column), (2, 11));
+        assert!(message.starts_with("Unsupported timeframe 2m"), "{}", message);
+        assert_eq!(error(&with_token("every -5")), (2, 7, "Durations must be positive".to_string()));
+        assert_eq!(error(&with_token("every 5x")), (2, 7, "Unknown duration unit x, use s, m, h or d".to_string()));
+        assert_eq!(error(&with_token("every")), (2, 2, "Expected a duration such as 5m".to_string()));
+        assert_eq!(error(&with_token("lookback 1.5")), (2, 10, "lookback must be a whole number of candles".to_string()));
+        assert_eq!(error(&with_token("stop_loss 150%")), (2, 11, "stop_loss must be between 0% and 100%".to_string()));
+    }
+
+    #[test]
+    fn reports_rule_errors_with_line_and_column() {
+        assert_eq!(
+            error(&with_token("when price > 1 buy 1 SOL")),
+            (2, 16, "Expected 'then' after the condition".to_string())
+        );
+        assert_eq!(
+            error(&with_token("when rsi(14) < then buy 1 SOL")),
+            (2, 16, "Unknown indicator then".to_string())
+        );
+        assert_eq!(error(&with_token("when foo > 1 then buy 1 SOL")), (2, 6, "Unknown indicator foo".to_string()));
+        assert_eq!(
+            error(&with_token("when price > 1 and then buy 1 SOL")),
+            (2, 20, "Unknown indicator then".to_string())
+        );
+        assert_eq!(
+            error(&with_token("when price > , then buy 1 SOL")),
+            (2, 14, "Expected an indicator or number but found ','".to_string())
+        );
+        assert_eq!(error(&with_token("when price > 1 then hold")), (2, 21, "Expected buy or sell but found 'hold'".to_string()));
+        assert_eq!(
+            error(&with_token("when price > 1 then buy 1")),
+            (2, 26, "Buy amounts are in SOL, e.g. buy 0.1 SOL".to_string())
+        );
+        assert_eq!(error(&with_token("when price > 1 then buy 0 SOL")), (2, 25, "Buy amount must be positive".to_string()));
+        assert_eq!(
+            error(&with_token("when price > 1 then sell 0%")),
+            (2, 26, "Sell size must be between 0% and 100%".to_string())
+        );
+    }
+
+    #[test]
+    fn reports_missing_statements_on_the_last_line() {
+        assert_eq!(error("every 1m\n"), (1, 1, "Missing a 'token' line".to_string()));
+        assert_eq!(
+            error(&with_token("when price > 1 then sell all")),
+            (2, 1, "A strategy needs at least one 'when ... then buy' rule".to_string())
+        );
+        assert_eq!(
+            error(&with_token("when price > 1 then buy 1 SOL\nmin_profit_to_hold 5%")),
+            (3, 1, "min_profit_to_hold needs a max_hold".to_string())
+        );
+    }
+
+    #[test]
+    fn counts_columns_in_characters_after_multibyte_text() {
+        // An ideographic space is one column but three bytes
+        assert_eq!(error("\u{3000}token NOPE"), (1, 8, "Unknown token NOPE".to_string()));
+        let script = parse(&format!("\u{3000}token {}\nwhen price > 1 then buy 1 SOL", BONK)).unwrap();
+        assert_eq!(script.token_mint, BONK);
+    }
+
+    #[test]
+    fn sell_rules_are_keyed_by_rule_not_line() {
+        let first = parse(&with_token("when price > 1 then buy 1 SOL\nwhen price > 2 then sell 50%")).unwrap();
+        let moved = parse(&with_token("\n\nwhen price > 1 then buy 1 SOL\nwhen price > 2 then sell 50%")).unwrap();
+        assert_ne!(first.rules[1].line, moved.rules[1].line);
+        assert_eq!(first.rules[1].key(), moved.rules[1].key());
+        assert_ne!(first.rules[0].key(), first.rules[1].key());
+    }
+}
@@ -1,21 +1,99 @@
-use crate::utils::{ wallet::Wallet, price_feed::{ get_price, monitor_new_pools } };
+use crate::utils::{ wallet::Wallet, price_guard::PriceGuard, price_feed::{ get_checked_quote, token_unit, PoolInfo, SOL_MINT }, liquidity::{ cap_order_size, max_order_impact_bps }, pool_events::{ PoolSubscriber, LaunchSubscriber }, pump_fun::PumpEvent, token_safety::{ check_token, PoolContext }, honeypot::{ check_honeypot, max_round_trip_tax, allow_unsimulated } };
+use crate::utils::{ raydium::NewPoolEvent, token_metadata::resolve_metadata, slot_clock::SlotClock, pool_discovery::amm_v4_pool };
 use crate::strategies::stop_loss::StopLoss;
+use crate::strategies::take_profit::{ parse_rungs, TakeProfit };
+use crate::strategies::sniper_filters::{ FilterDecision, SniperFilters };
 use crate::utils::telegram::TelegramBot;
-use solana_sdk::instruction::Instruction;
+use crate::utils::price_feed::sol_to_lamports;
+use log::info;
+use std::time::{ SystemTime, UNIX_EPOCH };
+use crate::strategies::handle::StrategyHandle;
+use tokio::sync::watch;
 use tokio::time::{ sleep, Duration };
 
+/// Seconds before open time at which the buy is signed, well within blockhash validity.
+const PRESIGN_LEAD_SECS: u64 = 20;
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// A pool to buy `token_mint` from.
+struct SnipeTarget {
+    token_mint: String,
+    source: String,
+    pool: PoolInfo,
+    /// Unix time the pool accepts swaps from, 0 if already open.
+    open_time: u64,
+    /// Initial (token, SOL) reserves, known for freshly created pools.
+    reserves: Option<(u64, u64)>,
+}
+
+impl SnipeTarget {
+    fn from_new_pool(event: &NewPoolEvent, token_mint: String) -> Self {
+        let reserves = if event.base_mint == token_mint {
+            (event.init_base_amount, event.init_quote_amount)
+        } else {
+            (event.init_quote_amount, event.init_base_amount)
+        };
+        SnipeTarget {
+            source: format!("Raydium pool {}", event.pool_id),
+            pool: PoolInfo {
+                id: event.pool_id.clone(),
+                base_mint: event.base_mint.clone(),
+                quote_mint: event.quote_mint.clone(),
+                base_vault: event.base_vault.clone(),
+                quote_vault: event.quote_vault.clone(),
+                market_id: event.market_id.clone(),
+            },
+            token_mint,
+            open_time: event.open_time,
+            // Output can only be computed from reserves against SOL
+            reserves: (event.base_mint == SOL_MINT || event.quote_mint == SOL_MINT).then_some(reserves),
+        }
+    }
+}
+
 pub struct Sniper {
     wallet: Wallet,
     telegram: TelegramBot,
+    price_guard: PriceGuard,
     profit_target: f64, // e.g., 0.1 for 10%
+    min_safety_score: u32, // tokens scoring below this are skipped
+    filters: SniperFilters,
+    slot_clock: SlotClock,
+    buy_sol: f64, // SOL spent per snipe
+    slippage_bps: u64,
+    take_profit: Vec<(f64, f64)>, // (gain, fraction) rungs; empty sells everything at profit_target
+    break_even: bool, // move the stop to entry after the first take-profit rung
 }
 
 impl Sniper {
-    pub fn new(wallet: Wallet, telegram: TelegramBot) -> Self {
+    pub fn new(wallet: Wallet, telegram: TelegramBot, price_guard: PriceGuard, slot_clock: SlotClock) -> Self {
         Sniper {
             wallet,
             telegram,
+            price_guard,
             profit_target: 0.1,
+            min_safety_score: std::env::var("SNIPER_MIN_SAFETY_SCORE")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60),
+            filters: SniperFilters::default(),
+            slot_clock,
+            buy_sol: std::env::var("SNIPER_BUY_SOL")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0.1),
+            slippage_bps: std::env::var("SNIPER_SLIPPAGE_BPS")
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1000),
+            take_profit: std::env::var("SNIPER_TAKE_PROFIT")
+                .ok()
+                .and_then(|v| parse_rungs(&v).ok())
+                .unwrap_or_default(),
+            break_even: std::env::var("SNIPER_BREAK_EVEN").map(|v| v == "true").unwrap_or(false),
         }
     }
 
@@ -30,68 +108,338 @@ impl Sniper {
         Ok(())
     }
 
-    pub async fn start(
-        &self,
-        token_mint: String,
-        pool_rx: tokio::sync::mpsc::Receiver<String>
+    pub async fn set_min_safety_score(
+        &mut self,
+        score: u32
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut rx = pool_rx;
-        while let Some(pool_id) = rx.recv().await {
-            if pool_id == token_mint {
-                let price = get_price(&token_mint, "SOL", &self.telegram).await?;
-                let instruction = Instruction {
-                    program_id: solana_sdk::pubkey::Pubkey::from_str("RAY...").unwrap(),
-                    accounts: vec![],
-                    data: vec![],
-                };
-                self.wallet.send_transaction(instruction, &token_mint, "buy", price, 1000.0).await?;
-                self.telegram.send_message(&format!("Sniped {} at {}", token_mint, price)).await?;
+        self.min_safety_score = score;
+        self.telegram.send_message(
+            &format!("Set sniper minimum safety score to {}", score)
+        ).await?;
+        Ok(())
+    }
 
-                let profit_price = price * (1.0 + self.profit_target);
-                if get_price(&token_mint, "SOL", &self.telegram).await? >= profit_price {
-                    self.sell(&token_mint, profit_price).await?;
-                    return Ok(());
-                }
+    /// Sets the take-profit ladder from `gain:fraction` pairs, e.g. `0.3:0.25,1.0:0.25`.
+    pub async fn set_take_profit(
+        &mut self,
+        value: &str
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.take_profit = parse_rungs(value)?;
+        self.telegram.send_message(
+            &format!("Set sniper take-profit ladder to {:?}", self.take_profit)
+        ).await?;
+        Ok(())
+    }
 
-                let stop_loss = StopLoss::new(
-                    token_mint.clone(),
-                    price,
-                    0.05,
-                    0.05,
-                    self.wallet.clone(),
-                    self.telegram.clone()
-                );
-                tokio::spawn(async move {
-                    loop {
-                        if stop_loss.check().await.unwrap() {
-                            break;
+    pub async fn set_break_even(
+        &mut self,
+        enabled: bool
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.break_even = enabled;
+        self.telegram.send_message(
+            &format!("Set sniper break-even stop after first take-profit to {}", enabled)
+        ).await?;
+        Ok(())
+    }
+
+    /// Updates one entry filter, see `SniperFilters::set` for the keys.
+    pub async fn set_filter(
+        &mut self,
+        key: &str,
+        value: &str
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.filters.set(key, value)?;
+        self.telegram.send_message(
+            &format!("Set sniper filter {} to {}", key, value)
+        ).await?;
+        Ok(())
+    }
+
+    /// Runs the sniper in its own task until `StrategyHandle::stop`.
+    pub fn spawn(&self, target: Option<String>, pools: PoolSubscriber, launches: LaunchSubscriber) -> StrategyHandle {
+        let sniper = self.clone();
+        StrategyHandle::spawn(move |stopped| async move {
+            let name = target.clone().unwrap_or_else(|| "new pools".to_string());
+            if let Err(e) = sniper.start(target, pools, launches, stopped).await {
+                log::error!("Sniper for {} stopped: {}", name, e);
+            }
+        })
+    }
+
+    /// With a `target`, snipes that token when a Raydium pool for it is created or
+    /// when its pump.fun bonding curve migrates to an AMM. Without one, snipes any
+    /// new pool that passes the entry filters. A snipe in progress finishes before a stop.
+    async fn start(
+        &self,
+        target: Option<String>,
+        mut pools: PoolSubscriber,
+        mut launches: LaunchSubscriber,
+        mut stopped: watch::Receiver<bool>
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        loop {
+            let (snipe, pool) = tokio::select! {
+                _ = stopped.changed() => return Ok(()),
+                event = pools.recv() => {
+                    let Some(event) = event else { return Ok(()) };
+                    if let Some(target) = &target && event.base_mint != *target && event.quote_mint != *target {
+                        continue;
+                    }
+                    let Some(decision) = self.filter_pool(&event, target.as_deref()).await else { continue };
+                    if !decision.accepted {
+                        continue;
+                    }
+                    let pool = PoolContext {
+                        lp_mint: Some(event.lp_mint.clone()),
+                        initial_lp_supply: Some(
+                            ((event.init_base_amount as f64) * (event.init_quote_amount as f64)).sqrt() as u64
+                        ),
+                        creator: Some(event.creator.clone()),
+                        vaults: vec![event.base_vault.clone(), event.quote_vault.clone()],
+                        just_created: true,
+                    };
+                    (SnipeTarget::from_new_pool(&event, decision.token_mint.unwrap()), pool)
+                },
+                event = launches.recv() => match event {
+                    Some(PumpEvent::Migrated { mint, pool, venue, .. }) if Some(&mint) == target.as_ref() => {
+                        // Buys are built for Raydium AMM v4, so PumpSwap migrations cannot be sniped
+                        if venue != "Raydium" {
+                            info!("Not sniping {}: migrated to {} pool {}", mint, venue, pool);
+                            self.telegram.send_message(
+                                &format!("Skipped snipe of {}: migrated to unsupported venue {} ({})", mint, venue, pool)
+                            ).await?;
+                            continue;
                         }
-                        sleep(Duration::from_secs(60)).await;
+                        let pool_info = match amm_v4_pool(&pool).await {
+                            Ok(pool_info) => pool_info,
+                            Err(e) => {
+                                log::warn!("No pool keys for migrated {} pool {}: {}", mint, pool, e);
+                                continue;
+                            }
+                        };
+                        let snipe = SnipeTarget {
+                            pool: pool_info,
+                            token_mint: mint,
+                            source: format!("pump.fun migration to {} pool {}", venue, pool),
+                            open_time: 0,
+                            reserves: None,
+                        };
+                        (snipe, PoolContext::default())
+                    }
+                    Some(_) => continue,
+                    None => return Ok(()),
+                },
+            };
+            let token_mint = snipe.token_mint.clone();
+            // Cache the symbol so notifications about this token are readable
+            if let Err(e) = resolve_metadata(&token_mint).await {
+                log::warn!("No metadata for {}: {}", token_mint, e);
+            }
+            let Some(report) = check_token(&token_mint, &pool, &self.telegram).await? else {
+                self.telegram.send_message(
+                    &format!("Skipped snipe of {}: safety check not completed", token_mint)
+                ).await?;
+                continue;
+            };
+            if report.score < self.min_safety_score {
+                self.telegram.send_message(
+                    &format!(
+                        "Skipped snipe of {}: safety score {} below {}",
+                        token_mint, report.score, self.min_safety_score
+                    )
+                ).await?;
+                continue;
+            }
+            // Swaps fail before open time, so the round trip is simulated at open instead.
+            // The snipe waits for open in its own task so this loop keeps receiving pools.
+            if snipe.open_time > unix_now() {
+                self.telegram.send_message(
+                    &format!(
+                        "Snipe of {} scheduled: pool opens in {}s, honeypot check runs at open",
+                        token_mint, snipe.open_time - unix_now()
+                    )
+                ).await?;
+                let sniper = self.clone();
+                tokio::spawn(async move {
+                    let result = sniper.snipe(&snipe).await.map_err(|e| e.to_string());
+                    if let Err(e) = result {
+                        log::error!("Scheduled snipe of {} failed: {}", snipe.token_mint, e);
+                        let _ = sniper.telegram
+                            .send_message(&format!("Scheduled snipe of {} failed: {}", snipe.token_mint, e))
+                            .await;
                     }
                 });
+                continue;
+            } else {
+                let blocked = match check_honeypot(&self.wallet, &token_mint, &self.telegram).await {
+                    Ok(honeypot) => honeypot.is_blocked(max_round_trip_tax(), allow_unsimulated()),
+                    Err(e) => {
+                        log::warn!("Honeypot check for {} failed: {}", token_mint, e);
+                        true
+                    }
+                };
+                if blocked {
+                    self.telegram.send_message(
+                        &format!("Skipped snipe of {}: blocked by honeypot check", token_mint)
+                    ).await?;
+                    continue;
+                }
+            }
+            // A targeted sniper is done once its token hits the profit target
+            if self.snipe(&snipe).await? && target.is_some() {
+                return Ok(());
             }
         }
-        Ok(())
     }
 
-    async fn sell(&self, token_mint: &str, price: f64) -> Result<(), Box<dyn std::error::Error>> {
-        let instruction = Instruction {
-            program_id: solana_sdk::pubkey::Pubkey::from_str("RAY...").unwrap(),
-            accounts: vec![],
-            data: vec![],
+    /// Runs the entry filters on a pool and logs the decision. Returns `None` when
+    /// the pool does not involve the target token through an allowed quote mint.
+    async fn filter_pool(&self, event: &NewPoolEvent, target: Option<&str>) -> Option<FilterDecision> {
+        let token_mint = if self.filters.quote_mints.contains(&event.quote_mint) {
+            &event.base_mint
+        } else {
+            &event.quote_mint
+        };
+        let metadata = if self.filters.needs_metadata() {
+            resolve_metadata(token_mint).await.ok()
+        } else {
+            None
         };
-        self.wallet.send_transaction(instruction, token_mint, "sell", price, 1000.0).await?;
+        let decision = self.filters.evaluate(event, metadata.as_ref(), unix_now());
+        info!("Sniper filter for pool {}: {}", event.pool_id, decision.summary());
+        if target.is_some() && decision.token_mint.as_deref() != target {
+            return None;
+        }
+        Some(decision)
+    }
+
+    /// Sizes the buy for a pool. Pools that are not open yet cannot be quoted, so
+    /// their output is computed from the initial reserves.
+    async fn size_buy(&self, snipe: &SnipeTarget) -> Result<(u64, u64), Box<dyn std::error::Error>> {
+        let mut lamports_in = sol_to_lamports(self.buy_sol);
+        match snipe.reserves {
+            Some((token_reserve, sol_reserve)) => {
+                // Constant product impact is in / (reserve + in)
+                let max_bps = max_order_impact_bps();
+                let max_lamports = (sol_reserve as f64 * max_bps / (10_000.0 - max_bps)) as u64;
+                if lamports_in > max_lamports {
+                    self.telegram.send_message(
+                        &format!(
+                            "Capped snipe of {} from {} to {:.4} SOL ({} bps impact limit)",
+                            snipe.token_mint, self.buy_sol, max_lamports as f64 / 1e9, max_bps
+                        )
+                    ).await?;
+                    lamports_in = max_lamports;
+                }
+                // Raydium AMM v4 charges 0.25% on the input
+                let in_after_fee = lamports_in as u128 * 9975 / 10_000;
+                let out = token_reserve as u128 * in_after_fee / (sol_reserve as u128 + in_after_fee);
+                Ok((lamports_in, out as u64))
+            }
+            None => {
+                let price = self.price_guard.get_price(&snipe.token_mint, "SOL", &self.telegram).await?;
+                let amount = cap_order_size(&snipe.token_mint, "buy", self.buy_sol / price, price, &self.telegram).await?;
+                lamports_in = sol_to_lamports(price * amount);
+                let quote = get_checked_quote("SOL", &snipe.token_mint, lamports_in, &self.telegram).await?;
+                Ok((lamports_in, quote.out_amount))
+            }
+        }
+    }
+
+    /// Buys at the pool's open time and arms the stop-loss. The transaction is signed
+    /// shortly before open and submitted at the slot matching open time; the landing
+    /// latency in slots is reported. Pools that were not open when found get their
+    /// honeypot check at open, before submission. Returns true if the profit target
+    /// was hit immediately.
+    async fn snipe(&self, snipe: &SnipeTarget) -> Result<bool, Box<dyn std::error::Error>> {
+        let token_mint = snipe.token_mint.as_str();
+        let scheduled = snipe.open_time > unix_now();
+        let (lamports_in, expected_out) = self.size_buy(snipe).await?;
+        if expected_out == 0 {
+            return Err(format!("Pool for {} returns no tokens", token_mint).into());
+        }
+        let min_out = (expected_out as u128 * (10_000 - self.slippage_bps as u128) / 10_000) as u64;
+        let amount = expected_out as f64 / token_unit(token_mint).await?;
+        let price = lamports_in as f64 / 1e9 / amount;
+        let instructions = self.wallet.buy_instructions(&snipe.pool, token_mint, lamports_in, min_out)?;
+
+        // Blockhashes expire after ~150 slots, so sign shortly before open
+        let now = unix_now();
+        if snipe.open_time > now + PRESIGN_LEAD_SECS {
+            sleep(Duration::from_secs(snipe.open_time - now - PRESIGN_LEAD_SECS)).await;
+        }
+        let tx = self.wallet.sign_transaction(&instructions)?;
+        let open_slot = self.slot_clock.slot_at(snipe.open_time);
+        self.slot_clock.wait_for_slot(open_slot).await?;
+        // Submission skips preflight, so this simulation is the only sell check
+        if scheduled {
+            let blocked = match check_honeypot(&self.wallet, token_mint, &self.telegram).await {
+                Ok(honeypot) => honeypot.is_blocked(max_round_trip_tax(), allow_unsimulated()),
+                Err(e) => {
+                    log::warn!("Honeypot check for {} failed: {}", token_mint, e);
+                    true
+                }
+            };
+            if blocked {
+                self.telegram.send_message(
+                    &format!("Cancelled snipe of {}: blocked by honeypot check at open", token_mint)
+                ).await?;
+                return Ok(false);
+            }
+        }
+        let submitted_slot = self.slot_clock.current_slot();
+        let (signature, landed_slot) = self.wallet
+            .submit_signed_transaction(&tx, token_mint, "buy", price, amount).await?;
+        let latency = landed_slot.saturating_sub(open_slot);
+        info!(
+            "Snipe {} of {}: target slot {}, submitted at {}, landed at {} ({} slots)",
+            signature, token_mint, open_slot, submitted_slot, landed_slot, latency
+        );
         self.telegram.send_message(
-            &format!("Sold {} at profit target: {}", token_mint, price)
+            &format!(
+                "Sniped {} at {} ({}): landed in slot {}, {} slots after target slot {}",
+                token_mint, price, snipe.source, landed_slot, latency, open_slot
+            )
         ).await?;
-        Ok(())
+
+        let mut stop_loss = StopLoss::new(
+            token_mint.to_string(),
+            price,
+            amount,
+            0.05,
+            0.05,
+            self.wallet.clone(),
+            self.telegram.clone(),
+            self.price_guard.clone()
+        )
+        // Sell a launch that has not moved into profit within 10 minutes
+        .with_max_hold(600, Some(0.0))
+        .with_env("SNIPER");
+        // Without a configured ladder the whole position is sold at the profit target
+        let rungs = if self.take_profit.is_empty() { vec![(self.profit_target, 1.0)] } else { self.take_profit.clone() };
+        stop_loss.attach_take_profit(
+            TakeProfit::new(token_mint.to_string(), price, amount, &rungs, self.wallet.clone(), self.telegram.clone())
+                .with_break_even(self.break_even)
+        );
+        if stop_loss.check().await? {
+            return Ok(true);
+        }
+        stop_loss.spawn();
+        Ok(false)
     }
 
     pub fn clone(&self) -> Self {
         Sniper {
             wallet: self.wallet.clone(),
             telegram: TelegramBot::new(),
+            price_guard: self.price_guard.clone(),
             profit_target: self.profit_target,
+            min_safety_score: self.min_safety_score,
+            filters: self.filters.clone(),
+            slot_clock: self.slot_clock.clone(),
+            buy_sol: self.buy_sol,
+            slippage_bps: self.slippage_bps,
+            take_profit: self.take_profit.clone(),
+            break_even: self.break_even,
         }
     }
 }
@@ -0,0 +1,283 @@
+use crate::utils::price_feed::SOL_MINT;
+use crate::utils::raydium::NewPoolEvent;
+use crate::utils::token_metadata::TokenMetadata;
+use regex::Regex;
+use std::collections::HashSet;
+
+/// Entry filters applied to every new pool before the sniper considers it.
+#[derive(Debug, Clone)]
+pub struct SniperFilters {
+    pub min_liquidity_sol: f64,
+    pub max_liquidity_sol: f64,
+    /// Quote mints the sniper will pay with.
+    pub quote_mints: HashSet<String>,
+    /// Skip pools opening further than this in the future.
+    pub max_open_delay_secs: u64,
+    pub creator_blacklist: HashSet<String>,
+    /// When non-empty, only pools created by these wallets pass.
+    pub creator_whitelist: HashSet<String>,
+    pub name_pattern: Option<Regex>,
+    pub symbol_pattern: Option<Regex>,
+}
+
+/// Outcome of running a pool through the filters.
+#[derive(Debug, Clone)]
+pub struct FilterDecision {
+    pub accepted: bool,
+    /// The mint that would be bought, if the pool pairs it with an allowed quote mint.
+    pub token_mint: Option<String>,
+    pub reasons: Vec<String>,
+}
+
+impl FilterDecision {
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {}: {}",
+            if self.accepted { "Accepted" } else { "Rejected" },
+            self.token_mint.as_deref().unwrap_or("pool"),
+            self.reasons.join("; ")
+        )
+    }
+}
+
+impl Default for SniperFilters {
+    fn default() -> Self {
+        SniperFilters {
+            min_liquidity_sol: 5.0,
+            max_liquidity_sol: 500.0,
+            quote_mints: HashSet::from([SOL_MINT.to_string()]),
+            max_open_delay_secs: 3600,
+            creator_blacklist: HashSet::new(),
+            creator_whitelist: HashSet::new(),
+            name_pattern: None,
+            symbol_pattern: None,
+        }
+    }
+}
+
+fn parse_set(value: &str) -> HashSet<String> {
+    value
+        .split(',')
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .collect()
+}
+
+impl SniperFilters {
+    /// Updates one filter from a `/set_params <token> sniper <key> <value>` command.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "min_liquidity" => self.min_liquidity_sol = value.parse().map_err(|_| "Invalid number")?,
+            "max_liquidity" => self.max_liquidity_sol = value.parse().map_err(|_| "Invalid number")?,
+            "quote_mints" => self.quote_mints = parse_set(value),
+            "max_open_delay" => self.max_open_delay_secs = value.parse().map_err(|_| "Invalid number")?,
+            "creator_blacklist" => self.creator_blacklist = parse_set(value),
+            "creator_whitelist" => self.creator_whitelist = parse_set(value),
+            "name_regex" => {
+                self.name_pattern = if value == "none" {
+                    None
+                } else {
+                    Some(Regex::new(value).map_err(|e| e.to_string())?)
+                }
+            }
+            "symbol_regex" => {
+                self.symbol_pattern = if value == "none" {
+                    None
+                } else {
+                    Some(Regex::new(value).map_err(|e| e.to_string())?)
+                }
+            }
+            _ => return Err(format!("Unknown sniper filter {}", key)),
+        }
+        Ok(())
+    }
+
+    /// Runs every filter and collects the reason for each pass or failure.
+    /// `now` is the current unix time; `metadata` is the bought token's metadata, if any.
+    pub fn evaluate(&self, event: &NewPoolEvent, metadata: Option<&TokenMetadata>, now: u64) -> FilterDecision {
+        let mut reasons = Vec::new();
+        let mut accepted = true;
+        let mut reject = |reason: String, reasons: &mut Vec<String>| {
+            accepted = false;
+            reasons.push(reason);
+        };
+
+        // Pools can list the quote mint on either side
+        let (token_mint, quote_mint, quote_amount) = if self.quote_mints.contains(&event.quote_mint) {
+            (Some(event.base_mint.clone()), event.quote_mint.as_str(), event.init_quote_amount)
+        } else if self.quote_mints.contains(&event.base_mint) {
+            (Some(event.quote_mint.clone()), event.base_mint.as_str(), event.init_base_amount)
+        } else {
+            (None, event.quote_mint.as_str(), 0)
+        };
+        if token_mint.is_none() {
+            reject(format!("quote mint {} not allowed", event.quote_mint), &mut reasons);
+        } else {
+            reasons.push(format!("quote mint {} allowed", quote_mint));
+        }
+
+        if quote_mint == SOL_MINT {
+            let liquidity = quote_amount as f64 / 1_000_000_000.0;
+            if liquidity < self.min_liquidity_sol {
+                reject(format!("liquidity {:.2} SOL below {}", liquidity, self.min_liquidity_sol), &mut reasons);
+            } else if liquidity > self.max_liquidity_sol {
+                reject(format!("liquidity {:.2} SOL above {}", liquidity, self.max_liquidity_sol), &mut reasons);
+            } else {
+                reasons.push(format!("liquidity {:.2} SOL in range", liquidity));
+            }
+        } else if token_mint.is_some() {
+            reasons.push("liquidity not checked for non-SOL quote".to_string());
+        }
+
+        let open_delay = event.open_time.saturating_sub(now);
+        if open_delay > self.max_open_delay_secs {
+            reject(format!("opens in {}s, more than {}s", open_delay, self.max_open_delay_secs), &mut reasons);
+        } else {
+            reasons.push(format!("opens in {}s", open_delay));
+        }
+
+        if self.creator_blacklist.contains(&event.creator) {
+            reject(format!("creator {} blacklisted", event.creator), &mut reasons);
+        } else if !self.creator_whitelist.is_empty() && !self.creator_whitelist.contains(&event.creator) {
+            reject(format!("creator {} not whitelisted", event.creator), &mut reasons);
+        }
+
+        for (label, pattern, value) in [
+            ("name", &self.name_pattern, metadata.map(|m| m.name.as_str())),
+            ("symbol", &self.symbol_pattern, metadata.map(|m| m.symbol.as_str())),
+        ] {
+            let Some(pattern) = pattern else { continue };
+            match value {
+                Some(value) if pattern.is_match(value) => reasons.push(format!("{} {} matches", label, value)),
+                Some(value) => reject(format!("{} {} does not match {}", label, value, pattern), &mut reasons),
+                None => reject(format!("no metadata to match {}", label), &mut reasons),
+            }
+        }
+
+        FilterDecision { accepted, token_mint, reasons }
+    }
+
+    /// Whether any filter needs token metadata, so it is only fetched when required.
+    pub fn needs_metadata(&self) -> bool {
+        self.name_pattern.is_some() || self.symbol_pattern.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    fn event(base_mint: &str, quote_mint: &str, quote_sol: f64) -> NewPoolEvent {
+        NewPoolEvent {
+            signature: "sig".to_string(),
+            slot: 1,
+            pool_id: "pool".to_string(),
+            base_mint: base_mint.to_string(),
+            quote_mint: quote_mint.to_string(),
+            base_vault: "base_vault".to_string(),
+            quote_vault: "quote_vault".to_string(),
+            lp_mint: "lp".to_string(),
+            market_id: "market".to_string(),
+            creator: "creator".to_string(),
+            open_time: NOW,
+            init_base_amount: 1_000_000,
+            init_quote_amount: (quote_sol * 1_000_000_000.0) as u64,
+        }
+    }
+
+    fn metadata(name: &str, symbol: &str) -> TokenMetadata {
+        TokenMetadata {
+            mint: "TOKEN".to_string(),
+            name: name.to_string(),
+            symbol: symbol.to_string(),
+            decimals: 6,
+            uri: String::new(),
+            is_mutable: false,
+        }
+    }
+
+    #[test]
+    fn accepts_a_sol_pool_in_range() {
+        let decision = SniperFilters::default().evaluate(&event("TOKEN", SOL_MINT, 50.0), None, NOW);
+        assert!(decision.accepted, "{}", decision.summary());
+        assert_eq!(decision.token_mint.as_deref(), Some("TOKEN"));
+    }
+
+    #[test]
+    fn finds_the_token_when_sol_is_the_base_mint() {
+        let mut pool = event(SOL_MINT, "TOKEN", 0.0);
+        pool.init_base_amount = 50_000_000_000;
+        let decision = SniperFilters::default().evaluate(&pool, None, NOW);
+        assert!(decision.accepted, "{}", decision.summary());
+        assert_eq!(decision.token_mint.as_deref(), Some("TOKEN"));
+    }
+
+    #[test]
+    fn rejects_pools_without_an_allowed_quote_mint() {
+        let decision = SniperFilters::default().evaluate(&event("TOKEN", "USDC", 50.0), None, NOW);
+        assert!(!decision.accepted);
+        assert!(decision.token_mint.is_none());
+    }
+
+    #[test]
+    fn rejects_liquidity_out_of_range() {
+        let filters = SniperFilters::default();
+        assert!(!filters.evaluate(&event("TOKEN", SOL_MINT, 1.0), None, NOW).accepted);
+        assert!(!filters.evaluate(&event("TOKEN", SOL_MINT, 1_000.0), None, NOW).accepted);
+    }
+
+    #[test]
+    fn rejects_pools_opening_too_late() {
+        let filters = SniperFilters::default();
+        let mut pool = event("TOKEN", SOL_MINT, 50.0);
+        pool.open_time = NOW + filters.max_open_delay_secs;
+        assert!(filters.evaluate(&pool, None, NOW).accepted);
+        pool.open_time += 1;
+        assert!(!filters.evaluate(&pool, None, NOW).accepted);
+    }
+
+    #[test]
+    fn applies_creator_lists() {
+        let pool = event("TOKEN", SOL_MINT, 50.0);
+        let mut filters = SniperFilters::default();
+        filters.set("creator_whitelist", "someone, else").unwrap();
+        assert!(!filters.evaluate(&pool, None, NOW).accepted);
+        filters.set("creator_whitelist", "creator").unwrap();
+        assert!(filters.evaluate(&pool, None, NOW).accepted);
+        // The blacklist wins over the whitelist
+        filters.set("creator_blacklist", "creator").unwrap();
+        assert!(!filters.evaluate(&pool, None, NOW).accepted);
+    }
+
+    #[test]
+    fn matches_name_and_symbol_patterns() {
+        let pool = event("TOKEN", SOL_MINT, 50.0);
+        let mut filters = SniperFilters::default();
+        assert!(!filters.needs_metadata());
+        filters.set("symbol_regex", "^[A-Z]{3,5}$").unwrap();
+        assert!(filters.needs_metadata());
+        assert!(filters.evaluate(&pool, Some(&metadata("Token", "TKN")), NOW).accepted);
+        assert!(!filters.evaluate(&pool, Some(&metadata("Token", "tkn")), NOW).accepted);
+        assert!(!filters.evaluate(&pool, None, NOW).accepted);
+        filters.set("symbol_regex", "none").unwrap();
+        assert!(filters.evaluate(&pool, None, NOW).accepted);
+    }
+
+    #[test]
+    fn set_parses_values_and_rejects_bad_ones() {
+        let mut filters = SniperFilters::default();
+        filters.set("min_liquidity", "2.5").unwrap();
+        filters.set("max_open_delay", "60").unwrap();
+        filters.set("quote_mints", "A,,B ").unwrap();
+        assert_eq!(filters.min_liquidity_sol, 2.5);
+        assert_eq!(filters.max_open_delay_secs, 60);
+        assert_eq!(filters.quote_mints, HashSet::from(["A".to_string(), "B".to_string()]));
+
+        assert!(filters.set("min_liquidity", "lots").is_err());
+        assert!(filters.set("name_regex", "(").is_err());
+        assert!(filters.set("unknown", "1").is_err());
+        assert_eq!(filters.min_liquidity_sol, 2.5);
+    }
+}
@@ -1,60 +1,840 @@
-use crate::utils::{wallet::Wallet, price_feed::get_price};
+use crate::utils::{wallet::Wallet, price_guard::PriceGuard, candles::{atr, fetch_candles}};
+use crate::utils::{liquidity::cap_order_size, price_feed::{get_checked_quote, token_unit}};
 use crate::utils::telegram::TelegramBot;
-use solana_sdk::instruction::Instruction;
+use crate::strategies::take_profit::{TakeProfit, TakeProfitState};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Mutex, OnceLock};
+use tokio::time::{sleep, Duration, Instant};
 
+/// Candles averaged for ATR-based trailing distances.
+const ATR_PERIOD: usize = 14;
+/// How long a fetched ATR is reused before refreshing.
+const ATR_REFRESH: Duration = Duration::from_secs(300);
+/// Positions below this many tokens count as closed.
+const DUST: f64 = 1e-6;
+
+static STORE: OnceLock<StopLossStore> = OnceLock::new();
+/// Ids of guards with a running monitor task, so a resumed guard is not watched twice.
+static RUNNING: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+
+/// How far below the high-water mark the trailing stop sits.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrailingDistance {
+    /// Fraction of the high-water mark, e.g. 0.05 for 5%.
+    Percent(f64),
+    /// Multiple of the average true range on `timeframe` candles.
+    Atr { multiplier: f64, timeframe: String },
+}
+
+/// Closes a position by age rather than price. Gains are fractions over entry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TimeStop {
+    /// Close once the position has been held this long...
+    pub max_hold_secs: Option<i64>,
+    /// ...unless it is at least this far in profit, in which case the other stops take over.
+    pub min_profit_to_hold: Option<f64>,
+    /// Take profit at a target that falls linearly from `start_gain` to `end_gain` over `decay_secs`.
+    pub decay: Option<DecayingTarget>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct DecayingTarget {
+    pub start_gain: f64,
+    pub end_gain: f64,
+    pub decay_secs: i64,
+}
+
+impl DecayingTarget {
+    /// Parses `start,end,secs`, e.g. `0.5,0.05,1800` lowers the target from +50% to +5% over 30 minutes.
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = value.split(',').map(|p| p.trim()).collect();
+        if parts.len() != 3 {
+            return Err(format!("Expected start,end,secs, got {}", value));
+        }
+        let start_gain = parts[0].parse::<f64>().map_err(|_| format!("Invalid start gain {}", parts[0]))?;
+        let end_gain = parts[1].parse::<f64>().map_err(|_| format!("Invalid end gain {}", parts[1]))?;
+        let decay_secs = parts[2].parse::<i64>().map_err(|_| format!("Invalid decay time {}", parts[2]))?;
+        if decay_secs <= 0 || end_gain > start_gain {
+            return Err("Decaying target needs a positive time and an end gain no higher than the start".to_string());
+        }
+        Ok(DecayingTarget { start_gain, end_gain, decay_secs })
+    }
+
+    /// Target gain after `held_secs`.
+    pub fn target_at(&self, held_secs: i64) -> f64 {
+        let progress = (held_secs as f64 / self.decay_secs as f64).clamp(0.0, 1.0);
+        self.start_gain - (self.start_gain - self.end_gain) * progress
+    }
+}
+
+impl TimeStop {
+    /// Which rule, if any, closes a position held `held_secs` at `gain` over entry.
+    pub fn triggered(&self, held_secs: i64, gain: f64) -> Option<String> {
+        if let Some(decay) = &self.decay {
+            let target = decay.target_at(held_secs);
+            if gain >= target {
+                return Some(format!(
+                    "decaying profit target reached: +{:.1}% against a target of +{:.1}% after {}s",
+                    gain * 100.0, target * 100.0, held_secs
+                ));
+            }
+        }
+        if let Some(max_hold) = self.max_hold_secs && held_secs >= max_hold {
+            match self.min_profit_to_hold {
+                Some(min_profit) if gain >= min_profit => {}
+                Some(min_profit) => {
+                    return Some(format!(
+                        "held {}s (max {}s) at {:+.1}%, below the {:+.1}% needed to keep holding",
+                        held_secs, max_hold, gain * 100.0, min_profit * 100.0
+                    ));
+                }
+                None => return Some(format!("max holding time of {}s reached at {:+.1}%", max_hold, gain * 100.0)),
+            }
+        }
+        None
+    }
+}
+
+/// Guards one position with a fixed stop and a trailing stop. The high-water mark is
+/// persisted on every new high, so a restart resumes the same trailing level, and a
+/// take-profit ladder is persisted with its filled rungs.
+///
+/// Exits only ever sell the guard's own `quantity`, never the rest of the wallet
+/// balance, so strategies holding the same token do not close each other out.
 pub struct StopLoss {
+    id: String,
     token_mint: String,
     entry_price: f64,
+    /// Tokens still held by this position.
+    quantity: f64,
+    /// Exit sells are split into this many tranches, `tranche_interval` apart.
+    exit_tranches: u32,
+    tranche_interval: Duration,
     fixed_stop_loss: f64,
-    trailing_stop_loss: f64,
+    trailing: TrailingDistance,
+    /// Percentage trailing distance, used when an ATR distance cannot be computed.
+    trailing_percent: f64,
+    /// Gain over entry required before the trailing stop is armed, e.g. 0.1 for +10%.
+    activation_gain: Option<f64>,
+    high_water_mark: f64,
     wallet: Wallet,
     telegram: TelegramBot,
+    price_guard: PriceGuard,
+    take_profit: Option<TakeProfit>,
+    /// Set once the stop has moved up to the entry price.
+    break_even: bool,
+    time_stop: TimeStop,
+    /// Unix seconds the position was opened, for the time-based rules.
+    opened_at: i64,
+    atr_cache: Option<(Instant, f64)>,
 }
 
 impl StopLoss {
-    pub fn new(token_mint: String, entry_price: f64, fixed_stop_loss: f64, trailing_stop_loss: f64, wallet: Wallet, telegram: TelegramBot) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(token_mint: String, entry_price: f64, quantity: f64, fixed_stop_loss: f64, trailing_stop_loss: f64, wallet: Wallet, telegram: TelegramBot, price_guard: PriceGuard) -> Self {
         StopLoss {
+            id: format!("{}:{}", token_mint, chrono::Utc::now().timestamp_millis()),
             token_mint,
             entry_price,
+            quantity,
+            exit_tranches: 1,
+            tranche_interval: Duration::from_secs(10),
             fixed_stop_loss,
-            trailing_stop_loss,
+            trailing: TrailingDistance::Percent(trailing_stop_loss),
+            trailing_percent: trailing_stop_loss,
+            activation_gain: None,
+            high_water_mark: entry_price,
             wallet,
             telegram,
+            price_guard,
+            take_profit: None,
+            break_even: false,
+            time_stop: TimeStop::default(),
+            opened_at: chrono::Utc::now().timestamp(),
+            atr_cache: None,
         }
     }
 
-    pub async fn check(&self) -> Result<bool, Box<dyn std::error::Error>> {
-        let current_price = get_price(&self.token_mint, "SOL", &self.telegram).await?;
-        let fixed_threshold = self.entry_price * (1.0 - self.fixed_stop_loss);
-        let mut trailing_threshold = self.entry_price;
+    /// Names the guard, e.g. `grid:<mint>`, so a strategy restarting on the same
+    /// token picks up the already resumed guard instead of starting a second one.
+    pub fn with_id(mut self, id: String) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Trails `multiplier` ATRs of `timeframe` candles below the high instead of a percentage.
+    pub fn with_trailing_atr(mut self, multiplier: f64, timeframe: &str) -> Self {
+        self.trailing = TrailingDistance::Atr { multiplier, timeframe: timeframe.to_string() };
+        self
+    }
+
+    /// Arms the trailing stop only after price has gained `gain` over entry.
+    pub fn with_activation(mut self, gain: f64) -> Self {
+        self.activation_gain = Some(gain);
+        self
+    }
+
+    /// Closes the position after `secs`, unless it is at least `min_profit` in profit.
+    pub fn with_max_hold(mut self, secs: i64, min_profit: Option<f64>) -> Self {
+        self.time_stop.max_hold_secs = Some(secs);
+        self.time_stop.min_profit_to_hold = min_profit;
+        self
+    }
+
+    /// Takes profit at a target that lowers as the position ages.
+    pub fn with_decaying_target(mut self, decay: DecayingTarget) -> Self {
+        self.time_stop.decay = Some(decay);
+        self
+    }
 
-        if current_price > trailing_threshold {
-            trailing_threshold = current_price * (1.0 - self.trailing_stop_loss);
+    /// Splits exit sells into `count` tranches `interval_secs` apart, to limit price impact on thin pools.
+    pub fn with_exit_tranches(mut self, count: u32, interval_secs: u64) -> Self {
+        self.exit_tranches = count.max(1);
+        self.tranche_interval = Duration::from_secs(interval_secs);
+        self
+    }
+
+    /// Applies per-strategy settings from the environment: `<PREFIX>_TRAILING_ATR`
+    /// (ATR multiple, with `<PREFIX>_TRAILING_TIMEFRAME`, default 15m),
+    /// `<PREFIX>_TRAILING_ACTIVATION` (gain before trailing starts), `<PREFIX>_MAX_HOLD_SECS`
+    /// (with `<PREFIX>_MIN_PROFIT_TO_HOLD`), `<PREFIX>_DECAY_TARGET` (`start,end,secs`) and
+    /// `<PREFIX>_EXIT_TRANCHES` (with `<PREFIX>_EXIT_TRANCHE_SECS`, default 10).
+    pub fn with_env(mut self, prefix: &str) -> Self {
+        let var = |name: &str| std::env::var(format!("{}_{}", prefix, name)).ok();
+        if let Some(multiplier) = var("TRAILING_ATR").and_then(|v| v.parse().ok()) {
+            let timeframe = var("TRAILING_TIMEFRAME").unwrap_or_else(|| "15m".to_string());
+            self = self.with_trailing_atr(multiplier, &timeframe);
+        }
+        if let Some(gain) = var("TRAILING_ACTIVATION").and_then(|v| v.parse().ok()) {
+            self = self.with_activation(gain);
+        }
+        if let Some(secs) = var("MAX_HOLD_SECS").and_then(|v| v.parse().ok()) {
+            let min_profit = var("MIN_PROFIT_TO_HOLD").and_then(|v| v.parse().ok());
+            self = self.with_max_hold(secs, min_profit);
+        }
+        if let Some(value) = var("DECAY_TARGET") {
+            match DecayingTarget::parse(&value) {
+                Ok(decay) => self = self.with_decaying_target(decay),
+                Err(e) => log::warn!("Ignoring {}_DECAY_TARGET: {}", prefix, e),
+            }
         }
+        if let Some(count) = var("EXIT_TRANCHES").and_then(|v| v.parse().ok()) {
+            let interval = var("EXIT_TRANCHE_SECS").and_then(|v| v.parse().ok()).unwrap_or(10);
+            self = self.with_exit_tranches(count, interval);
+        }
+        self
+    }
 
-        if current_price <= fixed_threshold || current_price <= trailing_threshold {
-            self.sell().await?;
+    /// Adds `delta` tokens (negative for sells) to the position guarded by `id`, for
+    /// strategies like the grid whose holdings change while the guard runs.
+    pub fn adjust_position(id: &str, delta: f64) -> Result<(), Box<dyn std::error::Error>> {
+        StopLossStore::global()?.adjust_quantity(id, delta)?;
+        Ok(())
+    }
+
+    /// Whether the guard `id` still has an open position.
+    pub fn is_open(id: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(Self::position(id)?.is_some())
+    }
+
+    /// Tokens still held by the position guarded by `id`, `None` once it is closed.
+    pub fn position(id: &str) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+        Ok(StopLossStore::global()?.quantity(id)?)
+    }
+
+    /// Closes the guard `id` after its owning strategy sold the position itself.
+    /// The running monitor stops on its next check.
+    pub fn release(id: &str) -> Result<(), Box<dyn std::error::Error>> {
+        StopLossStore::global()?.remove(id)?;
+        Ok(())
+    }
+
+    /// Scales out through `take_profit` once the fixed stop has been checked.
+    pub fn attach_take_profit(&mut self, take_profit: TakeProfit) {
+        self.take_profit = Some(take_profit);
+    }
+
+    /// Monitors the position every minute until it is closed. Does nothing if a guard
+    /// with the same id is already running.
+    pub fn spawn(mut self) {
+        let running = RUNNING.get_or_init(|| Mutex::new(HashSet::new()));
+        if !running.lock().unwrap().insert(self.id.clone()) {
+            log::info!("Stop-loss {} is already running", self.id);
+            return;
+        }
+        // Saved before the task starts, so the owning strategy sees the guard as open right away
+        if let Err(e) = self.save() {
+            log::error!("Failed to save stop-loss {}: {}", self.id, e);
+        }
+        tokio::spawn(async move {
+            loop {
+                if matches!(Self::is_open(&self.id), Ok(false)) {
+                    break;
+                }
+                match self.check().await {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(e) => log::warn!("Stop-loss check for {} failed: {}", self.token_mint, e),
+                }
+                sleep(Duration::from_secs(60)).await;
+            }
+            running.lock().unwrap().remove(&self.id);
+        });
+    }
+
+    /// Restarts monitoring for every open position saved before a restart.
+    pub fn resume_all(wallet: &Wallet, telegram: &TelegramBot, price_guard: &PriceGuard) -> Result<usize, Box<dyn std::error::Error>> {
+        let rows = StopLossStore::global()?.load()?;
+        let count = rows.len();
+        for row in rows {
+            let take_profit = row
+                .take_profit
+                .map(|state| TakeProfit::restore(row.token_mint.clone(), state, wallet.clone(), telegram.clone()));
+            StopLoss {
+                id: row.id,
+                token_mint: row.token_mint,
+                entry_price: row.entry_price,
+                quantity: row.quantity,
+                exit_tranches: row.exit_tranches,
+                tranche_interval: Duration::from_secs(row.tranche_secs),
+                fixed_stop_loss: row.fixed_stop_loss,
+                trailing: row.trailing,
+                trailing_percent: row.trailing_percent,
+                activation_gain: row.activation_gain,
+                high_water_mark: row.high_water_mark,
+                wallet: wallet.clone(),
+                telegram: telegram.clone(),
+                price_guard: price_guard.clone(),
+                take_profit,
+                break_even: row.break_even,
+                time_stop: row.time_stop,
+                opened_at: row.opened_at,
+                atr_cache: None,
+            }
+            .spawn();
+        }
+        Ok(count)
+    }
+
+    /// Returns true once the position is closed, by the stop or by the last take-profit rung.
+    /// The fixed stop is checked first, so a failing ladder or ATR cannot hold it up.
+    /// Nothing fires while the position is empty.
+    pub async fn check(&mut self) -> Result<bool, Box<dyn std::error::Error>> {
+        // Pick up fills recorded by the owning strategy since the last check
+        if let Some(quantity) = StopLossStore::global()?.quantity(&self.id)? {
+            self.quantity = quantity;
+        }
+        // An empty position, e.g. a grid waiting for its first buy, has nothing to stop out
+        if self.quantity <= DUST {
+            return Ok(false);
+        }
+        let current_price = self.price_guard.get_price(&self.token_mint, "SOL", &self.telegram).await?;
+
+        if raise_high_water_mark(&mut self.high_water_mark, current_price) {
+            self.save()?;
+        }
+
+        let fixed_threshold = fixed_threshold(self.entry_price, self.fixed_stop_loss, self.break_even);
+        if current_price <= fixed_threshold {
+            let reason = format!(
+                "Stop-loss triggered for {} at {}. Fixed: {}, High: {}",
+                self.token_mint, current_price, fixed_threshold, self.high_water_mark
+            );
+            return self.close(current_price, reason).await;
+        }
+
+        if let Some(take_profit) = &mut self.take_profit {
+            let sold = take_profit.check(current_price, self.quantity).await;
+            let closed = take_profit.is_closed();
+            let move_to_break_even = take_profit.break_even_due();
+            if sold > 0.0 {
+                self.reduce(sold)?;
+                self.save()?;
+            }
+            if closed {
+                StopLossStore::global()?.remove(&self.id)?;
+                return Ok(true);
+            }
+            if move_to_break_even && !self.break_even {
+                self.break_even = true;
+                self.save()?;
+                self.telegram
+                    .send_message(&format!("Moved stop for {} to break-even at {}", self.token_mint, self.entry_price))
+                    .await?;
+            }
+        }
+
+        let trailing_threshold = if trailing_armed(self.entry_price, self.high_water_mark, self.activation_gain) {
+            Some(self.high_water_mark - self.trailing_distance().await)
+        } else {
+            None
+        };
+        if let Some(threshold) = trailing_threshold.filter(|t| current_price <= *t) {
+            let reason = format!(
+                "Trailing stop triggered for {} at {}. Trailing: {}, High: {}",
+                self.token_mint, current_price, threshold, self.high_water_mark
+            );
+            return self.close(current_price, reason).await;
+        }
+
+        let held_secs = chrono::Utc::now().timestamp() - self.opened_at;
+        let gain = current_price / self.entry_price - 1.0;
+        if let Some(reason) = self.time_stop.triggered(held_secs, gain) {
+            let reason = format!("Time-based stop closed {} at {}: {}", self.token_mint, current_price, reason);
+            return self.close(current_price, reason).await;
+        }
+        Ok(false)
+    }
+
+    /// Sells the position and reports `reason`. Returns false while part of it is
+    /// still unsold, so the next check retries the rest.
+    async fn close(&mut self, price: f64, reason: String) -> Result<bool, Box<dyn std::error::Error>> {
+        let sold = self.sell(price).await?;
+        if self.quantity > DUST {
             self.telegram
-                .send_message(&format!(
-                    "Stop-loss triggered for {} at {}. Fixed: {}, Trailing: {}",
-                    self.token_mint, current_price, fixed_threshold, trailing_threshold
-                ))
+                .send_message(&format!("{}. Sold {:.2} tokens, {:.2} left to sell on the next check", reason, sold, self.quantity))
                 .await?;
-            return Ok(true);
+            return Ok(false);
         }
-        Ok(false)
+        StopLossStore::global()?.remove(&self.id)?;
+        self.telegram
+            .send_message(&format!("{}. Sold {:.2} tokens", reason, sold))
+            .await?;
+        Ok(true)
     }
 
-    async fn sell(&self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Sells this position's tokens in tranches, each capped by route impact, and
+    /// returns the amount sold.
+    async fn sell(&mut self, mut price: f64) -> Result<f64, Box<dyn std::error::Error>> {
+        // Tokens moved out of the wallet by hand can no longer be sold by this guard
         let balance = self.wallet.get_balance(&self.token_mint).await?;
-        if balance > 0.0 {
-            let instruction = Instruction {
-                program_id: solana_sdk::pubkey::Pubkey::from_str("RAY...").unwrap(),
-                accounts: vec![],
-                data: vec![],
+        if balance < self.quantity {
+            log::warn!("Position {} holds {} tokens but the wallet only has {}", self.id, self.quantity, balance);
+            self.reduce(self.quantity - balance)?;
+        }
+        let unit = token_unit(&self.token_mint).await?;
+        let tranche = self.quantity / self.exit_tranches as f64;
+        let mut sold = 0.0;
+        for index in 0..self.exit_tranches {
+            if self.quantity <= DUST {
+                break;
+            }
+            if index > 0 {
+                sleep(self.tranche_interval).await;
+                price = self.price_guard.get_price(&self.token_mint, "SOL", &self.telegram).await?;
+            }
+            let desired = if index + 1 == self.exit_tranches { self.quantity } else { tranche.min(self.quantity) };
+            let amount = cap_order_size(&self.token_mint, "sell", desired, price, &self.telegram).await?;
+            if amount <= DUST {
+                break;
+            }
+            let Ok(quote) = get_checked_quote(&self.token_mint, "SOL", (amount * unit) as u64, &self.telegram).await else {
+                break;
             };
-            self.wallet.send_transaction(instruction, &self.token_mint, "sell", get_price(&self.token_mint, "SOL", &self.telegram).await?, balance).await?;
+            self.wallet.swap(&quote, &self.token_mint, "sell", price, amount).await?;
+            self.reduce(amount)?;
+            sold += amount;
+        }
+        Ok(sold)
+    }
+
+    fn reduce(&mut self, amount: f64) -> Result<(), Box<dyn std::error::Error>> {
+        self.quantity = (self.quantity - amount).max(0.0);
+        StopLossStore::global()?.adjust_quantity(&self.id, -amount)?;
+        Ok(())
+    }
+
+    /// Distance of the trailing stop below the high. An ATR distance falls back to the
+    /// percentage distance while candles are unavailable or too few.
+    async fn trailing_distance(&mut self) -> f64 {
+        let percent = self.high_water_mark * self.trailing_percent;
+        let TrailingDistance::Atr { multiplier, timeframe } = self.trailing.clone() else {
+            return percent;
+        };
+        if let Some((fetched, value)) = self.atr_cache && fetched.elapsed() < ATR_REFRESH {
+            return multiplier * value;
+        }
+        let candles = fetch_candles(&self.token_mint, &timeframe, ATR_PERIOD + 1, &self.telegram)
+            .await
+            .map_err(|e| e.to_string());
+        match candles.map(|candles| atr(&candles, ATR_PERIOD)) {
+            Ok(Some(value)) => {
+                self.atr_cache = Some((Instant::now(), value));
+                multiplier * value
+            }
+            Ok(None) => {
+                log::warn!("Not enough candles for ATR of {}, trailing by {}", self.token_mint, self.trailing_percent);
+                percent
+            }
+            Err(e) => {
+                log::warn!("ATR for {} unavailable, trailing by {}: {}", self.token_mint, self.trailing_percent, e);
+                percent
+            }
+        }
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        StopLossStore::global()?.save(&StopLossRow {
+            id: self.id.clone(),
+            token_mint: self.token_mint.clone(),
+            entry_price: self.entry_price,
+            quantity: self.quantity,
+            exit_tranches: self.exit_tranches,
+            tranche_secs: self.tranche_interval.as_secs(),
+            fixed_stop_loss: self.fixed_stop_loss,
+            trailing: self.trailing.clone(),
+            trailing_percent: self.trailing_percent,
+            activation_gain: self.activation_gain,
+            high_water_mark: self.high_water_mark,
+            break_even: self.break_even,
+            time_stop: self.time_stop.clone(),
+            opened_at: self.opened_at,
+            take_profit: self.take_profit.as_ref().map(|t| t.state()),
+        })?;
+        Ok(())
+    }
+}
+
+/// Raises `high` to `price` on a new high. Returns true if it moved, so the caller saves it.
+fn raise_high_water_mark(high: &mut f64, price: f64) -> bool {
+    if price > *high {
+        *high = price;
+        return true;
+    }
+    false
+}
+
+/// Price at or below which the fixed stop sells. Once moved to break-even it sits at entry.
+fn fixed_threshold(entry_price: f64, fixed_stop_loss: f64, break_even: bool) -> f64 {
+    if break_even {
+        return entry_price;
+    }
+    entry_price * (1.0 - fixed_stop_loss)
+}
+
+/// Whether the trailing stop is armed: always without an activation gain, otherwise once
+/// the high has gained that much over entry.
+fn trailing_armed(entry_price: f64, high_water_mark: f64, activation_gain: Option<f64>) -> bool {
+    activation_gain
+        .map(|gain| high_water_mark >= entry_price * (1.0 + gain))
+        .unwrap_or(true)
+}
+
+/// Persisted state of an open stop-loss.
+struct StopLossRow {
+    id: String,
+    token_mint: String,
+    entry_price: f64,
+    quantity: f64,
+    exit_tranches: u32,
+    tranche_secs: u64,
+    fixed_stop_loss: f64,
+    trailing: TrailingDistance,
+    trailing_percent: f64,
+    activation_gain: Option<f64>,
+    high_water_mark: f64,
+    break_even: bool,
+    time_stop: TimeStop,
+    opened_at: i64,
+    take_profit: Option<TakeProfitState>,
+}
+
+/// Open stop-losses in SQLite. Rows are removed when the position closes.
+struct StopLossStore {
+    conn: Mutex<Connection>,
+}
+
+impl StopLossStore {
+    fn new() -> rusqlite::Result<Self> {
+        Self::with_connection(Connection::open("trades.db")?)
+    }
+
+    fn with_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS stop_losses (
+                id TEXT PRIMARY KEY,
+                token_mint TEXT NOT NULL,
+                entry_price REAL NOT NULL,
+                fixed_stop_loss REAL NOT NULL,
+                trailing_kind TEXT NOT NULL,
+                trailing_value REAL NOT NULL,
+                trailing_timeframe TEXT,
+                activation_gain REAL,
+                high_water_mark REAL NOT NULL,
+                break_even INTEGER NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+            []
+        )?;
+        // Columns added after the table was first released
+        for (column, definition) in [
+            ("time_stop", "TEXT"),
+            ("opened_at", "INTEGER"),
+            ("quantity", "REAL NOT NULL DEFAULT 0"),
+            ("exit_tranches", "INTEGER NOT NULL DEFAULT 1"),
+            ("tranche_secs", "INTEGER NOT NULL DEFAULT 10"),
+            ("trailing_percent", "REAL"),
+            ("take_profit", "TEXT"),
+        ] {
+            let exists = conn
+                .prepare("SELECT 1 FROM pragma_table_info('stop_losses') WHERE name = ?")?
+                .exists(params![column])?;
+            if !exists {
+                conn.execute(&format!("ALTER TABLE stop_losses ADD COLUMN {} {}", column, definition), [])?;
+            }
         }
+        Ok(StopLossStore { conn: Mutex::new(conn) })
+    }
+
+    fn global() -> rusqlite::Result<&'static StopLossStore> {
+        if let Some(store) = STORE.get() {
+            return Ok(store);
+        }
+        let store = StopLossStore::new()?;
+        Ok(STORE.get_or_init(|| store))
+    }
+
+    /// Inserts or updates a guard. An existing row keeps its quantity, which only
+    /// changes through `adjust_quantity` so concurrent fills are not overwritten.
+    fn save(&self, row: &StopLossRow) -> rusqlite::Result<()> {
+        let (kind, value, timeframe) = match &row.trailing {
+            TrailingDistance::Percent(fraction) => ("percent", *fraction, None),
+            TrailingDistance::Atr { multiplier, timeframe } => ("atr", *multiplier, Some(timeframe.clone())),
+        };
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO stop_losses (id, token_mint, entry_price, quantity, exit_tranches, tranche_secs,
+                fixed_stop_loss, trailing_kind, trailing_value, trailing_timeframe, activation_gain,
+                high_water_mark, break_even, time_stop, opened_at, trailing_percent, take_profit, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT(id) DO UPDATE SET high_water_mark = excluded.high_water_mark,
+                break_even = excluded.break_even, take_profit = excluded.take_profit,
+                updated_at = excluded.updated_at",
+            params![
+                row.id,
+                row.token_mint,
+                row.entry_price,
+                row.quantity,
+                row.exit_tranches,
+                row.tranche_secs as i64,
+                row.fixed_stop_loss,
+                kind,
+                value,
+                timeframe,
+                row.activation_gain,
+                row.high_water_mark,
+                row.break_even,
+                serde_json::to_string(&row.time_stop).unwrap_or_default(),
+                row.opened_at,
+                row.trailing_percent,
+                row.take_profit.as_ref().and_then(|state| serde_json::to_string(state).ok()),
+                chrono::Utc::now().to_rfc3339()
+            ]
+        )?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    fn load(&self) -> rusqlite::Result<Vec<StopLossRow>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, token_mint, entry_price, fixed_stop_loss, trailing_kind, trailing_value,
+                trailing_timeframe, activation_gain, high_water_mark, break_even, time_stop, opened_at,
+                quantity, exit_tranches, tranche_secs, trailing_percent, take_profit
+             FROM stop_losses"
+        )?;
+        let rows = stmt.query_map([], |row| {
+            let kind: String = row.get(4)?;
+            let value: f64 = row.get(5)?;
+            let timeframe: Option<String> = row.get(6)?;
+            let time_stop: Option<String> = row.get(10)?;
+            let opened_at: Option<i64> = row.get(11)?;
+            let tranche_secs: i64 = row.get(14)?;
+            let trailing_percent: Option<f64> = row.get(15)?;
+            let take_profit: Option<String> = row.get(16)?;
+            Ok(StopLossRow {
+                id: row.get(0)?,
+                token_mint: row.get(1)?,
+                entry_price: row.get(2)?,
+                quantity: row.get(12)?,
+                exit_tranches: row.get(13)?,
+                tranche_secs: tranche_secs.max(0) as u64,
+                fixed_stop_loss: row.get(3)?,
+                trailing: match (kind.as_str(), timeframe) {
+                    ("atr", Some(timeframe)) => TrailingDistance::Atr { multiplier: value, timeframe },
+                    _ => TrailingDistance::Percent(value),
+                },
+                // Rows saved before the fallback existed trail ATR guards by the fixed stop distance
+                trailing_percent: trailing_percent
+                    .unwrap_or(if kind == "atr" { row.get(3)? } else { value }),
+                activation_gain: row.get(7)?,
+                high_water_mark: row.get(8)?,
+                break_even: row.get(9)?,
+                time_stop: time_stop.and_then(|json| serde_json::from_str(&json).ok()).unwrap_or_default(),
+                // Rows saved before time-based stops existed start their clock on resume
+                opened_at: opened_at.unwrap_or_else(|| chrono::Utc::now().timestamp()),
+                take_profit: take_profit.and_then(|json| serde_json::from_str(&json).ok()),
+            })
+        })?;
+        rows.collect()
+    }
+
+    fn quantity(&self, id: &str) -> rusqlite::Result<Option<f64>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT quantity FROM stop_losses WHERE id = ?")?;
+        let mut rows = stmt.query(params![id])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(row.get(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn adjust_quantity(&self, id: &str, delta: f64) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "UPDATE stop_losses SET quantity = MAX(quantity + ?, 0), updated_at = ? WHERE id = ?",
+            params![delta, chrono::Utc::now().to_rfc3339(), id]
+        )?;
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> rusqlite::Result<()> {
+        self.conn.lock().unwrap().execute("DELETE FROM stop_losses WHERE id = ?", params![id])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> StopLossStore {
+        StopLossStore::with_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    fn row(id: &str, quantity: f64) -> StopLossRow {
+        StopLossRow {
+            id: id.to_string(),
+            token_mint: "MINT".to_string(),
+            entry_price: 1.0,
+            quantity,
+            exit_tranches: 1,
+            tranche_secs: 10,
+            fixed_stop_loss: 0.1,
+            trailing: TrailingDistance::Percent(0.05),
+            trailing_percent: 0.05,
+            activation_gain: None,
+            high_water_mark: 1.0,
+            break_even: false,
+            time_stop: TimeStop::default(),
+            opened_at: 0,
+            take_profit: None,
+        }
+    }
+
+    #[test]
+    fn max_hold_closes_unless_in_enough_profit() {
+        let stop = TimeStop { max_hold_secs: Some(600), min_profit_to_hold: Some(0.1), decay: None };
+        assert!(stop.triggered(599, -0.2).is_none());
+        assert!(stop.triggered(600, 0.05).is_some());
+        assert!(stop.triggered(600, 0.1).is_none());
+
+        let stop = TimeStop { max_hold_secs: Some(600), min_profit_to_hold: None, decay: None };
+        assert!(stop.triggered(600, 0.5).is_some());
+        assert!(TimeStop::default().triggered(i64::MAX, -1.0).is_none());
+    }
+
+    #[test]
+    fn decaying_target_closes_once_the_gain_reaches_it() {
+        let decay = DecayingTarget::parse("0.5,0.1,100").unwrap();
+        let stop = TimeStop { decay: Some(decay), ..TimeStop::default() };
+        assert!(stop.triggered(0, 0.3).is_none());
+        assert!(stop.triggered(50, 0.3).is_some());
+    }
+
+    #[test]
+    fn parses_decaying_targets() {
+        let decay = DecayingTarget::parse(" 0.5, 0.05 ,1800").unwrap();
+        assert_eq!(decay.start_gain, 0.5);
+        assert_eq!(decay.end_gain, 0.05);
+        assert_eq!(decay.decay_secs, 1800);
+        assert!(DecayingTarget::parse("0.5,0.05").is_err());
+        assert!(DecayingTarget::parse("0.5,x,1800").is_err());
+        assert!(DecayingTarget::parse("0.5,0.05,0").is_err());
+        // The target may only fall over time
+        assert!(DecayingTarget::parse("0.05,0.5,1800").is_err());
+    }
+
+    #[test]
+    fn decaying_target_falls_linearly_and_then_holds() {
+        let decay = DecayingTarget { start_gain: 0.5, end_gain: 0.1, decay_secs: 100 };
+        assert!((decay.target_at(0) - 0.5).abs() < 1e-9);
+        assert!((decay.target_at(50) - 0.3).abs() < 1e-9);
+        assert!((decay.target_at(100) - 0.1).abs() < 1e-9);
+        assert!((decay.target_at(1_000) - 0.1).abs() < 1e-9);
+        assert!((decay.target_at(-10) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn high_water_mark_only_rises() {
+        let mut high = 1.0;
+        assert!(raise_high_water_mark(&mut high, 1.2));
+        assert_eq!(high, 1.2);
+        assert!(!raise_high_water_mark(&mut high, 1.1));
+        assert!(!raise_high_water_mark(&mut high, 1.2));
+        assert_eq!(high, 1.2);
+    }
+
+    #[test]
+    fn trailing_arms_after_the_activation_gain() {
+        assert!(trailing_armed(1.0, 1.0, None));
+        assert!(!trailing_armed(1.0, 1.09, Some(0.1)));
+        assert!(trailing_armed(1.0, 1.1, Some(0.1)));
+        assert!(trailing_armed(1.0, 1.5, Some(0.1)));
+    }
+
+    #[test]
+    fn break_even_lifts_the_fixed_stop_to_entry() {
+        assert!((fixed_threshold(2.0, 0.1, false) - 1.8).abs() < 1e-9);
+        assert_eq!(fixed_threshold(2.0, 0.1, true), 2.0);
+    }
+
+    #[test]
+    fn upsert_keeps_the_stored_quantity() {
+        let store = store();
+        store.save(&row("a", 100.0)).unwrap();
+        store.adjust_quantity("a", -40.0).unwrap();
+
+        let mut updated = row("a", 100.0);
+        updated.high_water_mark = 1.5;
+        updated.break_even = true;
+        store.save(&updated).unwrap();
+
+        assert_eq!(store.quantity("a").unwrap(), Some(60.0));
+        let loaded = store.load().unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(loaded[0].high_water_mark, 1.5);
+        assert!(loaded[0].break_even);
+    }
+
+    #[test]
+    fn adjust_quantity_never_goes_below_zero() {
+        let store = store();
+        store.save(&row("a", 10.0)).unwrap();
+        store.adjust_quantity("a", -25.0).unwrap();
+        assert_eq!(store.quantity("a").unwrap(), Some(0.0));
+        store.adjust_quantity("a", 5.0).unwrap();
+        assert_eq!(store.quantity("a").unwrap(), Some(5.0));
+        // Unknown guards are left alone
+        store.adjust_quantity("b", 5.0).unwrap();
+        assert_eq!(store.quantity("b").unwrap(), None);
+    }
+
+    #[test]
+    fn removed_guards_have_no_position() {
+        let store = store();
+        store.save(&row("a", 10.0)).unwrap();
+        store.remove("a").unwrap();
+        assert_eq!(store.quantity("a").unwrap(), None);
+        assert!(store.load().unwrap().is_empty());
+    }
+}
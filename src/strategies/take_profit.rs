@@ -0,0 +1,241 @@
+use crate::utils::{wallet::Wallet, price_feed::get_checked_quote, price_feed::token_unit};
+use crate::utils::telegram::TelegramBot;
+use serde::{Deserialize, Serialize};
+
+/// Sell `fraction` of the original position once price is `gain` above entry (0.3 = +30%).
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TakeProfitRung {
+    pub gain: f64,
+    pub fraction: f64,
+    pub filled: bool,
+}
+
+/// Parses `gain:fraction` pairs, e.g. `0.3:0.25,1.0:0.25` sells 25% at +30% and 25% at +100%.
+pub fn parse_rungs(value: &str) -> Result<Vec<(f64, f64)>, String> {
+    let rungs = value
+        .split(',')
+        .map(|pair| {
+            let (gain, fraction) = pair.trim().split_once(':').ok_or_else(|| format!("Expected gain:fraction, got {}", pair))?;
+            let gain = gain.parse::<f64>().map_err(|_| format!("Invalid gain {}", gain))?;
+            let fraction = fraction.parse::<f64>().map_err(|_| format!("Invalid fraction {}", fraction))?;
+            if gain <= 0.0 || fraction <= 0.0 || fraction > 1.0 {
+                return Err(format!("Rung {} needs a positive gain and a fraction in (0, 1]", pair));
+            }
+            Ok((gain, fraction))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    if rungs.iter().map(|(_, f)| f).sum::<f64>() > 1.0 + 1e-9 {
+        return Err("Take-profit fractions add up to more than the whole position".to_string());
+    }
+    Ok(rungs)
+}
+
+/// The persisted part of a ladder, saved with its stop-loss so filled rungs survive a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TakeProfitState {
+    pub entry_price: f64,
+    /// Position size when the ladder was attached; rung fractions are of this amount.
+    pub quantity: f64,
+    pub rungs: Vec<TakeProfitRung>,
+    /// Move the stop to the entry price once the first rung fills.
+    pub break_even_after_first: bool,
+}
+
+impl TakeProfitState {
+    /// An unfilled ladder with its rungs sorted by gain.
+    pub fn new(entry_price: f64, quantity: f64, rungs: &[(f64, f64)]) -> Self {
+        let mut rungs: Vec<TakeProfitRung> = rungs
+            .iter()
+            .map(|&(gain, fraction)| TakeProfitRung { gain, fraction, filled: false })
+            .collect();
+        rungs.sort_by(|a, b| a.gain.partial_cmp(&b.gain).unwrap());
+        TakeProfitState { entry_price, quantity, rungs, break_even_after_first: false }
+    }
+
+    /// Unfilled rungs `price` has reached, lowest first.
+    pub fn due(&self, price: f64) -> Vec<usize> {
+        (0..self.rungs.len())
+            .filter(|&index| {
+                let rung = self.rungs[index];
+                !rung.filled && price >= self.entry_price * (1.0 + rung.gain)
+            })
+            .collect()
+    }
+
+    /// Tokens rung `index` sells, capped at the `remaining` position.
+    pub fn amount(&self, index: usize, remaining: f64) -> f64 {
+        (self.quantity * self.rungs[index].fraction).min(remaining).max(0.0)
+    }
+
+    /// True once filled rungs have sold the whole position.
+    pub fn is_closed(&self) -> bool {
+        self.rungs.iter().filter(|r| r.filled).map(|r| r.fraction).sum::<f64>() >= 1.0 - 1e-9
+    }
+
+    /// Whether the stop should sit at break-even: enabled and the first rung has filled.
+    pub fn break_even_due(&self) -> bool {
+        self.break_even_after_first && self.rungs.first().map(|r| r.filled).unwrap_or(false)
+    }
+}
+
+/// Scales out of a position in rungs as price rises. Attach it to a `StopLoss`,
+/// which checks it after its fixed stop.
+pub struct TakeProfit {
+    token_mint: String,
+    ladder: TakeProfitState,
+    wallet: Wallet,
+    telegram: TelegramBot,
+}
+
+impl TakeProfit {
+    pub fn new(token_mint: String, entry_price: f64, quantity: f64, rungs: &[(f64, f64)], wallet: Wallet, telegram: TelegramBot) -> Self {
+        TakeProfit::restore(token_mint, TakeProfitState::new(entry_price, quantity, rungs), wallet, telegram)
+    }
+
+    /// Rebuilds a ladder saved with `state`, keeping the rungs already filled.
+    pub fn restore(token_mint: String, state: TakeProfitState, wallet: Wallet, telegram: TelegramBot) -> Self {
+        TakeProfit { token_mint, ladder: state, wallet, telegram }
+    }
+
+    pub fn state(&self) -> TakeProfitState {
+        self.ladder.clone()
+    }
+
+    pub fn with_break_even(mut self, enabled: bool) -> Self {
+        self.ladder.break_even_after_first = enabled;
+        self
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.ladder.is_closed()
+    }
+
+    pub fn break_even_due(&self) -> bool {
+        self.ladder.break_even_due()
+    }
+
+    /// Sells every rung `price` has reached out of the `remaining` position and returns the
+    /// tokens sold. A rung whose sell fails is left unfilled and retried on the next check.
+    pub async fn check(&mut self, price: f64, remaining: f64) -> f64 {
+        let mut sold = 0.0;
+        let due = self.ladder.due(price);
+        if due.is_empty() {
+            return sold;
+        }
+        let unit = match token_unit(&self.token_mint).await.map_err(|e| e.to_string()) {
+            Ok(unit) => unit,
+            Err(e) => {
+                log::warn!("Take-profit for {} skipped, decimals unavailable: {}", self.token_mint, e);
+                return sold;
+            }
+        };
+        for index in due {
+            // Tokens sold by hand or by an earlier stop leave less than the original fractions
+            let amount = self.ladder.amount(index, remaining - sold);
+            if amount <= 0.0 {
+                break;
+            }
+            let rung = self.ladder.rungs[index];
+            let Ok(quote) = get_checked_quote(&self.token_mint, "SOL", (amount * unit) as u64, &self.telegram).await else {
+                continue;
+            };
+            let result = self.wallet.swap(&quote, &self.token_mint, "sell", price, amount).await;
+            if let Err(e) = result.map_err(|e| e.to_string()) {
+                log::warn!("Take-profit {} for {} failed: {}", index + 1, self.token_mint, e);
+                continue;
+            }
+            self.ladder.rungs[index].filled = true;
+            sold += amount;
+            // The sale is recorded either way, so a failed notification must not lose it
+            let message = format!(
+                "Take-profit {} of {} for {}: sold {:.0}% of the position at {} (+{:.0}%)",
+                index + 1, self.ladder.rungs.len(), self.token_mint, rung.fraction * 100.0, price, rung.gain * 100.0
+            );
+            if let Err(e) = self.telegram.send_message(&message).await.map_err(|e| e.to_string()) {
+                log::warn!("Failed to report take-profit for {}: {}", self.token_mint, e);
+            }
+        }
+        sold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_ladder() {
+        assert_eq!(parse_rungs("0.3:0.25, 1.0:0.5").unwrap(), vec![(0.3, 0.25), (1.0, 0.5)]);
+        assert_eq!(parse_rungs("2:1").unwrap(), vec![(2.0, 1.0)]);
+    }
+
+    #[test]
+    fn rejects_invalid_rungs() {
+        assert!(parse_rungs("0.3").is_err());
+        assert!(parse_rungs("x:0.5").is_err());
+        assert!(parse_rungs("0.3:y").is_err());
+        assert!(parse_rungs("0:0.5").is_err());
+        assert!(parse_rungs("-0.1:0.5").is_err());
+        assert!(parse_rungs("0.3:0").is_err());
+        assert!(parse_rungs("0.3:1.5").is_err());
+    }
+
+    #[test]
+    fn rejects_fractions_over_the_whole_position() {
+        assert!(parse_rungs("0.3:0.6,1.0:0.5").is_err());
+        assert!(parse_rungs("0.3:0.5,1.0:0.5").is_ok());
+    }
+
+    #[test]
+    fn sorts_rungs_by_gain() {
+        let ladder = TakeProfitState::new(1.0, 100.0, &parse_rungs("1.0:0.25,0.3:0.5,0.5:0.25").unwrap());
+        let gains: Vec<f64> = ladder.rungs.iter().map(|r| r.gain).collect();
+        assert_eq!(gains, vec![0.3, 0.5, 1.0]);
+        assert_eq!(ladder.rungs[0].fraction, 0.5);
+    }
+
+    #[test]
+    fn fills_every_rung_price_has_reached() {
+        let mut ladder = TakeProfitState::new(2.0, 100.0, &[(0.5, 0.5), (0.25, 0.25), (1.0, 0.25)]);
+        assert!(ladder.due(2.4).is_empty());
+        assert_eq!(ladder.due(3.0), vec![0, 1]);
+        ladder.rungs[0].filled = true;
+        assert_eq!(ladder.due(4.0), vec![1, 2]);
+    }
+
+    #[test]
+    fn caps_sells_at_the_remaining_position() {
+        let ladder = TakeProfitState::new(1.0, 100.0, &[(0.3, 0.5), (1.0, 0.5)]);
+        assert_eq!(ladder.amount(0, 100.0), 50.0);
+        assert_eq!(ladder.amount(1, 20.0), 20.0);
+        assert_eq!(ladder.amount(1, -1.0), 0.0);
+    }
+
+    #[test]
+    fn closes_once_filled_fractions_cover_the_position() {
+        let mut ladder = TakeProfitState::new(1.0, 100.0, &[(0.3, 0.4), (0.6, 0.3), (1.0, 0.3)]);
+        assert!(!ladder.is_closed());
+        ladder.rungs[0].filled = true;
+        ladder.rungs[1].filled = true;
+        assert!(!ladder.is_closed());
+        ladder.rungs[2].filled = true;
+        assert!(ladder.is_closed());
+
+        // A ladder that leaves part of the position to the stop never closes it
+        let mut partial = TakeProfitState::new(1.0, 100.0, &[(0.3, 0.5)]);
+        partial.rungs[0].filled = true;
+        assert!(!partial.is_closed());
+    }
+
+    #[test]
+    fn moves_to_break_even_after_the_first_rung_when_enabled() {
+        let mut ladder = TakeProfitState::new(1.0, 100.0, &[(1.0, 0.5), (0.3, 0.5)]);
+        ladder.rungs[1].filled = true;
+        ladder.break_even_after_first = true;
+        assert!(!ladder.break_even_due());
+        ladder.rungs[0].filled = true;
+        assert!(ladder.break_even_due());
+        ladder.break_even_after_first = false;
+        assert!(!ladder.break_even_due());
+    }
+}
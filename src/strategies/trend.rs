@@ -1,120 +1,241 @@
-use crate::utils::{wallet::Wallet, price_feed::get_price};
+use crate::utils::{wallet::Wallet, price_guard::PriceGuard, price_feed::{get_checked_quote, token_unit}, liquidity::cap_order_size};
+use crate::utils::candles::parse_timeframe;
 use crate::strategies::stop_loss::StopLoss;
+use crate::strategies::trend_rules::{Market, Rule};
 use crate::utils::telegram::TelegramBot;
-use solana_sdk::instruction::Instruction;
-use ta::indicators::RelativeStrengthIndex;
-use tokio::time::{sleep, Duration};
+use crate::utils::price_feed::sol_to_lamports;
+use std::env;
+use crate::strategies::handle::{sleep_or_stop, StrategyHandle};
+use tokio::sync::watch;
+use tokio::time::Duration;
 use reqwest::Client;
 
+const DEFAULT_ENTRY: &str = "ema(9) crosses_above ema(21) and macd_hist(12,26,9) > 0 and volume > volume_sma(20)";
+const DEFAULT_EXIT: &str = "ema(9) crosses_below ema(21) or price < bb_lower(20,2)";
+/// Seconds to wait after a candle closes before fetching it, so the API has published it.
+const CLOSE_DELAY_SECS: u64 = 5;
 
+/// Buys when the entry rule holds on a closed candle and sells the position when
+/// the exit rule does. Holds at most one position, guarded by a stop-loss.
 pub struct Trend {
     wallet: Wallet,
     telegram: TelegramBot,
+    price_guard: PriceGuard,
     token_mint: String,
     period: usize,
     rsi_threshold: f64,
+    /// Also require a "buy" from the GMGN signal API before entering.
     use_ai: bool,
+    timeframe: String,
+    /// Closed candles fetched per timeframe and evaluation; long EMAs need more than their period to settle.
+    lookback: usize,
+    entry: Rule,
+    exit: Rule,
+    buy_sol: f64,
 }
 
 impl Trend {
-    pub fn new(wallet: Wallet, telegram: TelegramBot, token_mint: String, period: usize) -> Self {
+    pub fn new(wallet: Wallet, telegram: TelegramBot, price_guard: PriceGuard, token_mint: String, period: usize) -> Self {
+        let rule = |name: &str, default: &str| {
+            let source = env::var(name).unwrap_or_else(|_| default.to_string());
+            Rule::parse(&source).unwrap_or_else(|e| {
+                log::warn!("Ignoring {}: {}", name, e);
+                Rule::parse(default).unwrap()
+            })
+        };
         Trend {
             wallet,
             telegram,
+            price_guard,
             token_mint,
             period,
             rsi_threshold: 30.0,
-            use_ai: true,
+            use_ai: env::var("TREND_USE_AI").map(|v| v == "true").unwrap_or(false),
+            timeframe: env::var("TREND_TIMEFRAME").unwrap_or_else(|_| "15m".to_string()),
+            lookback: env::var("TREND_LOOKBACK").ok().and_then(|v| v.parse().ok()).unwrap_or(200),
+            entry: rule("TREND_ENTRY", DEFAULT_ENTRY),
+            exit: rule("TREND_EXIT", DEFAULT_EXIT),
+            buy_sol: env::var("TREND_BUY_SOL").ok().and_then(|v| v.parse().ok()).unwrap_or(0.1),
         }
     }
 
-    pub fn set_rsi_threshold(&mut self, threshold: f64) {
+    /// Replaces the entry rule with `rsi(period) < threshold`.
+    pub async fn set_rsi_threshold(&mut self, threshold: f64) -> Result<(), Box<dyn std::error::Error>> {
         self.rsi_threshold = threshold;
+        self.entry = Rule::parse(&format!("rsi({}) < {}", self.period, threshold))?;
         self.telegram
-            .send_message(&format!("Set RSI threshold for {} to {}", self.token_mint, threshold))
-            .await.unwrap();
+            .send_message(&format!("Set RSI threshold for {} to {}, entry rule is now {}", self.token_mint, threshold, self.entry))
+            .await?;
+        Ok(())
     }
 
-    pub fn set_use_ai(&mut self, use_ai: bool) {
+    pub async fn set_use_ai(&mut self, use_ai: bool) -> Result<(), Box<dyn std::error::Error>> {
         self.use_ai = use_ai;
         self.telegram
             .send_message(&format!("Set AI usage for {} to {}", self.token_mint, use_ai))
-            .await.unwrap();
+            .await?;
+        Ok(())
     }
 
+    /// Sets `entry`, `exit` (rule expressions), `timeframe`, `lookback` or `buy_sol`.
+    pub async fn set_param(&mut self, key: &str, value: &str) -> Result<(), Box<dyn std::error::Error>> {
+        match key {
+            "entry" => self.entry = Rule::parse(value)?,
+            "exit" => self.exit = Rule::parse(value)?,
+            "timeframe" => {
+                parse_timeframe(value)?;
+                self.timeframe = value.to_string();
+            }
+            "lookback" => self.lookback = value.parse().map_err(|_| format!("Invalid lookback {}", value))?,
+            "buy_sol" => {
+                let amount: f64 = value.parse().map_err(|_| format!("Invalid SOL amount {}", value))?;
+                if amount <= 0.0 {
+                    return Err("buy_sol must be positive".into());
+                }
+                self.buy_sol = amount;
+            }
+            _ => return Err(format!("Unknown trend parameter {}", key).into()),
+        }
+        self.telegram
+            .send_message(&format!("Set trend {} for {} to {}", key, self.token_mint, value))
+            .await?;
+        Ok(())
+    }
 
-    pub async fn start(&self) -> Result<(), Box<dyn std::error::Error>> {
-        let mut prices = vec![];
-        for _ in 0..self.period {
-            prices.push(get_price(&self.token_mint, "SOL", &self.telegram).await?);
-            sleep(Duration::from_secs(1)).await;
+    /// Runs the strategy in its own task until `StrategyHandle::stop`.
+    pub fn spawn(&self) -> StrategyHandle {
+        let trend = self.clone();
+        StrategyHandle::spawn(move |stopped| async move {
+            if let Err(e) = trend.start(stopped).await {
+                log::error!("Trend for {} stopped: {}", trend.token_mint, e);
+            }
+        })
+    }
+
+    /// Runs until stopped, evaluating the rules each time a candle closes.
+    async fn start(&self, mut stopped: watch::Receiver<bool>) -> Result<(), Box<dyn std::error::Error>> {
+        let (_, _, candle_secs) = parse_timeframe(&self.timeframe)?;
+        self.telegram
+            .send_message(&format!(
+                "Trend for {} on {} candles. Entry: {}. Exit: {}",
+                self.token_mint, self.timeframe, self.entry, self.exit
+            ))
+            .await?;
+        loop {
+            let now = chrono::Utc::now().timestamp() as u64;
+            let next_close = (now / candle_secs + 1) * candle_secs;
+            if sleep_or_stop(Duration::from_secs(next_close - now + CLOSE_DELAY_SECS), &mut stopped).await {
+                return Ok(());
+            }
+            if let Err(e) = self.on_candle_close().await {
+                log::warn!("Trend for {} skipped a candle: {}", self.token_mint, e);
+            }
         }
+    }
 
-        let should_buy = if self.use_ai {
-            match get_ai_signal(&self.token_mint).await {
-                Ok(signal) => signal == "buy",
-                Err(_) => {
-                    self.telegram
-                        .send_message(&format!("AI signal unavailable for {}, falling back to RSI", self.token_mint))
-                        .await?;
-                    let mut rsi = RelativeStrengthIndex::new(self.period).unwrap();
-                    for &price in &prices {
-                        rsi.next(price);
-                    }
-                    rsi.next(prices.last().unwrap().clone()) < self.rsi_threshold
+    async fn on_candle_close(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let market = Market::fetch(&self.token_mint, &self.timeframe, &[&self.entry, &self.exit], self.lookback, None, &self.telegram).await?;
+        let position = StopLoss::position(&self.stop_id())?;
+        match position {
+            None => {
+                if self.entry.evaluate(&market) && self.ai_confirms().await? {
+                    self.buy(&market).await?;
                 }
             }
-        } else {
-            let mut rsi = RelativeStrengthIndex::new(self.period).unwrap();
-            for &price in &prices {
-                rsi.next(price);
+            Some(quantity) => {
+                if self.exit.evaluate(&market) {
+                    self.sell(quantity).await?;
+                }
             }
-            rsi.next(prices.last().unwrap().clone()) < self.rsi_threshold
-        };
+        }
+        Ok(())
+    }
 
-        if should_buy {
-            let current_price = get_price(&self.token_mint, "SOL", &self.telegram).await?;
-            let instruction = Instruction {
-                program_id: solana_sdk::pubkey::Pubkey::from_str("RAY...").unwrap(),
-                accounts: vec![],
-                data: vec![],
-            };
-            self.wallet.send_transaction(instruction, &self.token_mint, "buy", current_price, 1000.0).await?;
-            self.telegram
-                .send_message(&format!(
-                    "Bought {} at {} (AI: {}, RSI: {})",
-                    self.token_mint, current_price, self.use_ai, self.rsi_threshold
-                ))
-                .await?;
-
-            let stop_loss = StopLoss::new(
-                self.token_mint.clone(),
-                current_price,
-                0.05,
-                0.05,
-                self.wallet.clone(),
-                self.telegram.clone(),
-            );
-            tokio::spawn(async move {
-                loop {
-                    if stop_loss.check().await.unwrap() {
-                        break;
-                    }
-                    sleep(Duration::from_secs(60)).await;
-                }
-            });
+    async fn ai_confirms(&self) -> Result<bool, Box<dyn std::error::Error>> {
+        if !self.use_ai {
+            return Ok(true);
         }
+        match get_ai_signal(&self.token_mint).await.map_err(|e| e.to_string()) {
+            Ok(signal) => Ok(signal == "buy"),
+            Err(_) => {
+                self.telegram
+                    .send_message(&format!("AI signal unavailable for {}, using the entry rule alone", self.token_mint))
+                    .await?;
+                Ok(true)
+            }
+        }
+    }
+
+    async fn buy(&self, market: &Market) -> Result<(), Box<dyn std::error::Error>> {
+        let current_price = self.price_guard.get_price(&self.token_mint, "SOL", &self.telegram).await?;
+        let amount = cap_order_size(&self.token_mint, "buy", self.buy_sol / current_price, current_price, &self.telegram).await?;
+        let quote = get_checked_quote("SOL", &self.token_mint, sol_to_lamports(current_price * amount), &self.telegram).await?;
+        self.wallet.swap(&quote, &self.token_mint, "buy", current_price, amount).await?;
+        self.telegram
+            .send_message(&format!(
+                "Bought {} of {} at {} on {} close {} (entry: {}, AI: {})",
+                amount, self.token_mint, current_price, self.timeframe,
+                market.price().unwrap_or_default(), self.entry, self.use_ai
+            ))
+            .await?;
+
+        let stop_loss = StopLoss::new(
+            self.token_mint.clone(),
+            current_price,
+            amount,
+            0.05,
+            0.05,
+            self.wallet.clone(),
+            self.telegram.clone(),
+            self.price_guard.clone()
+        )
+        .with_env("TREND")
+        .with_id(self.stop_id());
+        stop_loss.spawn();
         Ok(())
     }
 
+    /// Sells the trend position; whatever route impact leaves unsold is retried on the next close.
+    async fn sell(&self, quantity: f64) -> Result<(), Box<dyn std::error::Error>> {
+        let current_price = self.price_guard.get_price(&self.token_mint, "SOL", &self.telegram).await?;
+        let amount = cap_order_size(&self.token_mint, "sell", quantity, current_price, &self.telegram).await?;
+        let unit = token_unit(&self.token_mint).await?;
+        let quote = get_checked_quote(&self.token_mint, "SOL", (amount * unit) as u64, &self.telegram).await?;
+        self.wallet.swap(&quote, &self.token_mint, "sell", current_price, amount).await?;
+        if amount >= quantity {
+            StopLoss::release(&self.stop_id())?;
+        } else {
+            StopLoss::adjust_position(&self.stop_id(), -amount)?;
+        }
+        self.telegram
+            .send_message(&format!(
+                "Sold {} of {} at {} on exit rule {}{}",
+                amount, self.token_mint, current_price, self.exit,
+                if amount < quantity { format!(", {} left for the next candle", quantity - amount) } else { String::new() }
+            ))
+            .await?;
+        Ok(())
+    }
+
+    /// Id of the stop-loss guarding the trend position.
+    fn stop_id(&self) -> String {
+        format!("trend:{}", self.token_mint)
+    }
+
     pub fn clone(&self) -> Self {
         Trend {
             wallet: self.wallet.clone(),
             telegram: TelegramBot::new(),
+            price_guard: self.price_guard.clone(),
             token_mint: self.token_mint.clone(),
             period: self.period,
             rsi_threshold: self.rsi_threshold,
             use_ai: self.use_ai,
+            timeframe: self.timeframe.clone(),
+            lookback: self.lookback,
+            entry: self.entry.clone(),
+            exit: self.exit.clone(),
+            buy_sol: self.buy_sol,
         }
     }
 }
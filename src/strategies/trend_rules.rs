@@ -0,0 +1,771 @@
+use crate::utils::candles::{fetch_candles, parse_timeframe, Candle};
+use crate::utils::telegram::TelegramBot;
+use std::collections::HashMap;
+use std::fmt;
+use ta::indicators::{
+    AverageTrueRange, BollingerBands, ExponentialMovingAverage, MovingAverageConvergenceDivergence,
+    RelativeStrengthIndex, SimpleMovingAverage,
+};
+use ta::{ DataItem, Next };
+
+/// A parse failure at a 1-based column of the parsed text.
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub column: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(column: usize, message: String) -> Self {
+        ParseError { column, message }
+    }
+}
+
+/// A value computed for every candle. Periods are in candles.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Indicator {
+    /// Candle close, or the live price where the caller provides one. Crossovers
+    /// always use closes.
+    Price,
+    Volume,
+    Number(f64),
+    Rsi(usize),
+    Ema(usize),
+    Sma(usize),
+    /// MACD histogram for fast, slow and signal periods.
+    MacdHist(usize, usize, usize),
+    /// Bollinger bands for a period and a number of standard deviations.
+    BollingerUpper(usize, f64),
+    BollingerLower(usize, f64),
+    Atr(usize),
+    /// ATR as a fraction of the close, so filters work at any price scale.
+    AtrPercent(usize),
+    VolumeSma(usize),
+}
+
+impl Indicator {
+    fn parse(name: &str, args: &[f64], column: usize) -> Result<Self, ParseError> {
+        let error = |message: String| ParseError::new(column, message);
+        let period = |index: usize| -> Result<usize, ParseError> {
+            match args.get(index) {
+                Some(&value) if value >= 1.0 && value.fract() == 0.0 => Ok(value as usize),
+                Some(value) => Err(error(format!("{} needs whole positive periods, got {}", name, value))),
+                None => Err(error(format!("{} is missing argument {}", name, index + 1))),
+            }
+        };
+        let arity = |count: usize| -> Result<(), ParseError> {
+            if args.len() == count {
+                Ok(())
+            } else {
+                Err(error(format!("{} takes {} arguments, got {}", name, count, args.len())))
+            }
+        };
+        let indicator = match name {
+            "price" | "close" => { arity(0)?; Indicator::Price }
+            "volume" => { arity(0)?; Indicator::Volume }
+            "rsi" => { arity(1)?; Indicator::Rsi(period(0)?) }
+            "ema" => { arity(1)?; Indicator::Ema(period(0)?) }
+            "sma" => { arity(1)?; Indicator::Sma(period(0)?) }
+            "macd_hist" => {
+                arity(3)?;
+                let (fast, slow, signal) = (period(0)?, period(1)?, period(2)?);
+                if fast >= slow {
+                    return Err(error("macd_hist needs a fast period below the slow one".to_string()));
+                }
+                Indicator::MacdHist(fast, slow, signal)
+            }
+            "bb_upper" | "bb_lower" => {
+                arity(2)?;
+                let deviations = args[1];
+                if deviations <= 0.0 {
+                    return Err(error(format!("{} needs a positive deviation multiplier", name)));
+                }
+                if name == "bb_upper" {
+                    Indicator::BollingerUpper(period(0)?, deviations)
+                } else {
+                    Indicator::BollingerLower(period(0)?, deviations)
+                }
+            }
+            "atr" => { arity(1)?; Indicator::Atr(period(0)?) }
+            "atr_pct" => { arity(1)?; Indicator::AtrPercent(period(0)?) }
+            "volume_sma" => { arity(1)?; Indicator::VolumeSma(period(0)?) }
+            _ => return Err(error(format!("Unknown indicator {}", name))),
+        };
+        Ok(indicator)
+    }
+
+    /// Candles needed before the value is meaningful.
+    pub fn warmup(&self) -> usize {
+        match self {
+            Indicator::Price | Indicator::Volume | Indicator::Number(_) => 1,
+            Indicator::Rsi(period) | Indicator::Atr(period) | Indicator::AtrPercent(period) => period + 1,
+            Indicator::Ema(period) | Indicator::Sma(period) | Indicator::VolumeSma(period) => *period,
+            Indicator::BollingerUpper(period, _) | Indicator::BollingerLower(period, _) => *period,
+            Indicator::MacdHist(_, slow, signal) => slow + signal,
+        }
+    }
+
+    /// The indicator's value at every candle, oldest first, or `None` without enough history.
+    pub fn series(&self, candles: &[Candle]) -> Option<Vec<f64>> {
+        if candles.len() < self.warmup() {
+            return None;
+        }
+        let closes = candles.iter().map(|c| c.close);
+        let series = match self {
+            Indicator::Price => closes.collect(),
+            Indicator::Volume => candles.iter().map(|c| c.volume).collect(),
+            Indicator::Number(value) => vec![*value; candles.len()],
+            Indicator::Rsi(period) => {
+                let mut indicator = RelativeStrengthIndex::new(*period).ok()?;
+                closes.map(|close| indicator.next(close)).collect()
+            }
+            Indicator::Ema(period) => {
+                let mut indicator = ExponentialMovingAverage::new(*period).ok()?;
+                closes.map(|close| indicator.next(close)).collect()
+            }
+            Indicator::Sma(period) => {
+                let mut indicator = SimpleMovingAverage::new(*period).ok()?;
+                closes.map(|close| indicator.next(close)).collect()
+            }
+            Indicator::VolumeSma(period) => {
+                let mut indicator = SimpleMovingAverage::new(*period).ok()?;
+                candles.iter().map(|c| indicator.next(c.volume)).collect()
+            }
+            Indicator::MacdHist(fast, slow, signal) => {
+                let mut indicator = MovingAverageConvergenceDivergence::new(*fast, *slow, *signal).ok()?;
+                closes.map(|close| indicator.next(close).histogram).collect()
+            }
+            Indicator::BollingerUpper(period, deviations) => {
+                let mut indicator = BollingerBands::new(*period, *deviations).ok()?;
+                closes.map(|close| indicator.next(close).upper).collect()
+            }
+            Indicator::BollingerLower(period, deviations) => {
+                let mut indicator = BollingerBands::new(*period, *deviations).ok()?;
+                closes.map(|close| indicator.next(close).lower).collect()
+            }
+            Indicator::Atr(period) | Indicator::AtrPercent(period) => {
+                let mut indicator = AverageTrueRange::new(*period).ok()?;
+                let mut values = Vec::with_capacity(candles.len());
+                for candle in candles {
+                    let item = DataItem::builder()
+                        .open(candle.open)
+                        .high(candle.high)
+                        .low(candle.low)
+                        .close(candle.close)
+                        .volume(candle.volume)
+                        .build()
+                        .ok()?;
+                    let value = indicator.next(&item);
+                    values.push(if matches!(self, Indicator::AtrPercent(_)) { value / candle.close } else { value });
+                }
+                values
+            }
+        };
+        Some(series)
+    }
+}
+
+impl fmt::Display for Indicator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Indicator::Price => write!(f, "price"),
+            Indicator::Volume => write!(f, "volume"),
+            Indicator::Number(value) => write!(f, "{}", value),
+            Indicator::Rsi(period) => write!(f, "rsi({})", period),
+            Indicator::Ema(period) => write!(f, "ema({})", period),
+            Indicator::Sma(period) => write!(f, "sma({})", period),
+            Indicator::MacdHist(fast, slow, signal) => write!(f, "macd_hist({},{},{})", fast, slow, signal),
+            Indicator::BollingerUpper(period, deviations) => write!(f, "bb_upper({},{})", period, deviations),
+            Indicator::BollingerLower(period, deviations) => write!(f, "bb_lower({},{})", period, deviations),
+            Indicator::Atr(period) => write!(f, "atr({})", period),
+            Indicator::AtrPercent(period) => write!(f, "atr_pct({})", period),
+            Indicator::VolumeSma(period) => write!(f, "volume_sma({})", period),
+        }
+    }
+}
+
+/// An indicator on an explicit timeframe, e.g. `ema(50,1h)`, or on the caller's
+/// default timeframe when `timeframe` is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operand {
+    pub indicator: Indicator,
+    pub timeframe: Option<String>,
+}
+
+impl Operand {
+    /// Values on the previous and the last closed candle. With `live`, `price` on the
+    /// default timeframe is the live price instead of the last close; crossovers pass
+    /// false so both sides compare the same two candles.
+    fn values(&self, market: &Market, live: bool) -> Option<(Option<f64>, f64)> {
+        if let Indicator::Number(value) = self.indicator {
+            return Some((Some(value), value));
+        }
+        let series = self.indicator.series(market.candles(self.timeframe.as_deref()))?;
+        let previous = series.len().checked_sub(2).map(|index| series[index]);
+        let mut last = *series.last()?;
+        if live && self.indicator == Indicator::Price && self.timeframe.is_none() && let Some(price) = market.price {
+            last = price;
+        }
+        Some((previous, last))
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let indicator = self.indicator.to_string();
+        match &self.timeframe {
+            None => write!(f, "{}", indicator),
+            Some(timeframe) => match indicator.strip_suffix(')') {
+                Some(call) => write!(f, "{},{})", call, timeframe),
+                None => write!(f, "{}({})", indicator, timeframe),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Above,
+    Below,
+    AtLeast,
+    AtMost,
+    /// Left was at or below right on the previous candle and is above it on the last
+    /// closed one.
+    CrossesAbove,
+    CrossesBelow,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Comparison::Above => ">",
+            Comparison::Below => "<",
+            Comparison::AtLeast => ">=",
+            Comparison::AtMost => "<=",
+            Comparison::CrossesAbove => "crosses_above",
+            Comparison::CrossesBelow => "crosses_below",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+/// Indicator conditions combined with `and`/`or`; `and` binds tighter and
+/// parentheses group, e.g.
+/// `ema(9) crosses_above ema(21) and (macd_hist(12,26,9) > 0 or price > bb_upper(20,2,1h))`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rule {
+    All(Vec<Rule>),
+    Any(Vec<Rule>),
+    Condition { left: Operand, comparison: Comparison, right: Operand },
+}
+
+impl Rule {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let parse = || -> Result<Rule, ParseError> {
+            let mut parser = Parser::new(tokenize(source)?);
+            let rule = parser.rule()?;
+            if let Some(token) = parser.peek() {
+                return Err(ParseError::new(token.column, format!("Unexpected {}", token.kind)));
+            }
+            Ok(rule)
+        };
+        parse().map_err(|e| format!("{} at column {}", e.message, e.column))
+    }
+
+    /// Candles needed per timeframe, including one extra for crossovers. Operands
+    /// without a timeframe count towards `default_timeframe`.
+    pub fn required_candles(&self, default_timeframe: &str, needs: &mut HashMap<String, usize>) {
+        match self {
+            Rule::All(rules) | Rule::Any(rules) => {
+                for rule in rules {
+                    rule.required_candles(default_timeframe, needs);
+                }
+            }
+            Rule::Condition { left, right, .. } => {
+                for operand in [left, right] {
+                    if let Indicator::Number(_) = operand.indicator {
+                        continue;
+                    }
+                    let timeframe = operand.timeframe.clone().unwrap_or_else(|| default_timeframe.to_string());
+                    let need = needs.entry(timeframe).or_insert(0);
+                    *need = (*need).max(operand.indicator.warmup() + 1);
+                }
+            }
+        }
+    }
+
+    /// Whether the rule holds on the last candle. Conditions without enough
+    /// history are false.
+    pub fn evaluate(&self, market: &Market) -> bool {
+        match self {
+            Rule::All(rules) => rules.iter().all(|r| r.evaluate(market)),
+            Rule::Any(rules) => rules.iter().any(|r| r.evaluate(market)),
+            Rule::Condition { left, comparison, right } => {
+                let live = !matches!(comparison, Comparison::CrossesAbove | Comparison::CrossesBelow);
+                let (Some((left_previous, left)), Some((right_previous, right))) =
+                    (left.values(market, live), right.values(market, live))
+                else {
+                    return false;
+                };
+                match comparison {
+                    Comparison::Above => left > right,
+                    Comparison::Below => left < right,
+                    Comparison::AtLeast => left >= right,
+                    Comparison::AtMost => left <= right,
+                    Comparison::CrossesAbove => match (left_previous, right_previous) {
+                        (Some(lp), Some(rp)) => lp <= rp && left > right,
+                        _ => false,
+                    },
+                    Comparison::CrossesBelow => match (left_previous, right_previous) {
+                        (Some(lp), Some(rp)) => lp >= rp && left < right,
+                        _ => false,
+                    },
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |f: &mut fmt::Formatter, rules: &[Rule], separator: &str| -> fmt::Result {
+            for (index, rule) in rules.iter().enumerate() {
+                if index > 0 {
+                    write!(f, " {} ", separator)?;
+                }
+                match rule {
+                    Rule::Condition { .. } => write!(f, "{}", rule)?,
+                    _ => write!(f, "({})", rule)?,
+                }
+            }
+            Ok(())
+        };
+        match self {
+            Rule::All(rules) => join(f, rules, "and"),
+            Rule::Any(rules) => join(f, rules, "or"),
+            Rule::Condition { left, comparison, right } => write!(f, "{} {} {}", left, comparison, right),
+        }
+    }
+}
+
+/// Closed candles for every timeframe a set of rules uses, plus an optional live
+/// price, fetched once per evaluation.
+pub struct Market {
+    default_timeframe: String,
+    candles: HashMap<String, Vec<Candle>>,
+    /// Used for `price` on the default timeframe instead of the last close.
+    price: Option<f64>,
+}
+
+impl Market {
+    /// Fetches at least `lookback` candles per timeframe, more where an indicator needs them.
+    pub async fn fetch(
+        token_mint: &str,
+        default_timeframe: &str,
+        rules: &[&Rule],
+        lookback: usize,
+        price: Option<f64>,
+        telegram: &TelegramBot
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut needs = HashMap::new();
+        needs.insert(default_timeframe.to_string(), 1);
+        for rule in rules {
+            rule.required_candles(default_timeframe, &mut needs);
+        }
+        let mut candles = HashMap::new();
+        for (timeframe, need) in needs {
+            let series = fetch_candles(token_mint, &timeframe, need.max(lookback), telegram).await?;
+            candles.insert(timeframe, series);
+        }
+        Ok(Market { default_timeframe: default_timeframe.to_string(), candles, price })
+    }
+
+    pub fn candles(&self, timeframe: Option<&str>) -> &[Candle] {
+        let timeframe = timeframe.unwrap_or(&self.default_timeframe);
+        self.candles.get(timeframe).map(|c| c.as_slice()).unwrap_or(&[])
+    }
+
+    /// The live price if known, otherwise the last close on the default timeframe.
+    pub fn price(&self) -> Option<f64> {
+        self.price.or_else(|| self.candles(None).last().map(|c| c.close))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum TokenKind {
+    Word(String),
+    Number(f64),
+    /// A number with a unit, such as the candle timeframe `5m` or `1h`.
+    Timeframe(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenKind::Word(word) => write!(f, "'{}'", word),
+            TokenKind::Number(value) => write!(f, "{}", value),
+            TokenKind::Timeframe(timeframe) => write!(f, "'{}'", timeframe),
+            TokenKind::Symbol(symbol) => write!(f, "'{}'", symbol),
+        }
+    }
+}
+
+pub(crate) struct Token {
+    pub kind: TokenKind,
+    /// 1-based column in the source, counted in characters.
+    pub column: usize,
+    /// Byte offset of the token's start, for slicing the source.
+    pub offset: usize,
+}
+
+pub(crate) fn tokenize(source: &str) -> Result<Vec<Token>, ParseError> {
+    let (offsets, chars): (Vec<usize>, Vec<char>) = source.char_indices().unzip();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while index < chars.len() {
+        let c = chars[index];
+        let column = index + 1;
+        let offset = offsets[index];
+        if c.is_whitespace() {
+            index += 1;
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let start = index;
+            while index < chars.len() && (chars[index].is_ascii_alphanumeric() || chars[index] == '_') {
+                index += 1;
+            }
+            let word: String = chars[start..index].iter().collect();
+            tokens.push(Token { kind: TokenKind::Word(word.to_lowercase()), column, offset });
+        } else if c.is_ascii_digit() || c == '.' || (c == '-' && chars.get(index + 1).map(|n| n.is_ascii_digit()).unwrap_or(false)) {
+            let start = index;
+            index += 1;
+            while index < chars.len() && (chars[index].is_ascii_digit() || chars[index] == '.') {
+                index += 1;
+            }
+            let number: String = chars[start..index].iter().collect();
+            if index < chars.len() && chars[index].is_ascii_alphabetic() {
+                while index < chars.len() && chars[index].is_ascii_alphanumeric() {
+                    index += 1;
+                }
+                let timeframe: String = chars[start..index].iter().collect();
+                tokens.push(Token { kind: TokenKind::Timeframe(timeframe), column, offset });
+                continue;
+            }
+            let value = number.parse::<f64>().map_err(|_| ParseError::new(column, format!("Invalid number {}", number)))?;
+            tokens.push(Token { kind: TokenKind::Number(value), column, offset });
+        } else {
+            let two: String = chars[index..(index + 2).min(chars.len())].iter().collect();
+            let symbol = match two.as_str() {
+                ">=" => ">=",
+                "<=" => "<=",
+                _ => match c {
+                    '>' => ">",
+                    '<' => "<",
+                    '(' => "(",
+                    ')' => ")",
+                    ',' => ",",
+                    '%' => "%",
+                    // Separates gain and fraction in take-profit rungs
+                    ':' => ":",
+                    _ => return Err(ParseError::new(column, format!("Unexpected character '{}'", c))),
+                },
+            };
+            index += symbol.len();
+            tokens.push(Token { kind: TokenKind::Symbol(symbol), column, offset });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive-descent parser over the tokens of one line.
+pub(crate) struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Parser { tokens, position: 0 }
+    }
+
+    pub fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    pub fn advance(&mut self) -> Option<&Token> {
+        self.position += 1;
+        self.tokens.get(self.position - 1)
+    }
+
+    pub fn next_is(&self, kind: &TokenKind) -> bool {
+        self.peek().map(|t| &t.kind == kind).unwrap_or(false)
+    }
+
+    pub fn next_is_word(&self, word: &str) -> bool {
+        self.next_is(&TokenKind::Word(word.to_string()))
+    }
+
+    /// Column just past the last token, for errors about missing input.
+    pub fn end_column(&self) -> usize {
+        self.tokens.last().map(|t| t.column + 1).unwrap_or(1)
+    }
+
+    pub fn expect(&mut self, symbol: &'static str) -> Result<(), ParseError> {
+        match self.peek() {
+            Some(token) if token.kind == TokenKind::Symbol(symbol) => {
+                self.position += 1;
+                Ok(())
+            }
+            Some(token) => Err(ParseError::new(token.column, format!("Expected '{}' but found {}", symbol, token.kind))),
+            None => Err(ParseError::new(self.end_column(), format!("Expected '{}'", symbol))),
+        }
+    }
+
+    /// Parses a rule, stopping at the first token that cannot continue it.
+    pub fn rule(&mut self) -> Result<Rule, ParseError> {
+        let mut rules = vec![self.all()?];
+        while self.next_is_word("or") {
+            self.position += 1;
+            rules.push(self.all()?);
+        }
+        Ok(if rules.len() == 1 { rules.remove(0) } else { Rule::Any(rules) })
+    }
+
+    fn all(&mut self) -> Result<Rule, ParseError> {
+        let mut rules = vec![self.term()?];
+        while self.next_is_word("and") {
+            self.position += 1;
+            rules.push(self.term()?);
+        }
+        Ok(if rules.len() == 1 { rules.remove(0) } else { Rule::All(rules) })
+    }
+
+    fn term(&mut self) -> Result<Rule, ParseError> {
+        if self.next_is(&TokenKind::Symbol("(")) {
+            self.position += 1;
+            let rule = self.rule()?;
+            self.expect(")")?;
+            return Ok(rule);
+        }
+        let column = self.peek().map(|t| t.column).unwrap_or_else(|| self.end_column());
+        let left = self.operand()?;
+        let comparison = match self.peek() {
+            Some(token) => {
+                let comparison = match &token.kind {
+                    TokenKind::Symbol(">") => Comparison::Above,
+                    TokenKind::Symbol("<") => Comparison::Below,
+                    TokenKind::Symbol(">=") => Comparison::AtLeast,
+                    TokenKind::Symbol("<=") => Comparison::AtMost,
+                    TokenKind::Word(word) if word == "crosses_above" => Comparison::CrossesAbove,
+                    TokenKind::Word(word) if word == "crosses_below" => Comparison::CrossesBelow,
+                    other => return Err(ParseError::new(token.column, format!("Expected a comparison but found {}", other))),
+                };
+                self.position += 1;
+                comparison
+            }
+            None => return Err(ParseError::new(self.end_column(), "Expected a comparison".to_string())),
+        };
+        let right = self.operand()?;
+        if matches!(left.indicator, Indicator::Number(_)) && matches!(right.indicator, Indicator::Number(_)) {
+            return Err(ParseError::new(
+                column,
+                format!("Comparison of two numbers ({} {} {}) is always the same", left, comparison, right),
+            ));
+        }
+        Ok(Rule::Condition { left, comparison, right })
+    }
+
+    fn operand(&mut self) -> Result<Operand, ParseError> {
+        let Some(token) = self.peek() else {
+            return Err(ParseError::new(self.end_column(), "Expected an indicator or number".to_string()));
+        };
+        let column = token.column;
+        match token.kind.clone() {
+            TokenKind::Number(value) => {
+                self.position += 1;
+                Ok(Operand { indicator: Indicator::Number(value), timeframe: None })
+            }
+            TokenKind::Word(name) => {
+                self.position += 1;
+                let mut args = Vec::new();
+                let mut timeframe = None;
+                if self.next_is(&TokenKind::Symbol("(")) {
+                    self.position += 1;
+                    loop {
+                        match self.peek() {
+                            Some(Token { kind: TokenKind::Number(value), .. }) if timeframe.is_none() => {
+                                args.push(*value);
+                                self.position += 1;
+                            }
+                            Some(Token { kind: TokenKind::Timeframe(value), column, .. }) if timeframe.is_none() => {
+                                parse_timeframe(value).map_err(|e| ParseError::new(*column, e))?;
+                                timeframe = Some(value.clone());
+                                self.position += 1;
+                            }
+                            Some(token) if timeframe.is_some() => {
+                                return Err(ParseError::new(token.column, "The timeframe must be the last argument".to_string()));
+                            }
+                            Some(token) => {
+                                return Err(ParseError::new(token.column, format!("Expected a number or timeframe but found {}", token.kind)));
+                            }
+                            None => return Err(ParseError::new(self.end_column(), "Expected a number or timeframe".to_string())),
+                        }
+                        if self.next_is(&TokenKind::Symbol(",")) {
+                            self.position += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    self.expect(")")?;
+                }
+                Ok(Operand { indicator: Indicator::parse(&name, &args, column)?, timeframe })
+            }
+            other => Err(ParseError::new(column, format!("Expected an indicator or number but found {}", other))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candles(closes: &[f64]) -> Vec<Candle> {
+        closes
+            .iter()
+            .enumerate()
+            .map(|(index, &close)| Candle {
+                timestamp: index as i64 * 300,
+                open: close,
+                high: close,
+                low: close,
+                close,
+                volume: 1.0,
+            })
+            .collect()
+    }
+
+    fn market(closes: &[f64]) -> Market {
+        Market {
+            default_timeframe: "5m".to_string(),
+            candles: HashMap::from([("5m".to_string(), candles(closes))]),
+            price: None,
+        }
+    }
+
+    fn holds(rule: &str, market: &Market) -> bool {
+        Rule::parse(rule).unwrap().evaluate(market)
+    }
+
+    #[test]
+    fn crosses_above_only_on_the_crossing_candle() {
+        assert!(holds("price crosses_above 1.5", &market(&[1.0, 1.0, 1.0, 2.0])));
+        assert!(!holds("price crosses_above 1.5", &market(&[1.0, 1.0, 2.0, 2.0])));
+        assert!(!holds("price crosses_above 1.5", &market(&[1.0, 1.0, 1.0, 1.0])));
+        assert!(!holds("price crosses_below 1.5", &market(&[1.0, 1.0, 1.0, 2.0])));
+    }
+
+    #[test]
+    fn crosses_below_only_on_the_crossing_candle() {
+        assert!(holds("price crosses_below 1.5", &market(&[2.0, 2.0, 2.0, 1.0])));
+        assert!(!holds("price crosses_below 1.5", &market(&[2.0, 2.0, 1.0, 1.0])));
+        assert!(!holds("price crosses_above 1.5", &market(&[2.0, 2.0, 2.0, 1.0])));
+    }
+
+    #[test]
+    fn a_touch_counts_as_the_previous_side() {
+        assert!(holds("price crosses_above 1.5", &market(&[1.0, 1.5, 2.0])));
+        assert!(holds("price crosses_below 1.5", &market(&[2.0, 1.5, 1.0])));
+    }
+
+    #[test]
+    fn indicators_cross_each_other() {
+        let market = market(&[5.0, 4.0, 3.0, 2.0, 1.0, 1.0, 1.0, 6.0]);
+        assert!(holds("ema(2) crosses_above sma(4)", &market));
+        assert!(!holds("ema(2) crosses_below sma(4)", &market));
+        assert!(holds("ema(2) > sma(4)", &market));
+        assert!(holds("sma(4) crosses_below ema(2)", &market));
+    }
+
+    #[test]
+    fn crossovers_need_two_candles() {
+        assert!(!holds("price crosses_above 1.5", &market(&[2.0])));
+        assert!(holds("price > 1.5", &market(&[2.0])));
+    }
+
+    #[test]
+    fn conditions_without_enough_history_are_false() {
+        let market = market(&[1.0, 2.0, 3.0]);
+        assert!(!holds("sma(4) > 0", &market));
+        assert!(holds("sma(3) > 0", &market));
+        assert!(!holds("price(1h) > 0", &market));
+    }
+
+    #[test]
+    fn required_candles_cover_warmup_and_the_crossover_candle() {
+        let rule = Rule::parse("ema(9) crosses_above ema(21) and (rsi(14,1h) < 30 or macd_hist(12,26,9) > 0)").unwrap();
+        let mut needs = HashMap::new();
+        rule.required_candles("5m", &mut needs);
+        assert_eq!(needs, HashMap::from([("5m".to_string(), 36), ("1h".to_string(), 16)]));
+    }
+
+    #[test]
+    fn required_candles_keep_larger_existing_needs_and_skip_numbers() {
+        let rule = Rule::parse("price > 1 and volume > volume_sma(20)").unwrap();
+        let mut needs = HashMap::from([("5m".to_string(), 50)]);
+        rule.required_candles("5m", &mut needs);
+        assert_eq!(needs, HashMap::from([("5m".to_string(), 50)]));
+
+        let rule = Rule::parse("atr_pct(14) < 0.05").unwrap();
+        let mut needs = HashMap::new();
+        rule.required_candles("15m", &mut needs);
+        assert_eq!(needs, HashMap::from([("15m".to_string(), 16)]));
+    }
+
+    #[test]
+    fn comparisons_use_the_live_price_and_crossovers_use_closes() {
+        let mut market = market(&[1.0, 1.0, 2.0]);
+        market.price = Some(1.2);
+        assert!(!holds("price > 1.5", &market));
+        assert!(holds("price(5m) > 1.5", &market));
+        // The close crossed on the last candle, whatever the live price is doing now
+        assert!(holds("price crosses_above 1.5", &market));
+
+        let mut market = self::market(&[1.0, 2.0, 2.0]);
+        market.price = Some(1.0);
+        assert!(!holds("price crosses_above 1.5", &market));
+        assert!(!holds("price crosses_below 1.5", &market));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let market = market(&[1.5]);
+        assert!(holds("price > 1 or price > 2 and price > 3", &market));
+        assert!(!holds("(price > 1 or price > 2) and price > 3", &market));
+        assert!(holds("price > 3 and price > 2 or price > 1", &market));
+        assert!(!holds("price > 3 and (price > 2 or price > 1)", &market));
+        assert_eq!(
+            Rule::parse("price > 1 or price > 2 and price > 3").unwrap(),
+            Rule::parse("price > 1 or (price > 2 and price > 3)").unwrap()
+        );
+    }
+
+    #[test]
+    fn reports_parse_errors_with_columns() {
+        assert_eq!(Rule::parse("foo > 1").unwrap_err(), "Unknown indicator foo at column 1");
+        assert_eq!(Rule::parse("rsi(14) <").unwrap_err(), "Expected an indicator or number at column 10");
+        assert_eq!(Rule::parse("rsi(14) = 3").unwrap_err(), "Unexpected character '=' at column 9");
+        assert_eq!(Rule::parse("rsi(14)").unwrap_err(), "Expected a comparison at column 8");
+        assert_eq!(Rule::parse("rsi(14) and").unwrap_err(), "Expected a comparison but found 'and' at column 9");
+        assert_eq!(Rule::parse("rsi(1.5) > 3").unwrap_err(), "rsi needs whole positive periods, got 1.5 at column 1");
+        assert_eq!(Rule::parse("ema(9,1h,2) > 1").unwrap_err(), "The timeframe must be the last argument at column 10");
+        assert!(Rule::parse("ema(9,2m) > 1").unwrap_err().ends_with("at column 7"));
+        assert_eq!(Rule::parse("ema(9 > 1").unwrap_err(), "Expected ')' but found '>' at column 7");
+        assert_eq!(Rule::parse("(price > 1").unwrap_err(), "Expected ')' at column 11");
+        assert_eq!(Rule::parse("price > 1 )").unwrap_err(), "Unexpected ')' at column 11");
+        assert_eq!(
+            Rule::parse("macd_hist(26,12,9) > 0").unwrap_err(),
+            "macd_hist needs a fast period below the slow one at column 1"
+        );
+        assert_eq!(Rule::parse("1 > 2").unwrap_err(), "Comparison of two numbers (1 > 2) is always the same at column 1");
+    }
+}
@@ -0,0 +1,116 @@
+use crate::utils::pool_discovery::best_pool;
+use crate::utils::telegram::TelegramBot;
+use reqwest::Client;
+use std::env;
+use ta::indicators::AverageTrueRange;
+use ta::{ DataItem, Next };
+
+/// One OHLCV candle, prices in SOL. `timestamp` is the unix open time.
+#[derive(Debug, Clone, Copy)]
+pub struct Candle {
+    pub timestamp: i64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+}
+
+/// Maps a timeframe like "5m", "1h" or "1d" to the OHLCV API's period, aggregate and length in seconds.
+pub fn parse_timeframe(timeframe: &str) -> Result<(&'static str, u32, u64), String> {
+    match timeframe {
+        "1m" => Ok(("minute", 1, 60)),
+        "5m" => Ok(("minute", 5, 300)),
+        "15m" => Ok(("minute", 15, 900)),
+        "1h" => Ok(("hour", 1, 3600)),
+        "4h" => Ok(("hour", 4, 14_400)),
+        "12h" => Ok(("hour", 12, 43_200)),
+        "1d" => Ok(("day", 1, 86_400)),
+        _ => Err(format!("Unsupported timeframe {}, use 1m, 5m, 15m, 1h, 4h, 12h or 1d", timeframe)),
+    }
+}
+
+/// Fetches the last `limit` closed candles for `token_mint` against SOL from its most
+/// liquid pool, oldest first. Uses the GeckoTerminal OHLCV API (`CANDLE_API`).
+pub async fn fetch_candles(
+    token_mint: &str,
+    timeframe: &str,
+    limit: usize,
+    telegram: &TelegramBot
+) -> Result<Vec<Candle>, Box<dyn std::error::Error>> {
+    let (period, aggregate, _) = parse_timeframe(timeframe)?;
+    let pool = best_pool(token_mint, "SOL", telegram).await?;
+    let base = env::var("CANDLE_API").unwrap_or_else(|_| "https://api.geckoterminal.com/api/v2".to_string());
+    // One extra candle, since the newest one is still open
+    let url = format!(
+        "{}/networks/solana/pools/{}/ohlcv/{}?aggregate={}&limit={}&currency=token&token={}",
+        base, pool.id, period, aggregate, limit + 1, token_mint
+    );
+    let response = Client::new()
+        .get(&url)
+        .header("Accept", "application/json")
+        .send()
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+    let rows = response["data"]["attributes"]["ohlcv_list"]
+        .as_array()
+        .ok_or_else(|| format!("No candles for {}", token_mint))?;
+
+    let mut candles: Vec<Candle> = rows
+        .iter()
+        .filter_map(|row| {
+            Some(Candle {
+                timestamp: row.get(0)?.as_i64()?,
+                open: row.get(1)?.as_f64()?,
+                high: row.get(2)?.as_f64()?,
+                low: row.get(3)?.as_f64()?,
+                close: row.get(4)?.as_f64()?,
+                volume: row.get(5)?.as_f64()?,
+            })
+        })
+        .collect();
+    // The API returns newest first
+    candles.sort_by_key(|c| c.timestamp);
+    candles.pop();
+    if candles.len() > limit {
+        candles.drain(..candles.len() - limit);
+    }
+    Ok(candles)
+}
+
+/// Average true range over `period` candles, `None` with too little history.
+pub fn atr(candles: &[Candle], period: usize) -> Option<f64> {
+    if candles.len() <= period {
+        return None;
+    }
+    let mut indicator = AverageTrueRange::new(period).ok()?;
+    let mut value = 0.0;
+    for candle in candles {
+        let item = DataItem::builder()
+            .open(candle.open)
+            .high(candle.high)
+            .low(candle.low)
+            .close(candle.close)
+            .volume(candle.volume)
+            .build()
+            .ok()?;
+        value = indicator.next(&item);
+    }
+    Some(value)
+}
+
+/// Standard deviation of log returns between closes, per candle.
+pub fn realized_volatility(candles: &[Candle]) -> Option<f64> {
+    let returns: Vec<f64> = candles
+        .windows(2)
+        .filter(|pair| pair[0].close > 0.0 && pair[1].close > 0.0)
+        .map(|pair| (pair[1].close / pair[0].close).ln())
+        .collect();
+    if returns.len() < 2 {
+        return None;
+    }
+    let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (returns.len() - 1) as f64;
+    Some(variance.sqrt())
+}
@@ -0,0 +1,295 @@
+use crate::utils::price_feed::{ get_pool_keys, get_quote };
+use crate::utils::rpc;
+use crate::utils::telegram::TelegramBot;
+use crate::utils::wallet::Wallet;
+use base64::{ engine::general_purpose::STANDARD, Engine };
+use serde_json::json;
+use std::env;
+
+const TOKEN_2022_PROGRAM: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+/// Token-2022 extension types relevant to sellability.
+const EXT_TRANSFER_FEE_CONFIG: u16 = 1;
+const EXT_TRANSFER_HOOK: u16 = 14;
+/// Lamports spent by the simulated buy.
+const PROBE_LAMPORTS: u64 = 1_000_000;
+
+#[derive(Debug, Clone)]
+pub struct HoneypotReport {
+    pub mint: String,
+    /// Why the round trip was not simulated, e.g. the token has no pool the simulation can be built for.
+    pub skipped: Option<String>,
+    pub buy_succeeded: bool,
+    pub sell_succeeded: bool,
+    /// Share of expected tokens not received on the buy.
+    pub buy_tax: f64,
+    /// Share of SOL lost on buy plus sell, including AMM fees and impact.
+    pub round_trip_tax: f64,
+    pub transfer_hook: bool,
+    pub transfer_fee_bps: Option<u16>,
+    pub error: Option<String>,
+}
+
+impl HoneypotReport {
+    /// True when the token cannot be bought or sold, or taxes more than `max_round_trip_tax`.
+    /// A skipped simulation blocks the token unless `allow_unsimulated` is set, and a
+    /// transfer hook always blocks it.
+    pub fn is_blocked(&self, max_round_trip_tax: f64, allow_unsimulated: bool) -> bool {
+        if self.transfer_hook {
+            return true;
+        }
+        if self.skipped.is_some() {
+            return !allow_unsimulated;
+        }
+        !self.buy_succeeded || !self.sell_succeeded || self.round_trip_tax > max_round_trip_tax
+    }
+
+    pub fn summary(&self) -> String {
+        if let Some(reason) = &self.skipped {
+            return format!(
+                "Honeypot check for {} skipped: {}{}",
+                self.mint,
+                reason,
+                if self.transfer_hook { ", transfer hook" } else { "" }
+            );
+        }
+        if !self.buy_succeeded {
+            return format!(
+                "Honeypot check for {}: buy failed ({})",
+                self.mint,
+                self.error.as_deref().unwrap_or("unknown error")
+            );
+        }
+        if !self.sell_succeeded {
+            return format!(
+                "Honeypot check for {}: sell failed ({})",
+                self.mint,
+                self.error.as_deref().unwrap_or("unknown error")
+            );
+        }
+        format!(
+            "Honeypot check for {}: buy tax {:.1}%, round-trip tax {:.1}%{}{}",
+            self.mint,
+            self.buy_tax * 100.0,
+            self.round_trip_tax * 100.0,
+            if self.transfer_hook { ", transfer hook" } else { "" },
+            self.transfer_fee_bps.map(|bps| format!(", transfer fee {} bps", bps)).unwrap_or_default()
+        )
+    }
+}
+
+/// Reads Token-2022 transfer hook and transfer fee extensions from a mint account.
+async fn mint_extensions(mint: &str) -> Result<(bool, Option<u16>), Box<dyn std::error::Error>> {
+    let account = rpc::call("getAccountInfo", json!([mint, { "encoding": "base64" }])).await?;
+    if account["value"]["owner"].as_str() != Some(TOKEN_2022_PROGRAM) {
+        return Ok((false, None));
+    }
+    let data = account["value"]["data"][0]
+        .as_str()
+        .and_then(|d| STANDARD.decode(d).ok())
+        .ok_or("Mint account not found")?;
+    Ok(parse_mint_extensions(&data))
+}
+
+/// Reads the transfer hook flag and transfer fee from Token-2022 mint account data.
+fn parse_mint_extensions(data: &[u8]) -> (bool, Option<u16>) {
+    // Base mint (82 bytes) padded to 165, account type byte, then TLV entries
+    let mut offset = 166;
+    let mut transfer_hook = false;
+    let mut transfer_fee_bps = None;
+    while offset + 4 <= data.len() {
+        let ext_type = u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let len = u16::from_le_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        let value = data.get(offset + 4..offset + 4 + len).unwrap_or_default();
+        match ext_type {
+            // A hook with an unset program id (all zeros) is inert
+            EXT_TRANSFER_HOOK => transfer_hook = value.get(32..64).map(|p| p.iter().any(|b| *b != 0)).unwrap_or(false),
+            // Newer fee (epoch u64, max fee u64, bps u16) at the end of the config
+            EXT_TRANSFER_FEE_CONFIG if len >= 108 => {
+                transfer_fee_bps = Some(u16::from_le_bytes([value[len - 2], value[len - 1]]));
+            }
+            0 => break,
+            _ => {}
+        }
+        offset += 4 + len;
+    }
+    (transfer_hook, transfer_fee_bps)
+}
+
+/// Simulates a tiny buy followed by an immediate sell of half the expected tokens
+/// and reports whether both succeed and what the round trip cost. Tokens without a
+/// Raydium AMM v4 pool get a skipped report rather than an error.
+pub async fn check_honeypot(
+    wallet: &Wallet,
+    token_mint: &str,
+    telegram: &TelegramBot
+) -> Result<HoneypotReport, Box<dyn std::error::Error>> {
+    let (transfer_hook, transfer_fee_bps) = mint_extensions(token_mint).await?;
+    let mut report = HoneypotReport {
+        mint: token_mint.to_string(),
+        skipped: None,
+        buy_succeeded: false,
+        sell_succeeded: false,
+        buy_tax: 0.0,
+        round_trip_tax: 1.0,
+        transfer_hook,
+        transfer_fee_bps,
+        error: None,
+    };
+    // The simulation builds Raydium AMM v4 swaps; tokens trading elsewhere, such as
+    // PumpSwap, cannot be simulated
+    let pool_info = get_pool_keys(token_mint, "SOL", telegram).await.map_err(|e| e.to_string());
+    let pool_info = match pool_info {
+        Ok(pool_info) => pool_info,
+        Err(e) => {
+            report.skipped = Some(format!("no Raydium AMM v4 pool ({})", e));
+            telegram.send_message(&report.summary()).await?;
+            return Ok(report);
+        }
+    };
+    let expected_tokens = get_quote("SOL", token_mint, PROBE_LAMPORTS).await?.out_amount;
+    // Selling half leaves room for a buy tax without the sell failing on balance alone
+    let sell_amount = expected_tokens / 2;
+
+    let simulation = wallet.simulate_round_trip(&pool_info, token_mint, PROBE_LAMPORTS, sell_amount)?;
+    report.buy_succeeded = simulation.buy_error.is_none();
+    report.sell_succeeded = report.buy_succeeded && simulation.sell_error.is_none();
+    report.error = simulation.buy_error.or(simulation.sell_error);
+
+    let bought = simulation.token_balance_after_buy
+        .map(|after| after.saturating_sub(simulation.token_balance_before))
+        .unwrap_or(0);
+    if let (true, true, Some(wsol_after)) =
+        (report.sell_succeeded, bought > 0, simulation.wsol_balance_after)
+    {
+        report.buy_tax = (1.0 - bought as f64 / expected_tokens as f64).max(0.0);
+        // The wrap adds PROBE_LAMPORTS and the buy spends it, so the net change is the sell proceeds
+        let sol_received = wsol_after.saturating_sub(simulation.wsol_balance_before) as f64;
+        let fair_value = PROBE_LAMPORTS as f64 * sell_amount as f64 / bought as f64;
+        report.round_trip_tax = (1.0 - sol_received / fair_value).max(0.0);
+    }
+
+    telegram.send_message(&report.summary()).await?;
+    Ok(report)
+}
+
+/// Maximum round-trip tax tolerated before a token is blocked, from `HONEYPOT_MAX_TAX` (default 10%).
+pub fn max_round_trip_tax() -> f64 {
+    env::var("HONEYPOT_MAX_TAX")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0.1)
+}
+
+/// Whether tokens whose round trip could not be simulated may still be traded, from
+/// `HONEYPOT_ALLOW_UNSIMULATED` (default false).
+pub fn allow_unsimulated() -> bool {
+    env::var("HONEYPOT_ALLOW_UNSIMULATED")
+        .map(|v| v == "true")
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> HoneypotReport {
+        HoneypotReport {
+            mint: "MINT".to_string(),
+            skipped: None,
+            buy_succeeded: true,
+            sell_succeeded: true,
+            buy_tax: 0.01,
+            round_trip_tax: 0.05,
+            transfer_hook: false,
+            transfer_fee_bps: None,
+            error: None,
+        }
+    }
+
+    /// Token-2022 mint data: the padded base mint, the account type byte, then `extensions`.
+    fn mint_data(extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![0u8; 165];
+        data.push(1);
+        for (ext_type, value) in extensions {
+            data.extend_from_slice(&ext_type.to_le_bytes());
+            data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            data.extend_from_slice(value);
+        }
+        data
+    }
+
+    fn transfer_hook(program: u8) -> Vec<u8> {
+        let mut value = vec![7u8; 32];
+        value.extend_from_slice(&[program; 32]);
+        value
+    }
+
+    fn transfer_fee_config(bps: u16) -> Vec<u8> {
+        let mut value = vec![0u8; 106];
+        value.extend_from_slice(&bps.to_le_bytes());
+        value
+    }
+
+    #[test]
+    fn passes_tradeable_tokens_within_the_tax_limit() {
+        assert!(!report().is_blocked(0.1, false));
+        assert!(report().is_blocked(0.04, false));
+    }
+
+    #[test]
+    fn blocks_failed_round_trips() {
+        let mut buy_failed = report();
+        buy_failed.buy_succeeded = false;
+        assert!(buy_failed.is_blocked(0.1, true));
+        let mut sell_failed = report();
+        sell_failed.sell_succeeded = false;
+        assert!(sell_failed.is_blocked(0.1, true));
+    }
+
+    #[test]
+    fn blocks_skipped_checks_unless_allowed() {
+        let mut skipped = report();
+        skipped.skipped = Some("no pool".to_string());
+        skipped.buy_succeeded = false;
+        skipped.sell_succeeded = false;
+        assert!(skipped.is_blocked(0.1, false));
+        assert!(!skipped.is_blocked(0.1, true));
+    }
+
+    #[test]
+    fn always_blocks_transfer_hooks() {
+        let mut hooked = report();
+        hooked.transfer_hook = true;
+        assert!(hooked.is_blocked(0.1, true));
+        hooked.skipped = Some("no pool".to_string());
+        assert!(hooked.is_blocked(0.1, true));
+    }
+
+    #[test]
+    fn reads_the_transfer_hook_and_fee() {
+        let data = mint_data(&[(EXT_TRANSFER_FEE_CONFIG, transfer_fee_config(250)), (EXT_TRANSFER_HOOK, transfer_hook(9))]);
+        assert_eq!(parse_mint_extensions(&data), (true, Some(250)));
+    }
+
+    #[test]
+    fn ignores_hooks_without_a_program() {
+        let data = mint_data(&[(EXT_TRANSFER_HOOK, transfer_hook(0))]);
+        assert_eq!(parse_mint_extensions(&data), (false, None));
+    }
+
+    #[test]
+    fn skips_unrelated_extensions_and_stops_at_padding() {
+        let data = mint_data(&[(3, vec![1u8; 10]), (EXT_TRANSFER_HOOK, transfer_hook(9))]);
+        assert_eq!(parse_mint_extensions(&data), (true, None));
+        let data = mint_data(&[(3, vec![1u8; 10]), (0, vec![]), (EXT_TRANSFER_HOOK, transfer_hook(9))]);
+        assert_eq!(parse_mint_extensions(&data), (false, None));
+    }
+
+    #[test]
+    fn tolerates_plain_and_truncated_mints() {
+        assert_eq!(parse_mint_extensions(&[0u8; 82]), (false, None));
+        let mut data = mint_data(&[(EXT_TRANSFER_HOOK, transfer_hook(9))]);
+        data.truncate(data.len() - 40);
+        assert_eq!(parse_mint_extensions(&data), (false, None));
+    }
+}
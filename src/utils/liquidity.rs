@@ -0,0 +1,174 @@
+use crate::utils::price_feed::{ get_quote, token_unit };
+use crate::utils::telegram::TelegramBot;
+use crate::utils::price_feed::sol_to_lamports;
+use std::env;
+use tokio::task::JoinSet;
+
+/// Fractions of the desired order size quoted to build the impact curve.
+const PROBE_FRACTIONS: [f64; 5] = [0.1, 0.25, 0.5, 1.0, 2.0];
+
+#[derive(Debug, Clone, Copy)]
+pub struct ImpactPoint {
+    /// Input size in UI units (SOL for buys, tokens for sells).
+    pub size: f64,
+    pub impact_bps: f64,
+}
+
+/// Size-versus-impact curve for one swap direction, sorted by size.
+#[derive(Debug, Clone)]
+pub struct ImpactCurve {
+    pub points: Vec<ImpactPoint>,
+}
+
+impl ImpactCurve {
+    /// Largest input size whose price impact stays within `max_bps`,
+    /// interpolating linearly between probed sizes.
+    pub fn max_size_at_bps(&self, max_bps: f64) -> f64 {
+        let mut prev = ImpactPoint { size: 0.0, impact_bps: 0.0 };
+        for point in &self.points {
+            if point.impact_bps > max_bps {
+                let span = point.impact_bps - prev.impact_bps;
+                if span <= 0.0 {
+                    return prev.size;
+                }
+                let ratio = (max_bps - prev.impact_bps) / span;
+                return prev.size + (point.size - prev.size) * ratio.clamp(0.0, 1.0);
+            }
+            prev = *point;
+        }
+        // Every probed size is within budget; do not extrapolate past the largest probe
+        prev.size
+    }
+}
+
+/// Quotes `sizes` (UI units of `input_mint`) concurrently and returns the impact curve.
+/// Sizes whose quote fails are left out, and it is an error only when every quote fails.
+/// Impact is the worse of the route's reported impact and the effective price
+/// degradation relative to the smallest probe.
+pub async fn probe_depth(
+    input_mint: &str,
+    output_mint: &str,
+    sizes: &[f64]
+) -> Result<ImpactCurve, Box<dyn std::error::Error>> {
+    let input_is_sol = input_mint == "SOL";
+    let unit = token_unit(input_mint).await?;
+    let mut probes = JoinSet::new();
+    for &size in sizes {
+        let input_mint = input_mint.to_string();
+        let output_mint = output_mint.to_string();
+        let amount = if input_is_sol { sol_to_lamports(size) } else { (size * unit) as u64 };
+        probes.spawn(async move {
+            get_quote(&input_mint, &output_mint, amount)
+                .await
+                .map(|quote| (size, quote))
+                .map_err(|e| e.to_string())
+        });
+    }
+
+    // A failed probe leaves a gap in the curve; the order is capped by the probes that were quoted
+    let mut quotes = Vec::new();
+    while let Some(result) = probes.join_next().await {
+        match result {
+            Ok(Ok(quote)) => quotes.push(quote),
+            Ok(Err(e)) => log::warn!("Depth probe for {} -> {} failed: {}", input_mint, output_mint, e),
+            Err(e) => log::warn!("Depth probe for {} -> {} panicked: {}", input_mint, output_mint, e),
+        }
+    }
+    if quotes.is_empty() {
+        return Err(format!("No depth quotes for {} -> {}", input_mint, output_mint).into());
+    }
+    quotes.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let reference_rate = quotes[0].1.out_amount as f64 / quotes[0].1.in_amount as f64;
+    let points = quotes
+        .iter()
+        .map(|(size, quote)| {
+            let rate = quote.out_amount as f64 / quote.in_amount as f64;
+            let degradation_bps = (1.0 - rate / reference_rate).max(0.0) * 10_000.0;
+            ImpactPoint {
+                size: *size,
+                impact_bps: degradation_bps.max(quote.price_impact * 10_000.0),
+            }
+        })
+        .collect();
+    Ok(ImpactCurve { points })
+}
+
+/// Price impact limit for a single order, from `MAX_ORDER_IMPACT_BPS` (default 100).
+pub fn max_order_impact_bps() -> f64 {
+    env::var("MAX_ORDER_IMPACT_BPS")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(100.0)
+}
+
+/// Caps an order of `desired_amount` tokens so its price impact stays within
+/// `MAX_ORDER_IMPACT_BPS` (default 100). `action` is "buy" or "sell".
+pub async fn cap_order_size(
+    token_mint: &str,
+    action: &str,
+    desired_amount: f64,
+    price: f64,
+    telegram: &TelegramBot
+) -> Result<f64, Box<dyn std::error::Error>> {
+    let max_bps = max_order_impact_bps();
+
+    let max_amount = if action == "buy" {
+        let sizes: Vec<f64> = PROBE_FRACTIONS.iter().map(|f| f * desired_amount * price).collect();
+        let curve = probe_depth("SOL", token_mint, &sizes).await?;
+        curve.max_size_at_bps(max_bps) / price
+    } else {
+        let sizes: Vec<f64> = PROBE_FRACTIONS.iter().map(|f| f * desired_amount).collect();
+        let curve = probe_depth(token_mint, "SOL", &sizes).await?;
+        curve.max_size_at_bps(max_bps)
+    };
+
+    if max_amount < desired_amount {
+        telegram
+            .send_message(&format!(
+                "Capped {} of {} from {} to {:.2} tokens ({} bps impact limit)",
+                action, token_mint, desired_amount, max_amount, max_bps
+            ))
+            .await?;
+        return Ok(max_amount);
+    }
+    Ok(desired_amount)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(points: &[(f64, f64)]) -> ImpactCurve {
+        ImpactCurve {
+            points: points.iter().map(|&(size, impact_bps)| ImpactPoint { size, impact_bps }).collect(),
+        }
+    }
+
+    #[test]
+    fn interpolates_between_probes() {
+        let curve = curve(&[(1.0, 20.0), (2.0, 60.0), (4.0, 140.0)]);
+        assert!((curve.max_size_at_bps(40.0) - 1.5).abs() < 1e-9);
+        assert!((curve.max_size_at_bps(100.0) - 3.0).abs() < 1e-9);
+        // Below the first probe the curve runs from zero size at zero impact
+        assert!((curve.max_size_at_bps(10.0) - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn does_not_extrapolate_past_the_largest_probe() {
+        let curve = curve(&[(1.0, 20.0), (2.0, 60.0)]);
+        assert_eq!(curve.max_size_at_bps(500.0), 2.0);
+    }
+
+    #[test]
+    fn budget_below_the_first_probe_scales_it_down() {
+        let curve = curve(&[(1.0, 50.0), (2.0, 80.0)]);
+        assert!((curve.max_size_at_bps(40.0) - 0.8).abs() < 1e-9);
+        assert_eq!(curve.max_size_at_bps(0.0), 0.0);
+    }
+
+    #[test]
+    fn an_empty_curve_allows_nothing() {
+        assert_eq!(curve(&[]).max_size_at_bps(100.0), 0.0);
+    }
+}
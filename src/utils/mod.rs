@@ -1,4 +1,19 @@
 pub mod wallet;
 pub mod price_feed;
 pub mod telegram;
-pub mod trade_log;
\ No newline at end of file
+pub mod trade_log;
+pub mod price_guard;
+pub mod liquidity;
+pub mod raydium;
+pub mod ws_manager;
+pub mod pool_events;
+pub mod pump_fun;
+pub mod rpc;
+pub mod pool_discovery;
+pub mod token_safety;
+pub mod honeypot;
+pub mod pool_registry;
+pub mod token_metadata;
+pub mod slot_clock;
+pub mod candles;
+pub mod schedule;
\ No newline at end of file
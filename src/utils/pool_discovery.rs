@@ -0,0 +1,424 @@
+use crate::utils::price_feed::{ PoolInfo, SOL_MINT };
+use crate::utils::rpc;
+use crate::utils::telegram::TelegramBot;
+use base64::{ engine::general_purpose::STANDARD, Engine };
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+use std::fmt;
+use tokio::task::JoinSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Venue {
+    RaydiumAmmV4,
+    RaydiumCpmm,
+    RaydiumClmm,
+    OrcaWhirlpool,
+    MeteoraDlmm,
+}
+
+impl fmt::Display for Venue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Venue::RaydiumAmmV4 => "Raydium AMM v4",
+            Venue::RaydiumCpmm => "Raydium CPMM",
+            Venue::RaydiumClmm => "Raydium CLMM",
+            Venue::OrcaWhirlpool => "Orca Whirlpool",
+            Venue::MeteoraDlmm => "Meteora DLMM",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Where the pair mints and vaults live inside a venue's pool account.
+struct PoolLayout {
+    venue: Venue,
+    program: &'static str,
+    data_size: u64,
+    mint_a_offset: usize,
+    mint_b_offset: usize,
+    vault_a_offset: usize,
+    vault_b_offset: usize,
+}
+
+static LAYOUTS: [PoolLayout; 5] = [
+    PoolLayout {
+        venue: Venue::RaydiumAmmV4,
+        program: "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8",
+        data_size: 752,
+        mint_a_offset: 400,
+        mint_b_offset: 432,
+        vault_a_offset: 336,
+        vault_b_offset: 368,
+    },
+    PoolLayout {
+        venue: Venue::RaydiumCpmm,
+        program: "CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C",
+        data_size: 637,
+        mint_a_offset: 168,
+        mint_b_offset: 200,
+        vault_a_offset: 72,
+        vault_b_offset: 104,
+    },
+    PoolLayout {
+        venue: Venue::RaydiumClmm,
+        program: "CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK",
+        data_size: 1544,
+        mint_a_offset: 73,
+        mint_b_offset: 105,
+        vault_a_offset: 137,
+        vault_b_offset: 169,
+    },
+    PoolLayout {
+        venue: Venue::OrcaWhirlpool,
+        program: "whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc",
+        data_size: 653,
+        mint_a_offset: 101,
+        mint_b_offset: 181,
+        vault_a_offset: 133,
+        vault_b_offset: 213,
+    },
+    PoolLayout {
+        venue: Venue::MeteoraDlmm,
+        program: "LBUZKhRxPF3XUpBCjp4YzTKgLccjZhTSDM9YuVaPwxo",
+        data_size: 904,
+        mint_a_offset: 88,
+        mint_b_offset: 120,
+        vault_a_offset: 152,
+        vault_b_offset: 184,
+    },
+];
+
+/// A pool for a token pair on one venue, with reserves in UI units.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct DiscoveredPool {
+    pub venue: Venue,
+    pub id: String,
+    pub token_mint: String,
+    pub vs_mint: String,
+    pub token_vault: String,
+    pub vs_vault: String,
+    pub token_reserve: f64,
+    pub vs_reserve: f64,
+    /// Spot price of one token in `vs_mint`.
+    pub price: f64,
+    /// Pool value in `vs_mint` units, used for ranking.
+    pub liquidity: f64,
+    /// Raw pool account, for venue-specific fields such as the AMM v4 market id.
+    pub data: Vec<u8>,
+}
+
+struct RawPool {
+    layout: &'static PoolLayout,
+    id: String,
+    data: Vec<u8>,
+    token_is_a: bool,
+}
+
+fn read_pubkey(data: &[u8], offset: usize) -> Option<String> {
+    let bytes: [u8; 32] = data.get(offset..offset + 32)?.try_into().ok()?;
+    Some(Pubkey::new_from_array(bytes).to_string())
+}
+
+fn read_u128(data: &[u8], offset: usize) -> Option<u128> {
+    Some(u128::from_le_bytes(data.get(offset..offset + 16)?.try_into().ok()?))
+}
+
+/// Price of mint A in mint B from the pool state, or `None` for constant-product venues
+/// where the vault ratio is the price.
+fn state_price_a_in_b(venue: Venue, data: &[u8], decimals_a: u8, decimals_b: u8) -> Option<f64> {
+    let scale = 10f64.powi(decimals_a as i32 - decimals_b as i32);
+    match venue {
+        Venue::RaydiumClmm | Venue::OrcaWhirlpool => {
+            let offset = if venue == Venue::RaydiumClmm { 253 } else { 65 };
+            let sqrt_price = read_u128(data, offset)? as f64 / 2f64.powi(64);
+            Some(sqrt_price * sqrt_price * scale)
+        }
+        Venue::MeteoraDlmm => {
+            let active_id = i32::from_le_bytes(data.get(76..80)?.try_into().ok()?);
+            let bin_step = u16::from_le_bytes(data.get(80..82)?.try_into().ok()?);
+            Some((1.0 + bin_step as f64 / 10_000.0).powi(active_id) * scale)
+        }
+        Venue::RaydiumAmmV4 | Venue::RaydiumCpmm => None,
+    }
+}
+
+async fn find_raw_pools(
+    layout: &'static PoolLayout,
+    mint_a: String,
+    mint_b: String
+) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let result = rpc::call(
+        "getProgramAccounts",
+        json!([
+            layout.program,
+            {
+                "encoding": "base64",
+                "commitment": "confirmed",
+                "filters": [
+                    { "dataSize": layout.data_size },
+                    { "memcmp": { "offset": layout.mint_a_offset, "bytes": mint_a } },
+                    { "memcmp": { "offset": layout.mint_b_offset, "bytes": mint_b } }
+                ]
+            }
+        ])
+    ).await.map_err(|e| e.to_string())?;
+    let accounts = result.as_array().ok_or("Invalid getProgramAccounts response")?;
+    Ok(accounts
+        .iter()
+        .filter_map(|a| {
+            let id = a["pubkey"].as_str()?.to_string();
+            let data = STANDARD.decode(a["account"]["data"][0].as_str()?).ok()?;
+            Some((id, data))
+        })
+        .collect())
+}
+
+/// Finds every pool pairing `token_mint` with `vs_token` across Raydium AMM v4,
+/// CPMM, CLMM, Orca Whirlpools and Meteora DLMM, ranked by liquidity.
+pub async fn discover_pools(
+    token_mint: &str,
+    vs_token: &str
+) -> Result<Vec<DiscoveredPool>, Box<dyn std::error::Error>> {
+    let vs_mint = if vs_token == "SOL" { SOL_MINT } else { vs_token };
+
+    let mut queries = JoinSet::new();
+    for layout in &LAYOUTS {
+        for token_is_a in [true, false] {
+            let (mint_a, mint_b) = if token_is_a {
+                (token_mint.to_string(), vs_mint.to_string())
+            } else {
+                (vs_mint.to_string(), token_mint.to_string())
+            };
+            queries.spawn(async move {
+                find_raw_pools(layout, mint_a, mint_b)
+                    .await
+                    .map(|pools| (layout, token_is_a, pools))
+            });
+        }
+    }
+
+    let mut raw_pools = Vec::new();
+    while let Some(result) = queries.join_next().await {
+        match result? {
+            Ok((layout, token_is_a, pools)) => {
+                raw_pools.extend(pools.into_iter().map(|(id, data)| RawPool { layout, id, data, token_is_a }));
+            }
+            // One venue failing (e.g. RPC refusing a heavy scan) should not hide the others
+            Err(e) => log::warn!("Pool discovery query failed: {}", e),
+        }
+    }
+    if raw_pools.is_empty() {
+        return Ok(vec![]);
+    }
+
+    // Vault balances, then both mints' decimals
+    let mut keys = Vec::new();
+    for pool in &raw_pools {
+        keys.push(read_pubkey(&pool.data, pool.layout.vault_a_offset).ok_or("Truncated pool account")?);
+        keys.push(read_pubkey(&pool.data, pool.layout.vault_b_offset).ok_or("Truncated pool account")?);
+    }
+    keys.push(token_mint.to_string());
+    keys.push(vs_mint.to_string());
+    let accounts = rpc::get_multiple_accounts(&keys).await?;
+    let decimals = |account: &Option<Vec<u8>>| account.as_ref().and_then(|d| d.get(44).copied());
+    let token_decimals = decimals(&accounts[keys.len() - 2]).ok_or("Token mint not found")?;
+    let vs_decimals = decimals(&accounts[keys.len() - 1]).ok_or("Quote mint not found")?;
+    let amount = |account: &Option<Vec<u8>>| {
+        account
+            .as_ref()
+            .and_then(|d| d.get(64..72))
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+            .unwrap_or(0)
+    };
+
+    let mut pools = Vec::new();
+    for (i, raw) in raw_pools.into_iter().enumerate() {
+        let vault_a = &keys[2 * i];
+        let vault_b = &keys[2 * i + 1];
+        let (amount_a, amount_b) = (amount(&accounts[2 * i]), amount(&accounts[2 * i + 1]));
+        let (token_vault, vs_vault, token_amount, vs_amount) = if raw.token_is_a {
+            (vault_a, vault_b, amount_a, amount_b)
+        } else {
+            (vault_b, vault_a, amount_b, amount_a)
+        };
+        let token_reserve = token_amount as f64 / 10f64.powi(token_decimals as i32);
+        let vs_reserve = vs_amount as f64 / 10f64.powi(vs_decimals as i32);
+
+        let (decimals_a, decimals_b) = if raw.token_is_a {
+            (token_decimals, vs_decimals)
+        } else {
+            (vs_decimals, token_decimals)
+        };
+        let price = match state_price_a_in_b(raw.layout.venue, &raw.data, decimals_a, decimals_b) {
+            Some(p) if raw.token_is_a => p,
+            Some(p) if p > 0.0 => 1.0 / p,
+            Some(_) => 0.0,
+            None if token_reserve > 0.0 => vs_reserve / token_reserve,
+            None => 0.0,
+        };
+
+        pools.push(DiscoveredPool {
+            venue: raw.layout.venue,
+            id: raw.id,
+            token_mint: token_mint.to_string(),
+            vs_mint: vs_mint.to_string(),
+            token_vault: token_vault.clone(),
+            vs_vault: vs_vault.clone(),
+            token_reserve,
+            vs_reserve,
+            price,
+            liquidity: vs_reserve + token_reserve * price,
+            data: raw.data,
+        });
+    }
+    pools.sort_by(|a, b| b.liquidity.total_cmp(&a.liquidity));
+    Ok(pools)
+}
+
+/// The most liquid pool for the pair on any supported venue.
+pub async fn best_pool(
+    token_mint: &str,
+    vs_token: &str,
+    telegram: &TelegramBot
+) -> Result<DiscoveredPool, Box<dyn std::error::Error>> {
+    let pool = discover_pools(token_mint, vs_token)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("No pool found for {}/{}", token_mint, vs_token))?;
+    telegram
+        .send_message(&format!(
+            "Best venue for {}/{}: {} pool {} (liquidity {:.2}, price {})",
+            token_mint, vs_token, pool.venue, pool.id, pool.liquidity, pool.price
+        ))
+        .await?;
+    Ok(pool)
+}
+
+impl DiscoveredPool {
+    /// Swap keys of a Raydium AMM v4 pool, the venue `Wallet::buy_instructions` executes on.
+    pub fn amm_v4_pool_info(&self) -> Option<PoolInfo> {
+        if self.venue != Venue::RaydiumAmmV4 {
+            return None;
+        }
+        decode_amm_v4_pool_info(&self.id, &self.data)
+    }
+}
+
+fn decode_amm_v4_pool_info(id: &str, data: &[u8]) -> Option<PoolInfo> {
+    if data.len() != 752 {
+        return None;
+    }
+    Some(PoolInfo {
+        id: id.to_string(),
+        base_mint: read_pubkey(data, 400)?,
+        quote_mint: read_pubkey(data, 432)?,
+        base_vault: read_pubkey(data, 336)?,
+        quote_vault: read_pubkey(data, 368)?,
+        market_id: read_pubkey(data, 528)?,
+    })
+}
+
+/// Swap keys of the Raydium AMM v4 pool at `pool_id`, read from its account.
+pub async fn amm_v4_pool(pool_id: &str) -> Result<PoolInfo, Box<dyn std::error::Error>> {
+    let data = rpc::get_multiple_accounts(&[pool_id.to_string()])
+        .await?
+        .pop()
+        .flatten()
+        .ok_or_else(|| format!("Pool {} not found", pool_id))?;
+    Ok(decode_amm_v4_pool_info(pool_id, &data).ok_or_else(|| format!("{} is not a Raydium AMM v4 pool", pool_id))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(byte: u8) -> Pubkey {
+        Pubkey::new_from_array([byte; 32])
+    }
+
+    fn write(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// sqrt(price) in Q64.64, as stored by CLMM and Whirlpool pools.
+    fn sqrt_price_x64(price: f64) -> u128 {
+        (price.sqrt() * 2f64.powi(64)) as u128
+    }
+
+    #[test]
+    fn constant_product_venues_have_no_state_price() {
+        let data = vec![0u8; 752];
+        assert!(state_price_a_in_b(Venue::RaydiumAmmV4, &data, 9, 6).is_none());
+        assert!(state_price_a_in_b(Venue::RaydiumCpmm, &data, 9, 6).is_none());
+    }
+
+    #[test]
+    fn reads_the_sqrt_price_of_concentrated_pools() {
+        for (venue, offset, size) in [(Venue::RaydiumClmm, 253, 1544), (Venue::OrcaWhirlpool, 65, 653)] {
+            let mut data = vec![0u8; size];
+            write(&mut data, offset, &sqrt_price_x64(4.0).to_le_bytes());
+            let price = state_price_a_in_b(venue, &data, 6, 6).unwrap();
+            assert!((price - 4.0).abs() < 1e-9, "{}: {}", venue, price);
+            // Raw price 4 of a 9-decimal mint in a 6-decimal one is 4000 in UI units
+            let price = state_price_a_in_b(venue, &data, 9, 6).unwrap();
+            assert!((price - 4_000.0).abs() < 1e-6, "{}: {}", venue, price);
+        }
+    }
+
+    #[test]
+    fn reads_the_active_bin_of_dlmm_pools() {
+        let mut data = vec![0u8; 904];
+        write(&mut data, 76, &100i32.to_le_bytes());
+        write(&mut data, 80, &25u16.to_le_bytes());
+        let price = state_price_a_in_b(Venue::MeteoraDlmm, &data, 6, 6).unwrap();
+        assert!((price - 1.0025f64.powi(100)).abs() < 1e-9);
+
+        write(&mut data, 76, &(-100i32).to_le_bytes());
+        let price = state_price_a_in_b(Venue::MeteoraDlmm, &data, 6, 6).unwrap();
+        assert!((price - 1.0025f64.powi(-100)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn truncated_state_has_no_price() {
+        assert!(state_price_a_in_b(Venue::RaydiumClmm, &[0u8; 100], 6, 6).is_none());
+        assert!(state_price_a_in_b(Venue::MeteoraDlmm, &[0u8; 80], 6, 6).is_none());
+    }
+
+    #[test]
+    fn layouts_point_at_the_pair_and_vaults() {
+        for layout in &LAYOUTS {
+            let mut data = vec![0u8; layout.data_size as usize];
+            write(&mut data, layout.mint_a_offset, key(1).as_ref());
+            write(&mut data, layout.mint_b_offset, key(2).as_ref());
+            write(&mut data, layout.vault_a_offset, key(3).as_ref());
+            write(&mut data, layout.vault_b_offset, key(4).as_ref());
+            assert_eq!(read_pubkey(&data, layout.mint_a_offset), Some(key(1).to_string()), "{}", layout.venue);
+            assert_eq!(read_pubkey(&data, layout.mint_b_offset), Some(key(2).to_string()), "{}", layout.venue);
+            assert_eq!(read_pubkey(&data, layout.vault_a_offset), Some(key(3).to_string()), "{}", layout.venue);
+            assert_eq!(read_pubkey(&data, layout.vault_b_offset), Some(key(4).to_string()), "{}", layout.venue);
+        }
+        assert!(read_pubkey(&[0u8; 40], 10).is_none());
+    }
+
+    #[test]
+    fn decodes_amm_v4_swap_keys() {
+        let mut data = vec![0u8; 752];
+        write(&mut data, 400, key(1).as_ref());
+        write(&mut data, 432, key(2).as_ref());
+        write(&mut data, 336, key(3).as_ref());
+        write(&mut data, 368, key(4).as_ref());
+        write(&mut data, 528, key(5).as_ref());
+        let pool = decode_amm_v4_pool_info("pool", &data).unwrap();
+        assert_eq!(pool.id, "pool");
+        assert_eq!(pool.base_mint, key(1).to_string());
+        assert_eq!(pool.quote_mint, key(2).to_string());
+        assert_eq!(pool.base_vault, key(3).to_string());
+        assert_eq!(pool.quote_vault, key(4).to_string());
+        assert_eq!(pool.market_id, key(5).to_string());
+
+        // Accounts of any other size are not AMM v4 pools
+        assert!(decode_amm_v4_pool_info("pool", &data[..700]).is_none());
+    }
+}
@@ -0,0 +1,57 @@
+use crate::utils::pump_fun::PumpEvent;
+use crate::utils::raydium::NewPoolEvent;
+use log::warn;
+use tokio::sync::broadcast::{ self, error::RecvError };
+
+/// Fan-out hub for new-pool events. Every subscriber receives every event;
+/// a subscriber that falls more than `capacity` events behind skips the
+/// oldest ones instead of blocking the publisher.
+#[derive(Clone)]
+pub struct EventBus<T: Clone> {
+    tx: broadcast::Sender<T>,
+}
+
+pub type PoolEventBus = EventBus<NewPoolEvent>;
+pub type PoolSubscriber = Subscriber<NewPoolEvent>;
+pub type LaunchEventBus = EventBus<PumpEvent>;
+pub type LaunchSubscriber = Subscriber<PumpEvent>;
+
+impl<T: Clone> EventBus<T> {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        EventBus { tx }
+    }
+
+    /// Publishes an event and returns how many subscribers will receive it.
+    pub fn publish(&self, event: T) -> usize {
+        // An error only means nobody is subscribed right now
+        self.tx.send(event).unwrap_or(0)
+    }
+
+    pub fn subscribe(&self, name: &str) -> Subscriber<T> {
+        Subscriber {
+            name: name.to_string(),
+            rx: self.tx.subscribe(),
+        }
+    }
+}
+
+pub struct Subscriber<T: Clone> {
+    name: String,
+    rx: broadcast::Receiver<T>,
+}
+
+impl<T: Clone> Subscriber<T> {
+    /// Next event, or `None` once the bus is gone. Lag is logged and skipped.
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!("Subscriber {} lagged, skipped {} events", self.name, skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use crate::utils::price_feed::{ PoolInfo, SOL_MINT };
+use crate::utils::raydium::NewPoolEvent;
+use rusqlite::{ params, Connection, Result };
+use std::collections::HashMap;
+use std::sync::{ Mutex, OnceLock, RwLock };
+
+static REGISTRY: OnceLock<PoolRegistry> = OnceLock::new();
+
+/// Raydium AMM v4 pool keys indexed by mint pair, persisted to SQLite so lookups
+/// after a restart are local. Populated from new-pool events and from on-chain
+/// discovery on a miss.
+pub struct PoolRegistry {
+    conn: Mutex<Connection>,
+    pools: RwLock<HashMap<(String, String), PoolInfo>>,
+}
+
+/// Order-independent key for a mint pair.
+fn pair_key(mint_a: &str, mint_b: &str) -> (String, String) {
+    let resolve = |mint: &str| if mint == "SOL" { SOL_MINT.to_string() } else { mint.to_string() };
+    let (a, b) = (resolve(mint_a), resolve(mint_b));
+    if a <= b { (a, b) } else { (b, a) }
+}
+
+impl PoolRegistry {
+    pub fn new() -> Result<Self> {
+        let conn = Connection::open("trades.db")?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS pools (
+                id TEXT PRIMARY KEY,
+                base_mint TEXT NOT NULL,
+                quote_mint TEXT NOT NULL,
+                base_vault TEXT NOT NULL,
+                quote_vault TEXT NOT NULL,
+                market_id TEXT NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+            []
+        )?;
+        conn.execute("CREATE INDEX IF NOT EXISTS pools_pair ON pools (base_mint, quote_mint)", [])?;
+
+        let mut pools = HashMap::new();
+        {
+            let mut stmt = conn.prepare(
+                "SELECT id, base_mint, quote_mint, base_vault, quote_vault, market_id FROM pools ORDER BY updated_at"
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok(PoolInfo {
+                    id: row.get(0)?,
+                    base_mint: row.get(1)?,
+                    quote_mint: row.get(2)?,
+                    base_vault: row.get(3)?,
+                    quote_vault: row.get(4)?,
+                    market_id: row.get(5)?,
+                })
+            })?;
+            // Later rows win, so the most recently confirmed pool is used for a pair
+            for pool in rows {
+                let pool = pool?;
+                pools.insert(pair_key(&pool.base_mint, &pool.quote_mint), pool);
+            }
+        }
+
+        Ok(PoolRegistry {
+            conn: Mutex::new(conn),
+            pools: RwLock::new(pools),
+        })
+    }
+
+    /// Loads the registry from disk and makes it available through `PoolRegistry::global`.
+    pub fn init() -> Result<&'static PoolRegistry> {
+        if let Some(registry) = REGISTRY.get() {
+            return Ok(registry);
+        }
+        let registry = PoolRegistry::new()?;
+        Ok(REGISTRY.get_or_init(|| registry))
+    }
+
+    pub fn global() -> Option<&'static PoolRegistry> {
+        REGISTRY.get()
+    }
+
+    pub fn get(&self, mint_a: &str, mint_b: &str) -> Option<PoolInfo> {
+        self.pools.read().unwrap().get(&pair_key(mint_a, mint_b)).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pools.read().unwrap().len()
+    }
+
+    /// Stores `pool` as the preferred pool for its pair.
+    pub fn insert(&self, pool: &PoolInfo) -> Result<()> {
+        self.persist(pool)?;
+        self.pools
+            .write()
+            .unwrap()
+            .insert(pair_key(&pool.base_mint, &pool.quote_mint), pool.clone());
+        Ok(())
+    }
+
+    /// Records a freshly created pool. An already known pair keeps its existing pool,
+    /// since a new pool for an established pair is usually the shallower one.
+    pub fn insert_new_pool(&self, event: &NewPoolEvent) -> Result<()> {
+        let key = pair_key(&event.base_mint, &event.quote_mint);
+        if self.pools.read().unwrap().contains_key(&key) {
+            return Ok(());
+        }
+        self.insert(&PoolInfo {
+            id: event.pool_id.clone(),
+            base_mint: event.base_mint.clone(),
+            quote_mint: event.quote_mint.clone(),
+            base_vault: event.base_vault.clone(),
+            quote_vault: event.quote_vault.clone(),
+            market_id: event.market_id.clone(),
+        })
+    }
+
+    fn persist(&self, pool: &PoolInfo) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO pools (id, base_mint, quote_mint, base_vault, quote_vault, market_id, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                pool.id,
+                pool.base_mint,
+                pool.quote_mint,
+                pool.base_vault,
+                pool.quote_vault,
+                pool.market_id,
+                chrono::Utc::now().to_rfc3339()
+            ]
+        )?;
+        Ok(())
+    }
+}
@@ -1,32 +1,157 @@
 use reqwest::Client;
 use serde::Deserialize;
 use std::env;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use log::{debug, error};
 use crate::utils::telegram::TelegramBot;
+use crate::utils::raydium;
+use crate::utils::pool_events::PoolEventBus;
+use crate::utils::pool_discovery::discover_pools;
+use crate::utils::ws_manager::{Subscription, WsEvent, WsManager};
 
-use lazy_static::lazy_static;
-use std::sync::Mutex;
+use crate::utils::pool_registry::PoolRegistry;
+use crate::utils::token_metadata::resolve_metadata;
+use std::collections::HashMap;
 
-lazy_static! {
-    static ref POOL_CACHE: Mutex<HashMap<String, PoolInfo>> = Mutex::new(HashMap::new());
+/// Jupiter v6 `/quote` response. Token amounts are encoded as decimal strings.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct JupiterQuoteResponse {
+    input_mint: String,
+    #[serde(deserialize_with = "de_u64_string")]
+    in_amount: u64,
+    output_mint: String,
+    #[serde(deserialize_with = "de_u64_string")]
+    out_amount: u64,
+    #[serde(deserialize_with = "de_u64_string")]
+    other_amount_threshold: u64,
+    swap_mode: String,
+    slippage_bps: u16,
+    #[serde(deserialize_with = "de_f64_string")]
+    price_impact_pct: f64,
+    route_plan: Vec<RoutePlanStep>,
+    #[serde(default)]
+    context_slot: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RoutePlanStep {
+    swap_info: SwapInfo,
+    percent: u8,
 }
 
-#[derive(Deserialize)]
-struct JupiterQuote {
-    data: Vec<QuoteData>,
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct SwapInfo {
+    amm_key: String,
+    #[serde(default)]
+    label: Option<String>,
+    input_mint: String,
+    output_mint: String,
+    #[serde(deserialize_with = "de_u64_string")]
+    in_amount: u64,
+    #[serde(deserialize_with = "de_u64_string")]
+    out_amount: u64,
+    #[serde(deserialize_with = "de_u64_string")]
+    fee_amount: u64,
+    fee_mint: String,
 }
 
-#[derive(Deserialize)]
-struct QuoteData {
-    out_amount: f64,
+fn de_u64_string<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value.parse().map_err(serde::de::Error::custom)
 }
 
-#[derive(Deserialize)]
-struct RaydiumPool {
-    id: String,
+fn de_f64_string<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    value.parse().map_err(serde::de::Error::custom)
 }
 
-#[derive(Deserialize, Debug)]
+/// A single AMM leg of a Jupiter route.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct RouteHop {
+    pub amm_key: String,
+    pub label: String,
+    pub input_mint: String,
+    pub output_mint: String,
+    pub in_amount: u64,
+    pub out_amount: u64,
+    pub fee_amount: u64,
+    pub fee_mint: String,
+    /// Share of the input routed through this hop.
+    pub percent: u8,
+}
+
+/// Typed swap quote. Amounts are in base units of the respective mints.
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct Quote {
+    pub input_mint: String,
+    pub output_mint: String,
+    pub in_amount: u64,
+    pub out_amount: u64,
+    /// Minimum output after slippage (`ExactIn`) or maximum input (`ExactOut`).
+    pub other_amount_threshold: u64,
+    pub swap_mode: String,
+    pub slippage_bps: u16,
+    /// Price impact as a fraction, e.g. 0.01 for 1%.
+    pub price_impact: f64,
+    pub hops: Vec<RouteHop>,
+    pub context_slot: Option<u64>,
+}
+
+impl Quote {
+    pub fn amm_labels(&self) -> Vec<&str> {
+        self.hops.iter().map(|h| h.label.as_str()).collect()
+    }
+
+    /// Total fees charged by the route, summed per fee mint.
+    pub fn fees(&self) -> HashMap<String, u64> {
+        let mut fees = HashMap::new();
+        for hop in &self.hops {
+            *fees.entry(hop.fee_mint.clone()).or_insert(0) += hop.fee_amount;
+        }
+        fees
+    }
+
+    pub fn exceeds_impact(&self, max_price_impact: f64) -> bool {
+        self.price_impact > max_price_impact
+    }
+}
+
+impl From<JupiterQuoteResponse> for Quote {
+    fn from(response: JupiterQuoteResponse) -> Self {
+        Quote {
+            input_mint: response.input_mint,
+            output_mint: response.output_mint,
+            in_amount: response.in_amount,
+            out_amount: response.out_amount,
+            other_amount_threshold: response.other_amount_threshold,
+            swap_mode: response.swap_mode,
+            slippage_bps: response.slippage_bps,
+            price_impact: response.price_impact_pct,
+            hops: response
+                .route_plan
+                .into_iter()
+                .map(|step| RouteHop {
+                    amm_key: step.swap_info.amm_key,
+                    label: step.swap_info.label.unwrap_or_else(|| "Unknown".to_string()),
+                    input_mint: step.swap_info.input_mint,
+                    output_mint: step.swap_info.output_mint,
+                    in_amount: step.swap_info.in_amount,
+                    out_amount: step.swap_info.out_amount,
+                    fee_amount: step.swap_info.fee_amount,
+                    fee_mint: step.swap_info.fee_mint,
+                    percent: step.percent,
+                })
+                .collect(),
+            context_slot: response.context_slot,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct PoolInfo {
     pub id: String,
     pub base_mint: String,
@@ -38,56 +163,233 @@ pub struct PoolInfo {
 
 
 
-pub async fn get_price(token_mint: &str, vs_token: &str, telegram: &TelegramBot) -> Result<f64, Box<dyn std::error::Error>> {
+pub const SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+pub const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+/// Base units in one whole token of `mint` ("SOL" for wrapped SOL), from its decimals.
+pub async fn token_unit(mint: &str) -> Result<f64, Box<dyn std::error::Error>> {
+    let mint = resolve_mint(mint);
+    // Wrapped SOL is quoted everywhere, so it skips the metadata lookup
+    let decimals = if mint == SOL_MINT { 9 } else { resolve_metadata(mint).await?.decimals };
+    Ok(10f64.powi(decimals as i32))
+}
+
+/// Lamports in `sol`, rounded down.
+pub fn sol_to_lamports(sol: f64) -> u64 {
+    (sol * 1e9) as u64
+}
+
+fn resolve_mint(token: &str) -> &str {
+    if token == "SOL" { SOL_MINT } else { token }
+}
+
+/// Fetches a typed Jupiter quote for swapping `amount` base units of `input_mint`.
+pub async fn get_quote(input_mint: &str, output_mint: &str, amount: u64) -> Result<Quote, Box<dyn std::error::Error>> {
     let client = Client::new();
     let url = format!(
-        "{}?inputMint={}&outputMint={}&amount=1000000",
+        "{}?inputMint={}&outputMint={}&amount={}",
         env::var("JUPITER_API")?,
-        token_mint,
-        vs_token
+        resolve_mint(input_mint),
+        resolve_mint(output_mint),
+        amount
     );
-    let response = client.get(&url).send().await?.json::<JupiterQuote>().await?;
-    let price = response.data[0].out_amount / 1_000_000.0;
+    let response = client.get(&url).send().await?;
+    if !response.status().is_success() {
+        return Err(format!("Error fetching quote: HTTP {}", response.status()).into());
+    }
+    let quote: Quote = response.json::<JupiterQuoteResponse>().await?.into();
+    if quote.out_amount == 0 {
+        return Err(format!("Empty quote for {} -> {}", input_mint, output_mint).into());
+    }
+    Ok(quote)
+}
+
+/// Quotes a swap and rejects routes whose price impact exceeds `MAX_PRICE_IMPACT` (default 2%).
+pub async fn get_checked_quote(
+    input_mint: &str,
+    output_mint: &str,
+    amount: u64,
+    telegram: &TelegramBot
+) -> Result<Quote, Box<dyn std::error::Error>> {
+    let max_price_impact = env::var("MAX_PRICE_IMPACT")
+        .ok()
+        .and_then(|v| v.parse::<f64>().ok())
+        .unwrap_or(0.02);
+    let quote = get_quote(input_mint, output_mint, amount).await?;
+    if quote.exceeds_impact(max_price_impact) {
+        let error_msg = format!(
+            "Rejected route {} -> {}: price impact {:.2}% via {}",
+            input_mint,
+            output_mint,
+            quote.price_impact * 100.0,
+            quote.amm_labels().join(" > ")
+        );
+        telegram.send_message(&error_msg).await?;
+        return Err(error_msg.into());
+    }
+    debug!("Quote {} -> {}: impact {:.2}%, fees {:?}", input_mint, output_mint, quote.price_impact * 100.0, quote.fees());
+    Ok(quote)
+}
+
+async fn fetch_out_amount(input_mint: &str, output_mint: &str, amount: u64) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(get_quote(input_mint, output_mint, amount).await?.out_amount as f64)
+}
+
+/// Quote price without a Telegram notification.
+pub async fn fetch_price(token_mint: &str, vs_token: &str) -> Result<f64, Box<dyn std::error::Error>> {
+    let unit = token_unit(token_mint).await?;
+    let vs_unit = token_unit(vs_token).await?;
+    Ok(fetch_out_amount(token_mint, vs_token, unit as u64).await? / vs_unit)
+}
+
+/// Independent price from the Jupiter Price API, used to confirm suspicious quotes.
+pub async fn fetch_reference_price(token_mint: &str, vs_token: &str) -> Result<f64, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let base = env::var("JUPITER_PRICE_API").unwrap_or_else(|_| "https://api.jup.ag/price/v2".to_string());
+    let url = format!("{}?ids={}&vsToken={}", base, token_mint, resolve_mint(vs_token));
+    let response = client.get(&url).send().await?.json::<serde_json::Value>().await?;
+    let price = response["data"][token_mint]["price"]
+        .as_str()
+        .and_then(|p| p.parse::<f64>().ok())
+        .ok_or_else(|| format!("No reference price for {}", token_mint))?;
+    Ok(price)
+}
+
+pub async fn get_price(token_mint: &str, vs_token: &str, telegram: &TelegramBot) -> Result<f64, Box<dyn std::error::Error>> {
+    let price = fetch_price(token_mint, vs_token).await?;
     telegram
         .send_message(&format!("Price for {}: {} SOL", token_mint, price))
         .await?;
     Ok(price)
 }
 
-pub async fn monitor_new_pools(telegram: &TelegramBot, tx: tokio::sync::mpsc::Sender<String>) -> Result<(), Box<dyn std::error::Error>> {
+/// SOL/USD reference rate, quoted as 1 SOL (in lamports) against USDC (6 decimals).
+pub async fn get_sol_usd_price() -> Result<f64, Box<dyn std::error::Error>> {
+    let usdc = fetch_out_amount(SOL_MINT, USDC_MINT, 1_000_000_000).await? / 1_000_000.0;
+    if usdc <= 0.0 {
+        return Err("Invalid SOL/USD quote".into());
+    }
+    Ok(usdc)
+}
+
+/// Most pool creations fetched when backfilling one reconnect gap.
+const MAX_BACKFILL: usize = 500;
+
+/// Streams Raydium AMM v4 `initialize2` transactions and forwards each decoded pool.
+/// The connection is kept alive by `WsManager`; pools created during a reconnect
+/// gap are backfilled in the background so live notifications keep flowing.
+pub async fn monitor_new_pools(telegram: &TelegramBot, bus: PoolEventBus) -> Result<(), Box<dyn std::error::Error>> {
     let ws_url = env::var("RPC_WEBSOCKET")?;
-    let (mut ws_stream, _) = connect_async(&ws_url).await?;
-    // Subscribe to Raydium pool creation events (simplified; requires specific subscription)
-    ws_stream.send(Message::Text(r#"{"method":"subscribe","params":{"accounts":["RaydiumProgramId"]}}"#.to_string())).await?;
-
-    while let Some(message) = ws_stream.next().await {
-        match message? {
-            Message::Text(data) => {
-                // Parse WebSocket data for new pools (assumes Raydium event format)
-                let pool_id = parse_pool_id(&data).unwrap_or_default();
-                if !pool_id.is_empty() {
-                    telegram.send_message(&format!("Detected new pool: {}", pool_id)).await?;
-                    tx.send(pool_id).await?;
+    let manager = WsManager::new(
+        ws_url,
+        vec![Subscription {
+            name: "raydium_initialize2".to_string(),
+            request: raydium::logs_subscribe_request,
+        }],
+    );
+    let (event_tx, mut event_rx) = tokio::sync::mpsc::channel::<WsEvent>(1000);
+    tokio::spawn(manager.run(event_tx));
+
+    while let Some(event) = event_rx.recv().await {
+        match event {
+            WsEvent::Notification { result, .. } => {
+                if let Some((signature, slot)) = raydium::parse_initialize2_notification(&result)
+                    && let Err(e) = forward_new_pool(telegram, &bus, &signature, slot).await
+                {
+                    error!("Failed to forward pool from {}: {}", signature, e);
                 }
             }
-            _ => {}
+            WsEvent::Gap { last_slot, resumed_slot, .. } => {
+                tokio::spawn(backfill_new_pools(telegram.clone(), bus.clone(), last_slot, resumed_slot));
+            }
         }
     }
     Ok(())
 }
 
-fn parse_pool_id(data: &str) -> Option<String> {
-    serde_json::from_str::<serde_json::Value>(data)
-        .ok()
-        .and_then(|v| v["result"]["value"]["pubkey"].as_str().map(String::from))
+/// Forwards pools created in slots strictly between `last_slot` and `resumed_slot`.
+async fn backfill_new_pools(telegram: TelegramBot, bus: PoolEventBus, last_slot: u64, resumed_slot: u64) {
+    let found = raydium::pool_creations_between(last_slot, resumed_slot, MAX_BACKFILL)
+        .await
+        .map_err(|e| e.to_string());
+    let (signatures, truncated) = match found {
+        Ok(found) => found,
+        Err(e) => {
+            error!("Backfill of slots {}..{} failed: {}", last_slot, resumed_slot, e);
+            let _ = telegram
+                .send_message(&format!("Pool monitor could not backfill slots {}..{}: {}", last_slot, resumed_slot, e))
+                .await;
+            return;
+        }
+    };
+    let mut message = format!(
+        "Pool monitor reconnected, backfilling {} pools from slots {}..{}",
+        signatures.len(), last_slot, resumed_slot
+    );
+    if truncated {
+        message.push_str(&format!(". Only the newest {} were fetched, older pools in the gap were skipped", MAX_BACKFILL));
+    }
+    if let Err(e) = telegram.send_message(&message).await {
+        error!("Failed to send backfill notice: {}", e);
+    }
+    for (signature, slot) in signatures {
+        if let Err(e) = forward_new_pool(&telegram, &bus, &signature, slot).await {
+            error!("Failed to forward backfilled pool from {}: {}", signature, e);
+        }
+    }
 }
 
+async fn forward_new_pool(
+    telegram: &TelegramBot,
+    bus: &PoolEventBus,
+    signature: &str,
+    slot: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let event = match raydium::fetch_new_pool_event(signature, slot).await {
+        Ok(event) => event,
+        Err(e) => {
+            debug!("No new pool decoded from {}: {}", signature, e);
+            return Ok(());
+        }
+    };
+    telegram
+        .send_message(&format!(
+            "Detected new pool {} ({} / {}), opens at {}",
+            event.pool_id, event.base_mint, event.quote_mint, event.open_time
+        ))
+        .await?;
+    bus.publish(event);
+    Ok(())
+}
 
 pub async fn get_pool_keys(
     token_mint: &str,
     vs_token: &str,
     telegram: &TelegramBot,
 ) -> Result<PoolInfo, Box<dyn std::error::Error>> {
+    let registry = PoolRegistry::global();
+    if let Some(pool) = registry.and_then(|r| r.get(token_mint, vs_token)) {
+        return Ok(pool);
+    }
+
+    // Prefer the most liquid AMM v4 pool found on-chain; fall back to the Raydium REST listing
+    let discovered = match discover_pools(token_mint, vs_token).await {
+        Ok(pools) => pools.iter().find_map(|p| p.amm_v4_pool_info()),
+        Err(e) => {
+            debug!("On-chain pool discovery failed for {}: {}", token_mint, e);
+            None
+        }
+    };
+    if let Some(pool) = discovered {
+        if let Some(registry) = registry {
+            registry.insert(&pool)?;
+        }
+        telegram
+            .send_message(&format!("Found pool for {}/{}: {}", token_mint, vs_token, pool.id))
+            .await?;
+        return Ok(pool);
+    }
+
     let client = reqwest::Client::new();
     let url = env::var("RAYDIUM_POOL_API").map_err(|_| "Missing RAYDIUM_POOL_API in .env")?;
     
@@ -109,19 +411,146 @@ pub async fn get_pool_keys(
         .await
         .map_err(|e| format!("Failed to parse pool data: {}", e))?;
 
+    let vs_mint = resolve_mint(vs_token);
     let pool = pools
         .into_iter()
         .find(|p| {
-            (p.base_mint == token_mint && p.quote_mint == vs_token) ||
-            (p.base_mint == vs_token && p.quote_mint == token_mint)
+            (p.base_mint == token_mint && p.quote_mint == vs_mint) ||
+            (p.base_mint == vs_mint && p.quote_mint == token_mint)
         })
         .ok_or_else(|| {
             format!("No pool found for {}/{}", token_mint, vs_token)
         })?;
+    if let Some(registry) = registry {
+        registry.insert(&pool)?;
+    }
 
     telegram
         .send_message(&format!("Found pool for {}/{}: {}", token_mint, vs_token, pool.id))
         .await?;
 
     Ok(pool)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recorded `/v6/quote` response for 0.1 SOL -> BONK routed through two AMMs.
+    const SPLIT_ROUTE: &str = r#"{
+        "inputMint": "So11111111111111111111111111111111111111112",
+        "inAmount": "100000000",
+        "outputMint": "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263",
+        "outAmount": "702317839822",
+        "otherAmountThreshold": "698806250623",
+        "swapMode": "ExactIn",
+        "slippageBps": 50,
+        "platformFee": null,
+        "priceImpactPct": "0.0012",
+        "routePlan": [
+            {
+                "swapInfo": {
+                    "ammKey": "Bzc9NZfMqkXR6fz1DBph7BDf9BroyEf6pnzESP7v5iiw",
+                    "label": "Raydium CLMM",
+                    "inputMint": "So11111111111111111111111111111111111111112",
+                    "outputMint": "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263",
+                    "inAmount": "70000000",
+                    "outAmount": "491640011201",
+                    "feeAmount": "17500",
+                    "feeMint": "So11111111111111111111111111111111111111112"
+                },
+                "percent": 70
+            },
+            {
+                "swapInfo": {
+                    "ammKey": "HVNwzt7Pxfu76KHCMQPTLuTCLTm6WnQ1esLv4eizseSv",
+                    "label": "Whirlpool",
+                    "inputMint": "So11111111111111111111111111111111111111112",
+                    "outputMint": "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263",
+                    "inAmount": "30000000",
+                    "outAmount": "210677828621",
+                    "feeAmount": "9000",
+                    "feeMint": "So11111111111111111111111111111111111111112"
+                },
+                "percent": 30
+            }
+        ],
+        "contextSlot": 287419350,
+        "timeTaken": 0.004158242
+    }"#;
+
+    /// Single-hop response without `contextSlot` or an AMM `label`.
+    const MINIMAL: &str = r#"{
+        "inputMint": "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263",
+        "inAmount": "1000000",
+        "outputMint": "So11111111111111111111111111111111111111112",
+        "outAmount": "142",
+        "otherAmountThreshold": "141",
+        "swapMode": "ExactIn",
+        "slippageBps": 100,
+        "priceImpactPct": "0.0345",
+        "routePlan": [
+            {
+                "swapInfo": {
+                    "ammKey": "58oQChx4yWmvKdwLLZzBi4ChoCc2fqCUWBkwMihLYQo2",
+                    "inputMint": "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263",
+                    "outputMint": "So11111111111111111111111111111111111111112",
+                    "inAmount": "1000000",
+                    "outAmount": "142",
+                    "feeAmount": "2500",
+                    "feeMint": "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263"
+                },
+                "percent": 100
+            }
+        ]
+    }"#;
+
+    fn parse(json: &str) -> Quote {
+        serde_json::from_str::<JupiterQuoteResponse>(json).unwrap().into()
+    }
+
+    #[test]
+    fn parses_a_split_route() {
+        let quote = parse(SPLIT_ROUTE);
+        assert_eq!(quote.input_mint, SOL_MINT);
+        assert_eq!(quote.in_amount, 100_000_000);
+        assert_eq!(quote.out_amount, 702_317_839_822);
+        assert_eq!(quote.other_amount_threshold, 698_806_250_623);
+        assert_eq!(quote.swap_mode, "ExactIn");
+        assert_eq!(quote.slippage_bps, 50);
+        assert!((quote.price_impact - 0.0012).abs() < 1e-12);
+        assert_eq!(quote.context_slot, Some(287_419_350));
+        assert_eq!(quote.amm_labels(), vec!["Raydium CLMM", "Whirlpool"]);
+        assert_eq!(quote.hops[0].percent, 70);
+        assert_eq!(quote.hops[1].in_amount, 30_000_000);
+        assert_eq!(quote.fees().get(SOL_MINT), Some(&26_500));
+    }
+
+    #[test]
+    fn defaults_absent_optional_fields() {
+        let quote = parse(MINIMAL);
+        assert_eq!(quote.context_slot, None);
+        assert_eq!(quote.amm_labels(), vec!["Unknown"]);
+        assert_eq!(quote.fees().get("DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263"), Some(&2_500));
+    }
+
+    #[test]
+    fn checks_price_impact() {
+        let quote = parse(MINIMAL);
+        assert!(quote.exceeds_impact(0.02));
+        assert!(!quote.exceeds_impact(0.05));
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        let json = SPLIT_ROUTE.replace(r#""outAmount": "702317839822""#, r#""outAmount": "7.02e11""#);
+        assert!(serde_json::from_str::<JupiterQuoteResponse>(&json).is_err());
+        let json = SPLIT_ROUTE.replace(r#""inAmount": "100000000""#, r#""inAmount": 100000000"#);
+        assert!(serde_json::from_str::<JupiterQuoteResponse>(&json).is_err());
+    }
+
+    #[test]
+    fn rejects_a_missing_route_plan() {
+        let json = MINIMAL.replace("routePlan", "routes");
+        assert!(serde_json::from_str::<JupiterQuoteResponse>(&json).is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use crate::utils::price_feed::{ get_quote, fetch_reference_price, token_unit };
+use crate::utils::slot_clock::SlotClock;
+use crate::utils::telegram::TelegramBot;
+use std::collections::{ HashMap, VecDeque };
+use std::env;
+use std::sync::{ Arc, Mutex };
+use tokio::time::{ Duration, Instant };
+
+/// Sits between the price feed and the strategies. Rejects non-positive quotes and
+/// quotes whose context slot is older than `max_age`, and pauses a token when a move exceeds `anomaly_sigma` standard
+/// deviations of recent returns and a second source does not confirm it.
+#[derive(Clone)]
+pub struct PriceGuard {
+    max_age: Duration,
+    anomaly_sigma: f64,
+    window: usize,
+    min_samples: usize,
+    pause_duration: Duration,
+    /// Largest relative difference between primary and reference price that still confirms a move.
+    confirm_tolerance: f64,
+    history: Arc<Mutex<HashMap<String, VecDeque<f64>>>>,
+    paused: Arc<Mutex<HashMap<String, Instant>>>,
+    slot_clock: SlotClock,
+}
+
+impl PriceGuard {
+    pub fn new(slot_clock: SlotClock) -> Self {
+        PriceGuard {
+            max_age: Duration::from_secs(env_or("PRICE_MAX_AGE_SECS", 10)),
+            anomaly_sigma: env_or("PRICE_ANOMALY_SIGMA", 4.0),
+            window: 30,
+            min_samples: 10,
+            pause_duration: Duration::from_secs(env_or("PRICE_PAUSE_SECS", 300)),
+            confirm_tolerance: 0.05,
+            history: Arc::new(Mutex::new(HashMap::new())),
+            paused: Arc::new(Mutex::new(HashMap::new())),
+            slot_clock,
+        }
+    }
+
+    /// Returns a validated price, or an error if the quote is unusable or the token is paused.
+    pub async fn get_price(
+        &self,
+        token_mint: &str,
+        vs_token: &str,
+        telegram: &TelegramBot
+    ) -> Result<f64, Box<dyn std::error::Error>> {
+        if self.is_paused(token_mint) {
+            return Err(format!("Trading paused for {} after a price anomaly", token_mint).into());
+        }
+
+        let unit = token_unit(token_mint).await?;
+        let vs_unit = token_unit(vs_token).await?;
+        let quote = get_quote(token_mint, vs_token, unit as u64).await?;
+        self.check_age(token_mint, quote.context_slot, self.slot_clock.current_slot(), self.slot_clock.slot_ms())?;
+        let price = quote.out_amount as f64 / vs_unit;
+        if !price.is_finite() || price <= 0.0 {
+            return Err(format!("Invalid price for {}: {}", token_mint, price).into());
+        }
+
+        if let Some(sigma) = self.deviation(token_mint, price) && sigma > self.anomaly_sigma {
+            let confirmed = match fetch_reference_price(token_mint, vs_token).await {
+                Ok(reference) => ((price - reference) / reference).abs() <= self.confirm_tolerance,
+                Err(_) => false,
+            };
+            if !confirmed {
+                self.pause(token_mint);
+                telegram
+                    .send_message(&format!(
+                        "Price anomaly for {}: {} SOL is {:.1} sigma from recent moves and was not confirmed. Strategies paused for {}s",
+                        token_mint, price, sigma, self.pause_duration.as_secs()
+                    ))
+                    .await?;
+                return Err(format!("Unconfirmed price anomaly for {}", token_mint).into());
+            }
+        }
+
+        self.record(token_mint, price);
+        Ok(price)
+    }
+
+    pub fn is_paused(&self, token_mint: &str) -> bool {
+        let mut paused = self.paused.lock().unwrap();
+        match paused.get(token_mint) {
+            Some(until) if Instant::now() < *until => true,
+            Some(_) => {
+                paused.remove(token_mint);
+                false
+            }
+            None => false,
+        }
+    }
+
+    pub fn pause(&self, token_mint: &str) {
+        self.paused
+            .lock()
+            .unwrap()
+            .insert(token_mint.to_string(), Instant::now() + self.pause_duration);
+    }
+
+    pub fn resume(&self, token_mint: &str) {
+        self.paused.lock().unwrap().remove(token_mint);
+        // Old samples describe the regime that triggered the pause
+        self.history.lock().unwrap().remove(token_mint);
+    }
+
+    /// Rejects quotes computed more than `max_age` before `current_slot`. The age is
+    /// unknown until the slot clock has seen a slot, and then the quote is accepted.
+    fn check_age(&self, token_mint: &str, context_slot: Option<u64>, current_slot: u64, slot_ms: f64) -> Result<(), String> {
+        if current_slot == 0 {
+            return Ok(());
+        }
+        let context_slot = context_slot.ok_or_else(|| format!("Quote for {} has no context slot", token_mint))?;
+        let age = Duration::from_secs_f64(current_slot.saturating_sub(context_slot) as f64 * slot_ms / 1000.0);
+        if age > self.max_age {
+            return Err(format!(
+                "Stale price for {}: quoted at slot {}, {} slots ({:?}) behind",
+                token_mint, context_slot, current_slot - context_slot, age
+            ));
+        }
+        Ok(())
+    }
+
+    /// Size of the move to `price` in standard deviations of recent log returns.
+    fn deviation(&self, token_mint: &str, price: f64) -> Option<f64> {
+        let history = self.history.lock().unwrap();
+        let samples = history.get(token_mint)?;
+        if samples.len() < self.min_samples {
+            return None;
+        }
+        let returns: Vec<f64> = samples
+            .iter()
+            .zip(samples.iter().skip(1))
+            .map(|(a, b)| (b / a).ln())
+            .collect();
+        let mean = returns.iter().sum::<f64>() / returns.len() as f64;
+        let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / returns.len() as f64;
+        // Floor the volatility so a flat series does not flag every tick
+        let std_dev = variance.sqrt().max(0.001);
+        let last = *samples.back()?;
+        Some(((price / last).ln() - mean).abs() / std_dev)
+    }
+
+    fn record(&self, token_mint: &str, price: f64) {
+        let mut history = self.history.lock().unwrap();
+        let samples = history.entry(token_mint.to_string()).or_default();
+        samples.push_back(price);
+        while samples.len() > self.window {
+            samples.pop_front();
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn guard() -> PriceGuard {
+        PriceGuard::new(SlotClock::stopped_at(1_000))
+    }
+
+    #[test]
+    fn accepts_recent_quotes_and_rejects_old_ones() {
+        let guard = guard();
+        // 10s at 400ms per slot is 25 slots
+        assert!(guard.check_age("MINT", Some(990), 1_000, 400.0).is_ok());
+        assert!(guard.check_age("MINT", Some(975), 1_000, 400.0).is_ok());
+        assert!(guard.check_age("MINT", Some(974), 1_000, 400.0).is_err());
+        assert!(guard.check_age("MINT", None, 1_000, 400.0).is_err());
+    }
+
+    #[test]
+    fn skips_the_age_check_before_the_first_slot() {
+        assert!(guard().check_age("MINT", None, 0, 400.0).is_ok());
+    }
+
+    #[test]
+    fn deviation_needs_enough_samples() {
+        let guard = guard();
+        for i in 0..guard.min_samples - 1 {
+            guard.record("MINT", 1.0 + i as f64 * 0.001);
+        }
+        assert!(guard.deviation("MINT", 2.0).is_none());
+        guard.record("MINT", 1.01);
+        assert!(guard.deviation("MINT", 2.0).is_some());
+    }
+
+    #[test]
+    fn flags_jumps_far_outside_recent_moves() {
+        let guard = guard();
+        let mut price = 1.0;
+        for i in 0..20 {
+            price *= if i % 2 == 0 { 1.002 } else { 0.999 };
+            guard.record("MINT", price);
+        }
+        assert!(guard.deviation("MINT", price * 1.001).unwrap() < guard.anomaly_sigma);
+        assert!(guard.deviation("MINT", price * 1.5).unwrap() > guard.anomaly_sigma);
+    }
+
+    #[test]
+    fn flat_history_does_not_flag_small_moves() {
+        let guard = guard();
+        for _ in 0..20 {
+            guard.record("MINT", 1.0);
+        }
+        // The 0.1% volatility floor keeps a 0.2% move at 2 sigma
+        let sigma = guard.deviation("MINT", 1.002).unwrap();
+        assert!(sigma < guard.anomaly_sigma, "sigma {}", sigma);
+    }
+
+    #[test]
+    fn keeps_only_the_window() {
+        let guard = guard();
+        for i in 0..guard.window + 5 {
+            guard.record("MINT", i as f64 + 1.0);
+        }
+        let history = guard.history.lock().unwrap();
+        let samples = &history["MINT"];
+        assert_eq!(samples.len(), guard.window);
+        assert_eq!(samples[0], 6.0);
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let guard = guard();
+        guard.record("MINT", 1.0);
+        assert!(!guard.is_paused("MINT"));
+        guard.pause("MINT");
+        assert!(guard.is_paused("MINT"));
+        assert!(!guard.is_paused("OTHER"));
+        guard.resume("MINT");
+        assert!(!guard.is_paused("MINT"));
+        assert!(guard.history.lock().unwrap().get("MINT").is_none());
+    }
+
+    #[test]
+    fn pause_expires() {
+        let mut guard = guard();
+        guard.pause_duration = Duration::from_secs(0);
+        guard.pause("MINT");
+        assert!(!guard.is_paused("MINT"));
+    }
+}
@@ -0,0 +1,564 @@
+use crate::utils::pool_events::{ LaunchEventBus, PoolSubscriber };
+use crate::utils::telegram::TelegramBot;
+use crate::utils::ws_manager::{ Subscription, WsEvent, WsManager };
+use base64::{ engine::general_purpose::STANDARD, Engine };
+use serde_json::{ json, Value };
+use solana_sdk::{ hash::hash, pubkey::Pubkey };
+use std::collections::HashMap;
+use std::env;
+use tokio::time::{ Duration, Instant };
+
+pub const PUMP_FUN_PROGRAM: &str = "6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P";
+pub const PUMP_SWAP_PROGRAM: &str = "pAMMBay6oceH9fJKBRHGP5D4bD4sWpmSwMn52FMfXEA";
+/// Account that created Raydium pools for completed curves before PumpSwap existed.
+pub const PUMP_RAYDIUM_MIGRATION: &str = "39azUYFWPz3VHgKCf3VChUwbpURdCHRxjWVowf5jUJjg";
+
+/// Virtual token reserves of a fresh curve and the tokens sold before it completes.
+const INITIAL_VIRTUAL_TOKEN_RESERVES: u64 = 1_073_000_000_000_000;
+const CURVE_TOKEN_SUPPLY: u64 = 793_100_000_000_000;
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub enum PumpEvent {
+    Created {
+        mint: String,
+        name: String,
+        symbol: String,
+        uri: String,
+        bonding_curve: String,
+        creator: String,
+    },
+    Trade {
+        mint: String,
+        is_buy: bool,
+        sol_amount: u64,
+        token_amount: u64,
+        trader: String,
+        /// Share of the curve supply sold, 0.0 to 1.0.
+        progress: f64,
+    },
+    Completed {
+        mint: String,
+        bonding_curve: String,
+    },
+    /// Liquidity moved from the curve to an AMM pool.
+    Migrated {
+        mint: String,
+        pool: String,
+        venue: String,
+        quote_amount: u64,
+    },
+}
+
+struct CurveState {
+    symbol: Option<String>,
+    completed: bool,
+    updated_at: Instant,
+}
+
+/// Tracks the symbol and completion of each bonding curve from decoded events.
+pub struct BondingCurveTracker {
+    curves: HashMap<String, CurveState>,
+    max_age: Duration,
+}
+
+impl BondingCurveTracker {
+    pub fn new() -> Self {
+        BondingCurveTracker {
+            curves: HashMap::new(),
+            max_age: Duration::from_secs(24 * 60 * 60),
+        }
+    }
+
+    pub fn apply(&mut self, event: &PumpEvent) {
+        let mint = match event {
+            PumpEvent::Created { mint, .. }
+            | PumpEvent::Trade { mint, .. }
+            | PumpEvent::Completed { mint, .. }
+            | PumpEvent::Migrated { mint, .. } => mint,
+        };
+        let state = self.curves.entry(mint.clone()).or_insert(CurveState {
+            symbol: None,
+            completed: false,
+            updated_at: Instant::now(),
+        });
+        state.updated_at = Instant::now();
+        match event {
+            PumpEvent::Created { symbol, .. } => state.symbol = Some(symbol.clone()),
+            PumpEvent::Trade { .. } => {}
+            PumpEvent::Completed { .. } | PumpEvent::Migrated { .. } => state.completed = true,
+        }
+    }
+
+    pub fn symbol(&self, mint: &str) -> Option<&str> {
+        self.curves.get(mint).and_then(|s| s.symbol.as_deref())
+    }
+
+    pub fn is_completed(&self, mint: &str) -> bool {
+        self.curves.get(mint).map(|s| s.completed).unwrap_or(false)
+    }
+
+    /// Drops curves that have not traded within `max_age`.
+    pub fn prune(&mut self) {
+        let max_age = self.max_age;
+        self.curves.retain(|_, s| s.updated_at.elapsed() < max_age);
+    }
+}
+
+fn event_discriminator(name: &str) -> [u8; 8] {
+    let digest = hash(format!("event:{}", name).as_bytes()).to_bytes();
+    digest[..8].try_into().unwrap()
+}
+
+/// Minimal Borsh reader for Anchor event payloads.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset + len)?;
+        self.offset += len;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.take(2)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        Some(self.u8()? != 0)
+    }
+
+    fn pubkey(&mut self) -> Option<String> {
+        let bytes: [u8; 32] = self.take(32)?.try_into().ok()?;
+        Some(Pubkey::new_from_array(bytes).to_string())
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = u32::from_le_bytes(self.take(4)?.try_into().ok()?) as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+}
+
+/// Decodes an Anchor `Program data:` payload emitted by pump.fun or PumpSwap.
+/// Only the leading fields are read, so newer event versions with appended fields still decode.
+pub fn decode_event(program: &str, data: &[u8]) -> Option<PumpEvent> {
+    if data.len() < 8 {
+        return None;
+    }
+    let (discriminator, payload) = data.split_at(8);
+    let mut r = Reader { data: payload, offset: 0 };
+
+    if program == PUMP_FUN_PROGRAM {
+        if discriminator == event_discriminator("CreateEvent") {
+            return Some(PumpEvent::Created {
+                name: r.string()?,
+                symbol: r.string()?,
+                uri: r.string()?,
+                mint: r.pubkey()?,
+                bonding_curve: r.pubkey()?,
+                creator: r.pubkey()?,
+            });
+        }
+        if discriminator == event_discriminator("TradeEvent") {
+            let mint = r.pubkey()?;
+            let sol_amount = r.u64()?;
+            let token_amount = r.u64()?;
+            let is_buy = r.bool()?;
+            let trader = r.pubkey()?;
+            let _timestamp = r.u64()?;
+            let _virtual_sol_reserves = r.u64()?;
+            let virtual_token_reserves = r.u64()?;
+            let sold = INITIAL_VIRTUAL_TOKEN_RESERVES.saturating_sub(virtual_token_reserves);
+            return Some(PumpEvent::Trade {
+                mint,
+                is_buy,
+                sol_amount,
+                token_amount,
+                trader,
+                progress: (sold as f64 / CURVE_TOKEN_SUPPLY as f64).min(1.0),
+            });
+        }
+        if discriminator == event_discriminator("CompleteEvent") {
+            let _user = r.pubkey()?;
+            return Some(PumpEvent::Completed {
+                mint: r.pubkey()?,
+                bonding_curve: r.pubkey()?,
+            });
+        }
+    } else if program == PUMP_SWAP_PROGRAM && discriminator == event_discriminator("CreatePoolEvent") {
+        let _timestamp = r.u64()?;
+        let _index = r.u16()?;
+        let _creator = r.pubkey()?;
+        let base_mint = r.pubkey()?;
+        let _quote_mint = r.pubkey()?;
+        let _base_decimals = r.u8()?;
+        let _quote_decimals = r.u8()?;
+        let _base_amount_in = r.u64()?;
+        let quote_amount = r.u64()?;
+        for _ in 0..5 {
+            r.u64()?; // pool amounts, minimum/initial liquidity, lp amount out
+        }
+        let _pool_bump = r.u8()?;
+        return Some(PumpEvent::Migrated {
+            mint: base_mint,
+            pool: r.pubkey()?,
+            venue: "PumpSwap".to_string(),
+            quote_amount,
+        });
+    }
+    None
+}
+
+/// Decodes every event in a `logsNotification` result, attributing each
+/// `Program data:` line to the program currently on top of the invoke stack.
+pub fn decode_logs(result: &Value) -> Vec<PumpEvent> {
+    if !result["value"]["err"].is_null() {
+        return vec![];
+    }
+    let Some(logs) = result["value"]["logs"].as_array() else { return vec![] };
+    let mut stack: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+
+    for line in logs.iter().filter_map(|l| l.as_str()) {
+        if let Some(rest) = line.strip_prefix("Program data: ") {
+            if let (Some(program), Ok(data)) = (stack.last(), STANDARD.decode(rest)) {
+                events.extend(decode_event(program, &data));
+            }
+        } else if let Some(rest) = line.strip_prefix("Program ") {
+            let mut parts = rest.split_whitespace();
+            let (Some(program), Some(status)) = (parts.next(), parts.next()) else { continue };
+            match status {
+                "invoke" => stack.push(program),
+                "success" | "failed" => {
+                    stack.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    events
+}
+
+fn logs_subscribe_request(id: u64) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "logsSubscribe",
+        "params": [
+            { "mentions": [PUMP_FUN_PROGRAM] },
+            { "commitment": "confirmed" }
+        ]
+    }).to_string()
+}
+
+/// Streams pump.fun launches, trades, curve completions and migrations onto `launches`.
+/// Raydium migrations are recognised from `pools` by the pump.fun migration account.
+pub async fn monitor_pump_fun(
+    telegram: &TelegramBot,
+    launches: LaunchEventBus,
+    mut pools: PoolSubscriber
+) -> Result<(), Box<dyn std::error::Error>> {
+    let ws_url = env::var("RPC_WEBSOCKET")?;
+    let manager = WsManager::new(
+        ws_url,
+        vec![Subscription {
+            name: "pump_fun".to_string(),
+            request: logs_subscribe_request,
+        }],
+    );
+    let (event_tx, mut event_rx) = tokio::sync::mpsc::channel::<WsEvent>(1000);
+    tokio::spawn(manager.run(event_tx));
+
+    let mut tracker = BondingCurveTracker::new();
+    let mut prune = tokio::time::interval(Duration::from_secs(600));
+
+    loop {
+        let events = tokio::select! {
+            ws_event = event_rx.recv() => match ws_event {
+                Some(WsEvent::Notification { result, .. }) => decode_logs(&result),
+                Some(WsEvent::Gap { .. }) => continue,
+                None => return Ok(()),
+            },
+            pool = pools.recv() => match pool {
+                Some(pool) if pool.creator == PUMP_RAYDIUM_MIGRATION => {
+                    let mint = if pool.base_mint == crate::utils::price_feed::SOL_MINT {
+                        pool.quote_mint
+                    } else {
+                        pool.base_mint
+                    };
+                    vec![PumpEvent::Migrated {
+                        mint,
+                        pool: pool.pool_id,
+                        venue: "Raydium".to_string(),
+                        quote_amount: pool.init_quote_amount,
+                    }]
+                }
+                Some(_) => continue,
+                None => return Ok(()),
+            },
+            _ = prune.tick() => {
+                tracker.prune();
+                continue;
+            }
+        };
+
+        for event in events {
+            let message = match &event {
+                // A curve completes once; repeated logs of the same completion are not reported again
+                PumpEvent::Completed { mint, .. } if !tracker.is_completed(mint) => {
+                    let symbol = tracker.symbol(mint).unwrap_or("unknown");
+                    Some(format!("pump.fun curve completed for {} ({})", symbol, mint))
+                }
+                PumpEvent::Migrated { mint, pool, venue, .. } => {
+                    let symbol = tracker.symbol(mint).unwrap_or("unknown");
+                    Some(format!("{} ({}) migrated to {} pool {}", symbol, mint, venue, pool))
+                }
+                _ => None,
+            };
+            tracker.apply(&event);
+            if let Some(message) = message
+                && let Err(e) = telegram.send_message(&message).await.map_err(|e| e.to_string())
+            {
+                log::warn!("Failed to report pump.fun event: {}", e);
+            }
+            launches.publish(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINT: &str = "7GCihgDB8fe6KNjn2MYtkzZcRjQy3t9GHdC8uHYmW2hr";
+    const BONDING_CURVE: &str = "Dc5xTBpUDY6r3ix9WGzcZjfFSy46ZNBmcoPfsbGTtszN";
+    const CREATOR: &str = "GThUX1Atko4tqhN2NaiTazWSeFWMuiUvfFnyJyUghFMJ";
+    const POOL: &str = "58oQChx4yWmvKdwLLZzBi4ChoCc2fqCUWBkwMihLYQo2";
+
+    /// Borsh writer for event payloads, the counterpart of `Reader`.
+    struct Payload(Vec<u8>);
+
+    impl Payload {
+        fn new(event: &str) -> Self {
+            Payload(event_discriminator(event).to_vec())
+        }
+
+        fn u8(mut self, value: u8) -> Self {
+            self.0.push(value);
+            self
+        }
+
+        fn u16(mut self, value: u16) -> Self {
+            self.0.extend(value.to_le_bytes());
+            self
+        }
+
+        fn u64(mut self, value: u64) -> Self {
+            self.0.extend(value.to_le_bytes());
+            self
+        }
+
+        fn pubkey(mut self, value: &str) -> Self {
+            self.0.extend(value.parse::<Pubkey>().unwrap().to_bytes());
+            self
+        }
+
+        fn string(mut self, value: &str) -> Self {
+            self.0.extend((value.len() as u32).to_le_bytes());
+            self.0.extend(value.as_bytes());
+            self
+        }
+    }
+
+    fn create_event() -> Vec<u8> {
+        Payload::new("CreateEvent")
+            .string("Pengu Inu")
+            .string("PINU")
+            .string("https://ipfs.io/ipfs/QmPinu")
+            .pubkey(MINT)
+            .pubkey(BONDING_CURVE)
+            .pubkey(CREATOR)
+            .0
+    }
+
+    fn trade_event(virtual_token_reserves: u64) -> Vec<u8> {
+        Payload::new("TradeEvent")
+            .pubkey(MINT)
+            .u64(1_500_000_000)
+            .u64(40_000_000_000_000)
+            .u8(1)
+            .pubkey(CREATOR)
+            .u64(1_718_900_000)
+            .u64(45_000_000_000)
+            .u64(virtual_token_reserves)
+            .0
+    }
+
+    fn complete_event() -> Vec<u8> {
+        Payload::new("CompleteEvent").pubkey(CREATOR).pubkey(MINT).pubkey(BONDING_CURVE).0
+    }
+
+    fn create_pool_event() -> Vec<u8> {
+        let mut payload = Payload::new("CreatePoolEvent")
+            .u64(1_718_900_000)
+            .u16(0)
+            .pubkey(CREATOR)
+            .pubkey(MINT)
+            .pubkey(crate::utils::price_feed::SOL_MINT)
+            .u8(6)
+            .u8(9)
+            .u64(206_900_000_000_000)
+            .u64(84_990_359_346);
+        for _ in 0..5 {
+            payload = payload.u64(1);
+        }
+        payload.u8(255).pubkey(POOL).0
+    }
+
+    /// `logsNotification` result with `lines` as its logs.
+    fn notification(lines: &[String], err: Value) -> Value {
+        json!({
+            "context": { "slot": 274035112 },
+            "value": { "signature": "5VERv8NM", "err": err, "logs": lines }
+        })
+    }
+
+    fn data_line(data: &[u8]) -> String {
+        format!("Program data: {}", STANDARD.encode(data))
+    }
+
+    #[test]
+    fn decodes_create_events() {
+        let Some(PumpEvent::Created { mint, name, symbol, uri, bonding_curve, creator }) =
+            decode_event(PUMP_FUN_PROGRAM, &create_event())
+        else {
+            panic!("expected a create event");
+        };
+        assert_eq!(mint, MINT);
+        assert_eq!(name, "Pengu Inu");
+        assert_eq!(symbol, "PINU");
+        assert_eq!(uri, "https://ipfs.io/ipfs/QmPinu");
+        assert_eq!(bonding_curve, BONDING_CURVE);
+        assert_eq!(creator, CREATOR);
+    }
+
+    #[test]
+    fn decodes_trades_with_curve_progress() {
+        let half_sold = INITIAL_VIRTUAL_TOKEN_RESERVES - CURVE_TOKEN_SUPPLY / 2;
+        let Some(PumpEvent::Trade { mint, is_buy, sol_amount, token_amount, trader, progress }) =
+            decode_event(PUMP_FUN_PROGRAM, &trade_event(half_sold))
+        else {
+            panic!("expected a trade event");
+        };
+        assert_eq!(mint, MINT);
+        assert!(is_buy);
+        assert_eq!(sol_amount, 1_500_000_000);
+        assert_eq!(token_amount, 40_000_000_000_000);
+        assert_eq!(trader, CREATOR);
+        assert!((progress - 0.5).abs() < 1e-9);
+
+        // Reserves below the completion point cap progress at the full curve
+        let Some(PumpEvent::Trade { progress, .. }) = decode_event(PUMP_FUN_PROGRAM, &trade_event(0)) else {
+            panic!("expected a trade event");
+        };
+        assert_eq!(progress, 1.0);
+    }
+
+    #[test]
+    fn decodes_completions_and_pump_swap_migrations() {
+        let Some(PumpEvent::Completed { mint, bonding_curve }) = decode_event(PUMP_FUN_PROGRAM, &complete_event()) else {
+            panic!("expected a complete event");
+        };
+        assert_eq!(mint, MINT);
+        assert_eq!(bonding_curve, BONDING_CURVE);
+
+        let Some(PumpEvent::Migrated { mint, pool, venue, quote_amount }) =
+            decode_event(PUMP_SWAP_PROGRAM, &create_pool_event())
+        else {
+            panic!("expected a migration");
+        };
+        assert_eq!(mint, MINT);
+        assert_eq!(pool, POOL);
+        assert_eq!(venue, "PumpSwap");
+        assert_eq!(quote_amount, 84_990_359_346);
+    }
+
+    #[test]
+    fn decodes_events_with_appended_fields() {
+        let mut data = complete_event();
+        data.extend([0u8; 16]);
+        assert!(matches!(decode_event(PUMP_FUN_PROGRAM, &data), Some(PumpEvent::Completed { .. })));
+    }
+
+    #[test]
+    fn rejects_truncated_and_foreign_payloads() {
+        let data = create_event();
+        assert!(decode_event(PUMP_FUN_PROGRAM, &data[..data.len() - 1]).is_none());
+        assert!(decode_event(PUMP_FUN_PROGRAM, &data[..4]).is_none());
+        // Events are only decoded for the program that defines them
+        assert!(decode_event(PUMP_SWAP_PROGRAM, &data).is_none());
+        assert!(decode_event(PUMP_FUN_PROGRAM, &create_pool_event()).is_none());
+    }
+
+    #[test]
+    fn attributes_program_data_to_the_invoking_program() {
+        let lines = vec![
+            "Program ComputeBudget111111111111111111111111111111 invoke [1]".to_string(),
+            "Program ComputeBudget111111111111111111111111111111 success".to_string(),
+            format!("Program {} invoke [1]", PUMP_FUN_PROGRAM),
+            "Program log: Instruction: Create".to_string(),
+            "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA invoke [2]".to_string(),
+            // Emitted by the token program, so not a pump.fun event
+            data_line(&complete_event()),
+            "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA success".to_string(),
+            data_line(&create_event()),
+            format!("Program {} invoke [2]", PUMP_SWAP_PROGRAM),
+            data_line(&create_pool_event()),
+            format!("Program {} success", PUMP_SWAP_PROGRAM),
+            data_line(&trade_event(INITIAL_VIRTUAL_TOKEN_RESERVES)),
+            format!("Program {} consumed 93417 of 199700 compute units", PUMP_FUN_PROGRAM),
+            format!("Program {} success", PUMP_FUN_PROGRAM),
+        ];
+        let events = decode_logs(&notification(&lines, Value::Null));
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], PumpEvent::Created { .. }));
+        assert!(matches!(events[1], PumpEvent::Migrated { .. }));
+        assert!(matches!(events[2], PumpEvent::Trade { progress, .. } if progress == 0.0));
+    }
+
+    #[test]
+    fn ignores_failed_transactions() {
+        let lines = vec![
+            format!("Program {} invoke [1]", PUMP_FUN_PROGRAM),
+            data_line(&create_event()),
+            format!("Program {} failed: custom program error: 0x1771", PUMP_FUN_PROGRAM),
+        ];
+        let result = notification(&lines, json!({ "InstructionError": [0, { "Custom": 6001 }] }));
+        assert!(decode_logs(&result).is_empty());
+    }
+
+    #[test]
+    fn tracks_symbols_and_completion() {
+        let mut tracker = BondingCurveTracker::new();
+        for data in [create_event(), trade_event(INITIAL_VIRTUAL_TOKEN_RESERVES)] {
+            tracker.apply(&decode_event(PUMP_FUN_PROGRAM, &data).unwrap());
+        }
+        assert_eq!(tracker.symbol(MINT), Some("PINU"));
+        assert!(!tracker.is_completed(MINT));
+
+        tracker.apply(&decode_event(PUMP_FUN_PROGRAM, &complete_event()).unwrap());
+        assert!(tracker.is_completed(MINT));
+        assert!(!tracker.is_completed(POOL));
+    }
+}
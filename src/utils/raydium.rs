@@ -0,0 +1,458 @@
+use crate::utils::rpc;
+use serde_json::{ json, Value };
+use solana_sdk::{ instruction::{ AccountMeta, Instruction }, pubkey::Pubkey };
+use tokio::time::{ sleep, Duration };
+
+pub const RAYDIUM_AMM_V4: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
+/// PDA that owns every AMM v4 pool's vaults.
+pub const RAYDIUM_AMM_V4_AUTHORITY: &str = "5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1";
+
+/// Instruction tag of `initialize2` in the Raydium AMM v4 program.
+const INITIALIZE2_TAG: u8 = 1;
+/// Instruction tag of `swap_base_in` in the Raydium AMM v4 program.
+const SWAP_BASE_IN_TAG: u8 = 9;
+
+/// A Raydium AMM v4 pool created by an `initialize2` instruction.
+#[derive(Debug, Clone)]
+pub struct NewPoolEvent {
+    pub signature: String,
+    pub slot: u64,
+    pub pool_id: String,
+    pub base_mint: String,
+    pub quote_mint: String,
+    pub base_vault: String,
+    pub quote_vault: String,
+    pub lp_mint: String,
+    pub market_id: String,
+    pub creator: String,
+    /// Unix timestamp before which swaps against the pool fail.
+    pub open_time: u64,
+    /// Initial liquidity in base units of the base and quote mints.
+    pub init_base_amount: u64,
+    pub init_quote_amount: u64,
+}
+
+/// `logsSubscribe` request for transactions mentioning the Raydium AMM v4 program.
+pub fn logs_subscribe_request(id: u64) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "method": "logsSubscribe",
+        "params": [
+            { "mentions": [RAYDIUM_AMM_V4] },
+            { "commitment": "confirmed" }
+        ]
+    }).to_string()
+}
+
+/// Signature of a `logsNotification` result, whether or not it is an `initialize2`.
+pub fn notification_signature(result: &Value) -> Option<String> {
+    result["value"]["signature"].as_str().map(String::from)
+}
+
+/// Returns the signature and slot of a successful `logsNotification` result whose logs contain `initialize2`.
+pub fn parse_initialize2_notification(result: &Value) -> Option<(String, u64)> {
+    if !result["value"]["err"].is_null() {
+        return None;
+    }
+    let logs = result["value"]["logs"].as_array()?;
+    let is_initialize2 = logs
+        .iter()
+        .filter_map(|l| l.as_str())
+        .any(|l| l.contains("initialize2"));
+    if !is_initialize2 {
+        return None;
+    }
+    let signature = notification_signature(result)?;
+    let slot = result["context"]["slot"].as_u64().unwrap_or_default();
+    Some((signature, slot))
+}
+
+/// Accounts an AMM v4 swap touches: the pool's own and those of its OpenBook market.
+#[derive(Debug, Clone)]
+pub struct AmmV4SwapKeys {
+    pub amm_id: Pubkey,
+    pub open_orders: Pubkey,
+    pub target_orders: Pubkey,
+    pub base_vault: Pubkey,
+    pub quote_vault: Pubkey,
+    pub market_program: Pubkey,
+    pub market_id: Pubkey,
+    pub market_bids: Pubkey,
+    pub market_asks: Pubkey,
+    pub market_event_queue: Pubkey,
+    pub market_base_vault: Pubkey,
+    pub market_quote_vault: Pubkey,
+    pub market_vault_signer: Pubkey,
+}
+
+fn read_key(data: &[u8], offset: usize) -> Option<Pubkey> {
+    Some(Pubkey::new_from_array(data.get(offset..offset + 32)?.try_into().ok()?))
+}
+
+impl AmmV4SwapKeys {
+    /// Market id referenced by an AMM v4 pool account.
+    pub fn market_of(amm: &[u8]) -> Option<Pubkey> {
+        read_key(amm, 528)
+    }
+
+    /// Decodes the pool account (752 bytes) and its OpenBook market account.
+    pub fn decode(amm_id: Pubkey, amm: &[u8], market: &[u8]) -> Option<Self> {
+        if amm.len() < 752 {
+            return None;
+        }
+        let market_id = read_key(amm, 528)?;
+        let market_program = read_key(amm, 560)?;
+        // Market layout v3: 5 byte "serum" padding, flags, own address, then the vault signer nonce
+        let nonce = u64::from_le_bytes(market.get(45..53)?.try_into().ok()?);
+        let market_vault_signer =
+            Pubkey::create_program_address(&[market_id.as_ref(), &nonce.to_le_bytes()], &market_program).ok()?;
+        Some(AmmV4SwapKeys {
+            amm_id,
+            base_vault: read_key(amm, 336)?,
+            quote_vault: read_key(amm, 368)?,
+            open_orders: read_key(amm, 496)?,
+            target_orders: read_key(amm, 592)?,
+            market_program,
+            market_id,
+            market_base_vault: read_key(market, 117)?,
+            market_quote_vault: read_key(market, 165)?,
+            market_event_queue: read_key(market, 253)?,
+            market_bids: read_key(market, 285)?,
+            market_asks: read_key(market, 317)?,
+            market_vault_signer,
+        })
+    }
+
+    /// `swap_base_in` of `amount_in` from the `user_source` token account to `user_destination`.
+    /// The direction follows from which mint the source account holds.
+    pub fn swap_base_in(
+        &self,
+        owner: &Pubkey,
+        user_source: &Pubkey,
+        user_destination: &Pubkey,
+        amount_in: u64,
+        min_amount_out: u64
+    ) -> Instruction {
+        let mut data = vec![SWAP_BASE_IN_TAG];
+        data.extend_from_slice(&amount_in.to_le_bytes());
+        data.extend_from_slice(&min_amount_out.to_le_bytes());
+        Instruction {
+            program_id: Pubkey::from_str_const(RAYDIUM_AMM_V4),
+            accounts: vec![
+                AccountMeta::new_readonly(spl_token::ID, false),
+                AccountMeta::new(self.amm_id, false),
+                AccountMeta::new_readonly(Pubkey::from_str_const(RAYDIUM_AMM_V4_AUTHORITY), false),
+                AccountMeta::new(self.open_orders, false),
+                AccountMeta::new(self.target_orders, false),
+                AccountMeta::new(self.base_vault, false),
+                AccountMeta::new(self.quote_vault, false),
+                AccountMeta::new_readonly(self.market_program, false),
+                AccountMeta::new(self.market_id, false),
+                AccountMeta::new(self.market_bids, false),
+                AccountMeta::new(self.market_asks, false),
+                AccountMeta::new(self.market_event_queue, false),
+                AccountMeta::new(self.market_base_vault, false),
+                AccountMeta::new(self.market_quote_vault, false),
+                AccountMeta::new_readonly(self.market_vault_signer, false),
+                AccountMeta::new(*user_source, false),
+                AccountMeta::new(*user_destination, false),
+                AccountMeta::new_readonly(*owner, true),
+            ],
+            data,
+        }
+    }
+}
+
+/// Fee account paid by every AMM v4 `initialize2`. Its history lists pool creations
+/// only, unlike the program's, which is mostly swaps.
+pub const RAYDIUM_CREATE_POOL_FEE: &str = "7YttLkHDoNj9wyDur5pM1ejNaAvT9X4eqaYcHQqtj2G5";
+
+/// Successful pool creations in slots strictly between `after_slot` and `before_slot`,
+/// oldest first, at most `max_signatures` of them. The flag is set when older creations
+/// in the range were left out. Used to backfill notifications missed while reconnecting.
+pub async fn pool_creations_between(
+    after_slot: u64,
+    before_slot: u64,
+    max_signatures: usize
+) -> Result<(Vec<(String, u64)>, bool), Box<dyn std::error::Error>> {
+    let mut signatures = Vec::new();
+    let mut cursor: Option<String> = None;
+
+    loop {
+        let mut config = json!({ "limit": 1000, "commitment": "confirmed" });
+        if let Some(cursor) = &cursor {
+            config["before"] = json!(cursor);
+        }
+        let result = rpc::call("getSignaturesForAddress", json!([RAYDIUM_CREATE_POOL_FEE, config])).await?;
+        let page = result.as_array().ok_or("Invalid getSignaturesForAddress response")?;
+        for entry in page {
+            let (Some(signature), Some(slot)) = (entry["signature"].as_str(), entry["slot"].as_u64()) else { continue };
+            if slot <= after_slot {
+                signatures.reverse();
+                return Ok((signatures, false));
+            }
+            cursor = Some(signature.to_string());
+            if slot >= before_slot || !entry["err"].is_null() {
+                continue;
+            }
+            if signatures.len() == max_signatures {
+                signatures.reverse();
+                return Ok((signatures, true));
+            }
+            signatures.push((signature.to_string(), slot));
+        }
+        if page.len() < 1000 {
+            break;
+        }
+    }
+    signatures.reverse();
+    Ok((signatures, false))
+}
+
+/// Fetches the transaction behind an `initialize2` notification and decodes the new pool.
+/// Retries briefly because the transaction may not be queryable the moment its logs arrive.
+pub async fn fetch_new_pool_event(
+    signature: &str,
+    slot: u64
+) -> Result<NewPoolEvent, Box<dyn std::error::Error>> {
+    let params = json!([
+        signature,
+        { "encoding": "json", "commitment": "confirmed", "maxSupportedTransactionVersion": 0 }
+    ]);
+
+    for attempt in 0..5 {
+        let result = rpc::call("getTransaction", params.clone()).await?;
+        if !result.is_null() {
+            return decode_initialize2(signature, slot, &result).ok_or_else(|| {
+                format!("No initialize2 instruction in {}", signature).into()
+            });
+        }
+        sleep(Duration::from_millis(200 * (attempt + 1))).await;
+    }
+    Err(format!("Transaction {} not available", signature).into())
+}
+
+/// Decodes a `getTransaction` (json encoding) result. Handles top-level and inner
+/// (CPI) `initialize2` instructions as well as address lookup tables.
+pub fn decode_initialize2(signature: &str, slot: u64, transaction: &Value) -> Option<NewPoolEvent> {
+    let message = &transaction["transaction"]["message"];
+    let meta = &transaction["meta"];
+
+    let mut keys: Vec<String> = message["accountKeys"]
+        .as_array()?
+        .iter()
+        .filter_map(|k| k.as_str().map(String::from))
+        .collect();
+    for loaded in ["writable", "readonly"] {
+        if let Some(addresses) = meta["loadedAddresses"][loaded].as_array() {
+            keys.extend(addresses.iter().filter_map(|k| k.as_str().map(String::from)));
+        }
+    }
+
+    let mut instructions: Vec<&Value> = message["instructions"].as_array()?.iter().collect();
+    if let Some(inner) = meta["innerInstructions"].as_array() {
+        for set in inner {
+            if let Some(ixs) = set["instructions"].as_array() {
+                instructions.extend(ixs.iter());
+            }
+        }
+    }
+
+    instructions.into_iter().find_map(|ix| {
+        let program = keys.get(ix["programIdIndex"].as_u64()? as usize)?;
+        if program != RAYDIUM_AMM_V4 {
+            return None;
+        }
+        let data = bs58::decode(ix["data"].as_str()?).into_vec().ok()?;
+        // tag (1) + nonce (1) + open_time (8) + init_pc_amount (8) + init_coin_amount (8)
+        if data.len() < 26 || data[0] != INITIALIZE2_TAG {
+            return None;
+        }
+        let accounts: Vec<usize> = ix["accounts"]
+            .as_array()?
+            .iter()
+            .filter_map(|a| a.as_u64().map(|a| a as usize))
+            .collect();
+        let account = |i: usize| accounts.get(i).and_then(|&k| keys.get(k)).cloned();
+        let read_u64 = |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+        Some(NewPoolEvent {
+            signature: signature.to_string(),
+            slot,
+            pool_id: account(4)?,
+            lp_mint: account(7)?,
+            base_mint: account(8)?,
+            quote_mint: account(9)?,
+            base_vault: account(10)?,
+            quote_vault: account(11)?,
+            market_id: account(16)?,
+            creator: account(17)?,
+            open_time: read_u64(2),
+            init_quote_amount: read_u64(10),
+            init_base_amount: read_u64(18),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recorded `logsNotification` frame for a pool creation.
+    const INITIALIZE2_FRAME: &str = r#"{
+        "jsonrpc": "2.0",
+        "method": "logsNotification",
+        "params": {
+            "result": {
+                "context": { "slot": 274035112 },
+                "value": {
+                    "signature": "3TrJhBkFqkhNaHXP8k4gTAWjeaGJBkHupFfxKHZ3VcktX6xSCSHLbXPLPYbFyNHWJHGKcxpwxLuFFHYsKXKbgNqr",
+                    "err": null,
+                    "logs": [
+                        "Program ComputeBudget111111111111111111111111111111 invoke [1]",
+                        "Program ComputeBudget111111111111111111111111111111 success",
+                        "Program 675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8 invoke [1]",
+                        "Program log: initialize2: InitializeInstruction2 { nonce: 254, open_time: 1718900000, init_pc_amount: 79000000000, init_coin_amount: 900000000000000 }",
+                        "Program 11111111111111111111111111111111 invoke [2]",
+                        "Program 11111111111111111111111111111111 success",
+                        "Program 675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8 consumed 93417 of 199700 compute units",
+                        "Program 675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8 success"
+                    ]
+                }
+            },
+            "subscription": 4512
+        }
+    }"#;
+
+    /// Recorded `logsNotification` frame for an ordinary swap.
+    const SWAP_FRAME: &str = r#"{
+        "jsonrpc": "2.0",
+        "method": "logsNotification",
+        "params": {
+            "result": {
+                "context": { "slot": 274035113 },
+                "value": {
+                    "signature": "5VERv8NMvzbJMEkV8xnrLkEaWRtSz9CosKDYjCJjBRnbJLgp8uirBgmQpjKhoR4tjF3ZpRzrFmBV6UjKdiSZkQUW",
+                    "err": null,
+                    "logs": [
+                        "Program 675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8 invoke [1]",
+                        "Program log: ray_log: A4CWmAAAAAAAAAAAAAAAAAACAAAAAAAAAA==",
+                        "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA invoke [2]",
+                        "Program TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA success",
+                        "Program 675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8 success"
+                    ]
+                }
+            },
+            "subscription": 4512
+        }
+    }"#;
+
+    /// `getTransaction` result (json encoding) for the pool creation above.
+    const INITIALIZE2_TRANSACTION: &str = r#"{
+        "slot": 274035112,
+        "transaction": {
+            "message": {
+                "accountKeys": [
+                    "GThUX1Atko4tqhN2NaiTazWSeFWMuiUvfFnyJyUghFMJ",
+                    "58oQChx4yWmvKdwLLZzBi4ChoCc2fqCUWBkwMihLYQo2",
+                    "8ZKS5MsWNufdPpSn8GdkFj2SCHXAUxvuxLk1gdkhqNtj",
+                    "HWy1jotHpo6UqeQxx49dpYYdQB8wj9Qk9MdxwjLvDHB8",
+                    "4Ri2vd6TLSdtWgPm8Q8W2hbhvCqhBsXQCnwg4FkKJ3WL",
+                    "DQyrAcCrDXQ7NeoqGgDCZwBvWDcYmFCjSb9JtteuvPpz",
+                    "HLmqeL62xR1QoZ1HKKbXRrdN1p3phKpxRMb2VVopvBBz",
+                    "7YttLkHDoNj9wyDur5pM1ejNaAvT9X4eqaYcHQqtj2G5",
+                    "EFGHJrWJ5RhjvoGNaEGQFF2KHMX7bmhJLbVuKsMpvd2q",
+                    "ComputeBudget111111111111111111111111111111",
+                    "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8",
+                    "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+                    "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL",
+                    "11111111111111111111111111111111",
+                    "SysvarRent111111111111111111111111111111111",
+                    "5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1",
+                    "9DCxsMizn3H1hprZ7xWe6LDzeUeZBksYFpBWBtSf1PQX",
+                    "srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX",
+                    "So11111111111111111111111111111111111111112",
+                    "GjRRZp3PFzWjwRvU8YdmPiaYvz9tcMPfUm9okqaQnUzv",
+                    "7fRHK4NqBrLQAk8hk4FQcvJw6Qz8yMqDTvb7aYZP4pbG",
+                    "9sV4b1EPkjuuQr6kkHv7hDLB3AFBf4MxEAhPk3ybGb6U"
+                ],
+                "instructions": [
+                    { "programIdIndex": 9, "accounts": [], "data": "3GAG5eogvTjV" },
+                    {
+                        "programIdIndex": 10,
+                        "accounts": [11, 12, 13, 14, 1, 15, 2, 3, 19, 18, 4, 5, 6, 16, 7, 17, 20, 0, 8, 21, 3],
+                        "data": "4YGKbA1nU6HfD8bF9QxfB9r9CioMgQ5HTsd"
+                    }
+                ]
+            }
+        },
+        "meta": {
+            "err": null,
+            "innerInstructions": [],
+            "loadedAddresses": { "writable": [], "readonly": [] }
+        }
+    }"#;
+
+    fn notification(frame: &str) -> Value {
+        let frame: Value = serde_json::from_str(frame).unwrap();
+        frame["params"]["result"].clone()
+    }
+
+    #[test]
+    fn detects_initialize2_notifications() {
+        let result = notification(INITIALIZE2_FRAME);
+        let (signature, slot) = parse_initialize2_notification(&result).unwrap();
+        assert!(signature.starts_with("3TrJhBkF"));
+        assert_eq!(slot, 274_035_112);
+    }
+
+    #[test]
+    fn ignores_other_instructions() {
+        let result = notification(SWAP_FRAME);
+        assert!(parse_initialize2_notification(&result).is_none());
+        assert!(notification_signature(&result).unwrap().starts_with("5VERv8NM"));
+    }
+
+    #[test]
+    fn ignores_failed_transactions() {
+        let mut result = notification(INITIALIZE2_FRAME);
+        result["value"]["err"] = json!({ "InstructionError": [1, { "Custom": 0 }] });
+        assert!(parse_initialize2_notification(&result).is_none());
+    }
+
+    #[test]
+    fn decodes_the_pool() {
+        let transaction: Value = serde_json::from_str(INITIALIZE2_TRANSACTION).unwrap();
+        let event = decode_initialize2("sig", 274_035_112, &transaction).unwrap();
+        assert_eq!(event.pool_id, "58oQChx4yWmvKdwLLZzBi4ChoCc2fqCUWBkwMihLYQo2");
+        assert_eq!(event.lp_mint, "HWy1jotHpo6UqeQxx49dpYYdQB8wj9Qk9MdxwjLvDHB8");
+        assert_eq!(event.base_mint, "GjRRZp3PFzWjwRvU8YdmPiaYvz9tcMPfUm9okqaQnUzv");
+        assert_eq!(event.quote_mint, "So11111111111111111111111111111111111111112");
+        assert_eq!(event.base_vault, "4Ri2vd6TLSdtWgPm8Q8W2hbhvCqhBsXQCnwg4FkKJ3WL");
+        assert_eq!(event.quote_vault, "DQyrAcCrDXQ7NeoqGgDCZwBvWDcYmFCjSb9JtteuvPpz");
+        assert_eq!(event.market_id, "7fRHK4NqBrLQAk8hk4FQcvJw6Qz8yMqDTvb7aYZP4pbG");
+        assert_eq!(event.creator, "GThUX1Atko4tqhN2NaiTazWSeFWMuiUvfFnyJyUghFMJ");
+        assert_eq!(event.open_time, 1_718_900_000);
+        assert_eq!(event.init_quote_amount, 79_000_000_000);
+        assert_eq!(event.init_base_amount, 900_000_000_000_000);
+    }
+
+    #[test]
+    fn decodes_initialize2_called_through_cpi() {
+        let mut transaction: Value = serde_json::from_str(INITIALIZE2_TRANSACTION).unwrap();
+        let instruction = transaction["transaction"]["message"]["instructions"][1].take();
+        transaction["transaction"]["message"]["instructions"] = json!([]);
+        transaction["meta"]["innerInstructions"] = json!([{ "index": 0, "instructions": [instruction] }]);
+        let event = decode_initialize2("sig", 1, &transaction).unwrap();
+        assert_eq!(event.pool_id, "58oQChx4yWmvKdwLLZzBi4ChoCc2fqCUWBkwMihLYQo2");
+    }
+
+    #[test]
+    fn skips_non_initialize2_instruction_data() {
+        let mut transaction: Value = serde_json::from_str(INITIALIZE2_TRANSACTION).unwrap();
+        // swap_base_in: tag 9, amount_in, minimum_amount_out
+        transaction["transaction"]["message"]["instructions"][1]["data"] = json!("63SfuT4qF7xK3FS6U5fTWjy");
+        assert!(decode_initialize2("sig", 1, &transaction).is_none());
+    }
+}
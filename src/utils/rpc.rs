@@ -0,0 +1,42 @@
+use reqwest::Client;
+use serde_json::{ json, Value };
+use std::env;
+
+/// Sends a JSON-RPC request to `RPC_ENDPOINT` and returns its `result`.
+pub async fn call(method: &str, params: Value) -> Result<Value, Box<dyn std::error::Error>> {
+    let client = Client::new();
+    let rpc_url = env::var("RPC_ENDPOINT")?;
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": method,
+        "params": params
+    });
+    let mut response = client.post(&rpc_url).json(&request).send().await?.json::<Value>().await?;
+    if !response["error"].is_null() {
+        return Err(format!("{} failed: {}", method, response["error"]).into());
+    }
+    Ok(response["result"].take())
+}
+
+/// Raw data of each account (base64 encoding), `None` for accounts that do not exist.
+pub async fn get_multiple_accounts(pubkeys: &[String]) -> Result<Vec<Option<Vec<u8>>>, Box<dyn std::error::Error>> {
+    use base64::{ engine::general_purpose::STANDARD, Engine };
+    let mut accounts = Vec::with_capacity(pubkeys.len());
+    // getMultipleAccounts accepts at most 100 keys per request
+    for chunk in pubkeys.chunks(100) {
+        let result = call(
+            "getMultipleAccounts",
+            json!([chunk, { "encoding": "base64", "commitment": "confirmed" }])
+        ).await?;
+        let values = result["value"].as_array().ok_or("Invalid getMultipleAccounts response")?;
+        for value in values {
+            accounts.push(
+                value["data"][0]
+                    .as_str()
+                    .and_then(|data| STANDARD.decode(data).ok())
+            );
+        }
+    }
+    Ok(accounts)
+}
@@ -0,0 +1,218 @@
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use std::fmt;
+
+/// A five-field cron schedule in UTC: minute, hour, day of month, month and day of
+/// week (0 or 7 is Sunday). Fields take `*`, values, ranges `a-b`, steps `*/n` or
+/// `a-b/n`, and comma lists. `@hourly`, `@daily` and `@weekly` are shorthands.
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    source: String,
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    /// With both day fields restricted, a day matching either one runs, as in cron.
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl CronSchedule {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let expanded = match source.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("Expected 5 cron fields (minute hour day month weekday), got {}", fields.len()));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7, "weekday")?;
+        // 7 is another name for Sunday
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        weekdays.truncate(7);
+        Ok(CronSchedule {
+            source: expanded.to_string(),
+            minutes: parse_field(fields[0], 0, 59, "minute")?,
+            hours: parse_field(fields[1], 0, 23, "hour")?,
+            days: parse_field(fields[2], 1, 31, "day")?,
+            months: parse_field(fields[3], 1, 12, "month")?,
+            weekdays,
+            days_restricted: fields[2] != "*",
+            weekdays_restricted: fields[4] != "*",
+        })
+    }
+
+    fn matches_day(&self, time: &DateTime<Utc>) -> bool {
+        let day = self.days[time.day() as usize];
+        let weekday = self.weekdays[time.weekday().num_days_from_sunday() as usize];
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// The first scheduled minute after `after`, or `None` if nothing runs within five years.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut time = (after + Duration::minutes(1)).with_second(0)?.with_nanosecond(0)?;
+        let limit = after + Duration::days(5 * 366);
+        while time < limit {
+            if !self.months[time.month() as usize] {
+                let (year, month) = if time.month() == 12 { (time.year() + 1, 1) } else { (time.year(), time.month() + 1) };
+                time = Utc.with_ymd_and_hms(year, month, 1, 0, 0, 0).single()?;
+            } else if !self.matches_day(&time) {
+                time = (time + Duration::days(1)).with_hour(0)?.with_minute(0)?;
+            } else if !self.hours[time.hour() as usize] {
+                time = (time + Duration::hours(1)).with_minute(0)?;
+            } else if !self.minutes[time.minute() as usize] {
+                time += Duration::minutes(1);
+            } else {
+                return Some(time);
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Parses one field into flags indexed by value, `max + 1` long.
+fn parse_field(field: &str, min: u32, max: u32, name: &str) -> Result<Vec<bool>, String> {
+    let mut allowed = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step.parse::<u32>().map_err(|_| format!("Invalid {} step {}", name, step))?;
+                if step == 0 {
+                    return Err(format!("The {} step must be positive", name));
+                }
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let value = |text: &str| -> Result<u32, String> {
+            let value = text.parse::<u32>().map_err(|_| format!("Invalid {} {}", name, text))?;
+            if value < min || value > max {
+                return Err(format!("The {} must be between {} and {}, got {}", name, min, max, value));
+            }
+            Ok(value)
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (value(start)?, value(end)?)
+        } else {
+            let start = value(range)?;
+            // `5/15` means every 15 starting at 5
+            (start, if step > 1 { max } else { start })
+        };
+        if start > end {
+            return Err(format!("Empty {} range {}", name, range));
+        }
+        for allowed_value in (start..=end).step_by(step as usize) {
+            allowed[allowed_value as usize] = true;
+        }
+    }
+    Ok(allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    fn next(schedule: &str, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        CronSchedule::parse(schedule).unwrap().next_after(after)
+    }
+
+    #[test]
+    fn runs_strictly_after_the_given_time() {
+        assert_eq!(next("*/15 * * * *", at(2026, 10, 19, 10, 15)), Some(at(2026, 10, 19, 10, 30)));
+        let just_before = at(2026, 10, 19, 10, 14) + Duration::seconds(30);
+        assert_eq!(next("*/15 * * * *", just_before), Some(at(2026, 10, 19, 10, 15)));
+    }
+
+    #[test]
+    fn expands_shorthands() {
+        // 2026-10-19 is a Monday
+        let monday = at(2026, 10, 19, 10, 7);
+        assert_eq!(next("@hourly", monday), Some(at(2026, 10, 19, 11, 0)));
+        assert_eq!(next("@daily", monday), Some(at(2026, 10, 20, 0, 0)));
+        assert_eq!(next("@weekly", monday), Some(at(2026, 10, 25, 0, 0)));
+        assert_eq!(CronSchedule::parse("@daily").unwrap().to_string(), "0 0 * * *");
+    }
+
+    #[test]
+    fn steps_and_ranges() {
+        assert_eq!(next("*/15 * * * *", at(2026, 10, 19, 10, 7)), Some(at(2026, 10, 19, 10, 15)));
+        assert_eq!(next("*/15 * * * *", at(2026, 10, 19, 10, 45)), Some(at(2026, 10, 19, 11, 0)));
+        // `5/20` starts at 5: 5, 25, 45
+        assert_eq!(next("5/20 * * * *", at(2026, 10, 19, 10, 25)), Some(at(2026, 10, 19, 10, 45)));
+        assert_eq!(next("5/20 * * * *", at(2026, 10, 19, 10, 45)), Some(at(2026, 10, 19, 11, 5)));
+        assert_eq!(next("0 9-17/4 * * *", at(2026, 10, 19, 13, 0)), Some(at(2026, 10, 19, 17, 0)));
+        assert_eq!(next("0 9-17/4 * * *", at(2026, 10, 19, 17, 0)), Some(at(2026, 10, 20, 9, 0)));
+        assert_eq!(next("30 8,20 * * *", at(2026, 10, 19, 9, 0)), Some(at(2026, 10, 19, 20, 30)));
+    }
+
+    #[test]
+    fn seven_is_sunday() {
+        let monday = at(2026, 10, 19, 0, 0);
+        assert_eq!(next("0 12 * * 7", monday), Some(at(2026, 10, 25, 12, 0)));
+        assert_eq!(next("0 12 * * 0", monday), Some(at(2026, 10, 25, 12, 0)));
+        // Friday through Sunday
+        assert_eq!(next("0 12 * * 5-7", monday), Some(at(2026, 10, 23, 12, 0)));
+        assert_eq!(next("0 12 * * 5-7", at(2026, 10, 24, 12, 0)), Some(at(2026, 10, 25, 12, 0)));
+        assert_eq!(next("0 12 * * 5-7", at(2026, 10, 25, 12, 0)), Some(at(2026, 10, 30, 12, 0)));
+    }
+
+    #[test]
+    fn either_day_field_matches_when_both_are_restricted() {
+        // The 13th or any Friday; 2026-12-13 is a Sunday
+        let schedule = "0 0 13 * 5";
+        assert_eq!(next(schedule, at(2026, 12, 11, 0, 0)), Some(at(2026, 12, 13, 0, 0)));
+        assert_eq!(next(schedule, at(2026, 12, 13, 0, 0)), Some(at(2026, 12, 18, 0, 0)));
+        // With one day field left as `*`, only the other one counts
+        assert_eq!(next("0 0 13 * *", at(2026, 12, 11, 0, 0)), Some(at(2026, 12, 13, 0, 0)));
+        assert_eq!(next("0 0 13 * *", at(2026, 12, 13, 0, 0)), Some(at(2027, 1, 13, 0, 0)));
+        assert_eq!(next("0 0 * * 5", at(2026, 12, 11, 0, 0)), Some(at(2026, 12, 18, 0, 0)));
+    }
+
+    #[test]
+    fn rolls_over_months_and_years() {
+        assert_eq!(next("0 0 1 * *", at(2026, 12, 15, 0, 0)), Some(at(2027, 1, 1, 0, 0)));
+        assert_eq!(next("59 23 * * *", at(2026, 12, 31, 23, 59)), Some(at(2027, 1, 1, 23, 59)));
+        // November has no 31st
+        assert_eq!(next("0 0 31 * *", at(2026, 10, 31, 0, 0)), Some(at(2026, 12, 31, 0, 0)));
+        assert_eq!(next("0 0 1 2 *", at(2026, 3, 1, 0, 0)), Some(at(2027, 2, 1, 0, 0)));
+        assert_eq!(next("0 0 29 2 *", at(2026, 3, 1, 0, 0)), Some(at(2028, 2, 29, 0, 0)));
+    }
+
+    #[test]
+    fn impossible_dates_never_run() {
+        assert_eq!(next("0 0 30 2 *", at(2026, 10, 19, 0, 0)), None);
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        assert!(CronSchedule::parse("0 0 * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("* 24 * * *").is_err());
+        assert!(CronSchedule::parse("* * 0 * *").is_err());
+        assert!(CronSchedule::parse("* * * 13 *").is_err());
+        assert!(CronSchedule::parse("* * * * 8").is_err());
+        assert!(CronSchedule::parse("*/0 * * * *").is_err());
+        assert!(CronSchedule::parse("5-3 * * * *").is_err());
+        assert!(CronSchedule::parse("x * * * *").is_err());
+    }
+}
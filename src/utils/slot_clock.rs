@@ -0,0 +1,106 @@
+use crate::utils::ws_manager::{ Subscription, WsEvent, WsManager };
+use std::env;
+use std::time::{ SystemTime, UNIX_EPOCH };
+use tokio::sync::watch;
+
+/// Nominal slot time, used until enough slots have been observed.
+const DEFAULT_SLOT_MS: f64 = 400.0;
+
+#[derive(Debug, Clone, Copy)]
+struct SlotTick {
+    slot: u64,
+    /// Unix time in milliseconds at which `slot` was observed.
+    observed_ms: u64,
+    /// Smoothed milliseconds per slot.
+    slot_ms: f64,
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn slot_subscribe_request(id: u64) -> String {
+    format!(r#"{{"jsonrpc":"2.0","id":{},"method":"slotSubscribe"}}"#, id)
+}
+
+/// Tracks the cluster's current slot from `slotSubscribe` and maps wall-clock
+/// times to slots, so transactions can be timed to a slot.
+#[derive(Clone)]
+pub struct SlotClock {
+    ticks: watch::Receiver<SlotTick>,
+}
+
+impl SlotClock {
+    /// Subscribes to slot updates on `RPC_WEBSOCKET` and keeps the clock current.
+    pub fn start() -> Result<Self, Box<dyn std::error::Error>> {
+        let ws_url = env::var("RPC_WEBSOCKET")?;
+        let manager = WsManager::new(
+            ws_url,
+            vec![Subscription {
+                name: "slot".to_string(),
+                request: slot_subscribe_request,
+            }],
+        );
+        let (event_tx, mut event_rx) = tokio::sync::mpsc::channel::<WsEvent>(1000);
+        tokio::spawn(manager.run(event_tx));
+
+        let (tick_tx, ticks) = watch::channel(SlotTick { slot: 0, observed_ms: now_ms(), slot_ms: DEFAULT_SLOT_MS });
+        tokio::spawn(async move {
+            while let Some(event) = event_rx.recv().await {
+                let WsEvent::Notification { slot, .. } = event else { continue };
+                let observed_ms = now_ms();
+                let previous = *tick_tx.borrow();
+                if slot <= previous.slot {
+                    continue;
+                }
+                // Smooth the measured slot time; skip the first tick and reconnect jumps
+                let slot_ms = if previous.slot > 0 && slot - previous.slot < 50 {
+                    let measured = (observed_ms - previous.observed_ms) as f64 / (slot - previous.slot) as f64;
+                    previous.slot_ms * 0.9 + measured * 0.1
+                } else {
+                    previous.slot_ms
+                };
+                if tick_tx.send(SlotTick { slot, observed_ms, slot_ms }).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(SlotClock { ticks })
+    }
+
+    /// Last slot reported by the cluster, 0 until the first update arrives.
+    pub fn current_slot(&self) -> u64 {
+        self.ticks.borrow().slot
+    }
+
+    /// Smoothed milliseconds per slot.
+    pub fn slot_ms(&self) -> f64 {
+        self.ticks.borrow().slot_ms
+    }
+
+    /// Slot expected to be current at `unix_secs`. Times in the past map to the current slot.
+    pub fn slot_at(&self, unix_secs: u64) -> u64 {
+        let tick = *self.ticks.borrow();
+        let target_ms = unix_secs * 1000;
+        if target_ms <= tick.observed_ms {
+            return tick.slot;
+        }
+        tick.slot + ((target_ms - tick.observed_ms) as f64 / tick.slot_ms).ceil() as u64
+    }
+
+    /// Resolves once the cluster reaches `slot`.
+    pub async fn wait_for_slot(&self, slot: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let mut ticks = self.ticks.clone();
+        ticks.wait_for(|tick| tick.slot >= slot).await.map_err(|_| "Slot clock stopped")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl SlotClock {
+    /// A clock stopped at `slot` with the nominal slot time.
+    pub fn stopped_at(slot: u64) -> Self {
+        let (_, ticks) = watch::channel(SlotTick { slot, observed_ms: now_ms(), slot_ms: DEFAULT_SLOT_MS });
+        SlotClock { ticks }
+    }
+}
@@ -1,8 +1,9 @@
 use teloxide::{prelude::*, types::ChatId};
 use std::env;
-use log::{info, error};
 use crate::utils::trade_log::TradeLog;
+use crate::utils::token_metadata::{ label_mints, resolve_token, symbol_for };
 
+#[derive(Clone)]
 pub struct TelegramBot {
     bot: Bot,
     user_id: i64,
@@ -21,9 +22,10 @@ impl TelegramBot {
         }
     }
 
+    /// Sends `message` to the authorized user, with known token mints shown as symbols.
     pub async fn send_message(&self, message: &str) -> Result<(), Box<dyn std::error::Error>> {
         self.bot
-            .send_message(ChatId(self.user_id), message)
+            .send_message(ChatId(self.user_id), label_mints(message))
             .await?;
         Ok(())
     }
@@ -34,6 +36,7 @@ impl TelegramBot {
 
         teloxide::repl(bot, move |msg: Message, bot: Bot| {
             let trade_log = trade_log.clone();
+            let commands = commands.clone();
             async move {
                 if msg.from().map(|u| u.id.0 as i64) != Some(user_id) {
                     bot.send_message(msg.chat.id, "Unauthorized user").await?;
@@ -47,24 +50,39 @@ impl TelegramBot {
                     return Ok(());
                 }
 
+                // Commands take a mint or a symbol; "new" addresses the launch sniper
+                let token = match parts.get(1) {
+                    Some(&"new") => "new".to_string(),
+                    Some(arg) if !matches!(parts[0], "/status" | "/load" | "/unload") => match resolve_token(arg) {
+                        Ok(mint) => mint,
+                        Err(e) => {
+                            bot.send_message(msg.chat.id, e).await?;
+                            return Ok(());
+                        }
+                    },
+                    _ => String::new(),
+                };
+
                 match parts[0] {
                     "/start" => {
                         if parts.len() > 1 {
-                            let token = parts[1].to_string();
-                            commands.send(BotCommand::Start(token)).await.unwrap();
-                            bot.send_message(msg.chat.id, format!("Started trading for {}", token)).await?;
+                            commands.send(BotCommand::Start(token.clone())).await.unwrap();
+                            bot.send_message(msg.chat.id, format!("Started trading for {}", symbol_for(&token))).await?;
                         }
                     }
                     "/stop" => {
                         if parts.len() > 1 {
-                            let token = parts[1].to_string();
-                            commands.send(BotCommand::Stop(token)).await.unwrap();
-                            bot.send_message(msg.chat.id, format!("Stopped trading for {}", token)).await?;
+                            commands.send(BotCommand::Stop(token.clone())).await.unwrap();
+                            bot.send_message(msg.chat.id, format!("Stopped trading for {}", symbol_for(&token))).await?;
+                        }
+                    }
+                    "/resume" => {
+                        if parts.len() > 1 {
+                            commands.send(BotCommand::Resume(token)).await.unwrap();
                         }
                     }
                     "/balance" => {
                         if parts.len() > 1 {
-                            let token = parts[1].to_string();
                             commands.send(BotCommand::Balance(token)).await.unwrap();
                         }
                     }
@@ -72,30 +90,47 @@ impl TelegramBot {
                         commands.send(BotCommand::Status).await.unwrap();
                     }
                     "/set_params" => {
-                        if parts.len() == 5 {
-                            let token = parts[1].to_string();
+                        if parts.len() >= 5 {
                             let strategy = parts[2].to_string();
                             let key = parts[3].to_string();
-                            let value = parts[4].to_string();
-                            commands.send(BotCommand::SetParams(token, strategy, key, value)).await.unwrap();
-                            bot.send_message(msg.chat.id, format!("Set {} for {} on {}", key, strategy, token)).await?;
+                            // Rule expressions contain spaces
+                            let value = parts[4..].join(" ");
+                            commands.send(BotCommand::SetParams(token.clone(), strategy.clone(), key.clone(), value)).await.unwrap();
+                            bot.send_message(msg.chat.id, format!("Set {} for {} on {}", key, strategy, symbol_for(&token))).await?;
+                        }
+                    }
+                    "/load" => {
+                        if let Some(name) = parts.get(1) {
+                            commands.send(BotCommand::LoadStrategy(name.to_string())).await.unwrap();
+                        }
+                    }
+                    "/unload" => {
+                        if let Some(name) = parts.get(1) {
+                            commands.send(BotCommand::UnloadStrategy(name.to_string())).await.unwrap();
                         }
                     }
                     "/profit" => {
                         if parts.len() > 1 {
-                            let token = parts[1].to_string();
                             commands.send(BotCommand::Profit(token)).await.unwrap();
                         }
                     }
                     "/trades" => {
                         if parts.len() > 1 {
-                            let token = parts[1].to_string();
                             let limit = parts.get(2).and_then(|s| s.parse::<i64>().ok()).unwrap_or(10);
-                            let trades = trade_log.get_trades(&token, limit)?;
+                            let trades = match trade_log.get_trades(&token, limit) {
+                                Ok(trades) => trades,
+                                Err(e) => {
+                                    bot.send_message(msg.chat.id, format!("Failed to read trades: {}", e)).await?;
+                                    return Ok(());
+                                }
+                            };
                             let message = trades
                                 .into_iter()
-                                .map(|(mint, action, price, amount, timestamp)| {
-                                    format!("{}: {} {} at {} SOL on {}", mint, action, amount, price, timestamp)
+                                .map(|(mint, action, price, amount, timestamp, sol_usd)| {
+                                    let usd = sol_usd
+                                        .map(|rate| format!(" (${:.2})", price * amount * rate))
+                                        .unwrap_or_default();
+                                    format!("{}: {} {} at {} SOL{} on {}", symbol_for(&mint), action, amount, price, usd, timestamp)
                                 })
                                 .collect::<Vec<_>>()
                                 .join("\n");
@@ -117,8 +152,12 @@ impl TelegramBot {
 pub enum BotCommand {
     Start(String),
     Stop(String),
+    Resume(String),
     Balance(String),
     Status,
     SetParams(String, String, String, String),
     Profit(String),
+    /// Loads or reloads the strategy file with this name.
+    LoadStrategy(String),
+    UnloadStrategy(String),
 }
\ No newline at end of file
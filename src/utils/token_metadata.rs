@@ -0,0 +1,477 @@
+use crate::utils::price_feed::SOL_MINT;
+use crate::utils::rpc;
+use regex::{ Captures, Regex };
+use rusqlite::{ params, Connection, Result };
+use solana_sdk::pubkey::Pubkey;
+use std::collections::{ HashMap, HashSet };
+use std::str::FromStr;
+use std::sync::{ Mutex, OnceLock, RwLock };
+
+pub const METADATA_PROGRAM: &str = "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s";
+/// Token-2022 extension holding metadata inside the mint account.
+const EXT_TOKEN_METADATA: u16 = 19;
+
+static CACHE: OnceLock<TokenMetadataCache> = OnceLock::new();
+static MINT_PATTERN: OnceLock<Regex> = OnceLock::new();
+
+#[derive(Debug, Clone)]
+pub struct TokenMetadata {
+    pub mint: String,
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+    pub uri: String,
+    pub is_mutable: bool,
+}
+
+pub fn metadata_address(mint: &str) -> Result<Pubkey, Box<dyn std::error::Error>> {
+    let program = Pubkey::from_str(METADATA_PROGRAM)?;
+    let mint = Pubkey::from_str(mint)?;
+    let (address, _) = Pubkey::find_program_address(
+        &[b"metadata", program.as_ref(), mint.as_ref()],
+        &program
+    );
+    Ok(address)
+}
+
+/// Reads a borsh string (u32 length prefix) and advances `offset`. Metaplex pads with NULs.
+fn read_string(data: &[u8], offset: &mut usize) -> Option<String> {
+    let len = u32::from_le_bytes(data.get(*offset..*offset + 4)?.try_into().ok()?) as usize;
+    let bytes = data.get(*offset + 4..*offset + 4 + len)?;
+    *offset += 4 + len;
+    Some(String::from_utf8_lossy(bytes).trim_end_matches('\0').trim().to_string())
+}
+
+/// Decodes a Metaplex metadata account. Decimals live on the mint and are left at 0.
+pub fn parse_metaplex_metadata(mint: &str, data: &[u8]) -> Option<TokenMetadata> {
+    // key (1) + update_authority (32) + mint (32)
+    let mut offset = 65;
+    let name = read_string(data, &mut offset)?;
+    let symbol = read_string(data, &mut offset)?;
+    let uri = read_string(data, &mut offset)?;
+
+    offset += 2; // seller_fee_basis_points
+    if *data.get(offset)? == 1 {
+        let creators = u32::from_le_bytes(data.get(offset + 1..offset + 5)?.try_into().ok()?) as usize;
+        offset += 5 + creators * 34;
+    } else {
+        offset += 1;
+    }
+    offset += 1; // primary_sale_happened
+    let is_mutable = *data.get(offset)? == 1;
+
+    Some(TokenMetadata {
+        mint: mint.to_string(),
+        name,
+        symbol,
+        decimals: 0,
+        uri,
+        is_mutable,
+    })
+}
+
+/// Decodes the Token-2022 metadata extension from a mint account, if present.
+pub fn parse_token_2022_metadata(mint: &str, data: &[u8]) -> Option<TokenMetadata> {
+    // Base mint (82 bytes) padded to 165, then the account type byte (1 = mint)
+    if data.len() <= 166 || data[165] != 1 {
+        return None;
+    }
+    let mut offset = 166;
+    while offset + 4 <= data.len() {
+        let ext_type = u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let len = u16::from_le_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        if ext_type == EXT_TOKEN_METADATA {
+            let value = data.get(offset + 4..offset + 4 + len)?;
+            // update_authority (32, zero when immutable) + mint (32)
+            let is_mutable = value.get(..32)?.iter().any(|b| *b != 0);
+            let mut field = 64;
+            let name = read_string(value, &mut field)?;
+            let symbol = read_string(value, &mut field)?;
+            let uri = read_string(value, &mut field)?;
+            return Some(TokenMetadata {
+                mint: mint.to_string(),
+                name,
+                symbol,
+                decimals: data[44],
+                uri,
+                is_mutable,
+            });
+        }
+        if ext_type == 0 {
+            break;
+        }
+        offset += 4 + len;
+    }
+    None
+}
+
+/// Reads metadata for `mint` from the Token-2022 extension or the Metaplex account.
+/// Returns `None` if the mint does not exist; tokens without metadata get empty names.
+pub async fn fetch_metadata(mint: &str) -> Result<Option<TokenMetadata>, Box<dyn std::error::Error>> {
+    let address = metadata_address(mint)?.to_string();
+    let mut accounts = rpc::get_multiple_accounts(&[mint.to_string(), address]).await?.into_iter();
+    let (Some(mint_data), metaplex) = (accounts.next().flatten(), accounts.next().flatten()) else {
+        return Ok(None);
+    };
+    let decimals = *mint_data.get(44).ok_or("Invalid mint account")?;
+    let metadata = parse_token_2022_metadata(mint, &mint_data)
+        .or_else(|| metaplex.and_then(|data| parse_metaplex_metadata(mint, &data)))
+        .unwrap_or_else(|| TokenMetadata {
+            mint: mint.to_string(),
+            name: String::new(),
+            symbol: String::new(),
+            decimals,
+            uri: String::new(),
+            is_mutable: false,
+        });
+    Ok(Some(TokenMetadata { decimals, ..metadata }))
+}
+
+/// Token metadata by mint, persisted to SQLite so symbols are known offline and
+/// after a restart. Filled on demand through `resolve_metadata`.
+///
+/// Anyone can mint a token named "BONK", so symbols are only trusted for the
+/// configured tokens registered with `trust`.
+pub struct TokenMetadataCache {
+    conn: Mutex<Connection>,
+    tokens: RwLock<HashMap<String, TokenMetadata>>,
+    trusted: RwLock<HashSet<String>>,
+}
+
+impl TokenMetadataCache {
+    pub fn new() -> Result<Self> {
+        Self::with_connection(Connection::open("trades.db")?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self> {
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS token_metadata (
+                mint TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                symbol TEXT NOT NULL,
+                decimals INTEGER NOT NULL,
+                uri TEXT NOT NULL,
+                is_mutable INTEGER NOT NULL,
+                updated_at TEXT NOT NULL
+            )",
+            []
+        )?;
+
+        let mut tokens = HashMap::new();
+        {
+            let mut stmt = conn.prepare(
+                "SELECT mint, name, symbol, decimals, uri, is_mutable FROM token_metadata"
+            )?;
+            let rows = stmt.query_map([], |row| {
+                Ok(TokenMetadata {
+                    mint: row.get(0)?,
+                    name: row.get(1)?,
+                    symbol: row.get(2)?,
+                    decimals: row.get(3)?,
+                    uri: row.get(4)?,
+                    is_mutable: row.get(5)?,
+                })
+            })?;
+            for metadata in rows {
+                let metadata = metadata?;
+                tokens.insert(metadata.mint.clone(), metadata);
+            }
+        }
+
+        Ok(TokenMetadataCache {
+            conn: Mutex::new(conn),
+            tokens: RwLock::new(tokens),
+            trusted: RwLock::new(HashSet::new()),
+        })
+    }
+
+    /// Loads the cache from disk and makes it available through `TokenMetadataCache::global`.
+    pub fn init() -> Result<&'static TokenMetadataCache> {
+        if let Some(cache) = CACHE.get() {
+            return Ok(cache);
+        }
+        let cache = TokenMetadataCache::new()?;
+        Ok(CACHE.get_or_init(|| cache))
+    }
+
+    pub fn global() -> Option<&'static TokenMetadataCache> {
+        CACHE.get()
+    }
+
+    pub fn get(&self, mint: &str) -> Option<TokenMetadata> {
+        self.tokens.read().unwrap().get(mint).cloned()
+    }
+
+    /// Marks `mint` as a configured token whose symbol may be shown and resolved.
+    pub fn trust(&self, mint: &str) {
+        self.trusted.write().unwrap().insert(mint.to_string());
+    }
+
+    pub fn is_trusted(&self, mint: &str) -> bool {
+        self.trusted.read().unwrap().contains(mint)
+    }
+
+    /// Symbol of a trusted mint, if it has one.
+    pub fn trusted_symbol(&self, mint: &str) -> Option<String> {
+        if !self.is_trusted(mint) {
+            return None;
+        }
+        self.get(mint).map(|m| m.symbol).filter(|symbol| !symbol.is_empty())
+    }
+
+    /// Trusted mints whose symbol matches `symbol`, ignoring case.
+    pub fn mints_for_symbol(&self, symbol: &str) -> Vec<String> {
+        let trusted = self.trusted.read().unwrap();
+        self.tokens
+            .read()
+            .unwrap()
+            .values()
+            .filter(|m| trusted.contains(&m.mint))
+            .filter(|m| !m.symbol.is_empty() && m.symbol.eq_ignore_ascii_case(symbol))
+            .map(|m| m.mint.clone())
+            .collect()
+    }
+
+    pub fn insert(&self, metadata: &TokenMetadata) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO token_metadata (mint, name, symbol, decimals, uri, is_mutable, updated_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                metadata.mint,
+                metadata.name,
+                metadata.symbol,
+                metadata.decimals,
+                metadata.uri,
+                metadata.is_mutable,
+                chrono::Utc::now().to_rfc3339()
+            ]
+        )?;
+        self.tokens.write().unwrap().insert(metadata.mint.clone(), metadata.clone());
+        Ok(())
+    }
+}
+
+/// Returns cached metadata for `mint`, fetching and caching it on a miss.
+pub async fn resolve_metadata(mint: &str) -> Result<TokenMetadata, Box<dyn std::error::Error>> {
+    let cache = TokenMetadataCache::global();
+    if let Some(metadata) = cache.and_then(|c| c.get(mint)) {
+        return Ok(metadata);
+    }
+    let metadata = fetch_metadata(mint).await?.ok_or_else(|| format!("Mint {} not found", mint))?;
+    if let Some(cache) = cache {
+        cache.insert(&metadata)?;
+    }
+    Ok(metadata)
+}
+
+fn short_address(mint: &str) -> String {
+    if mint.len() > 8 {
+        format!("{}…{}", &mint[..4], &mint[mint.len() - 4..])
+    } else {
+        mint.to_string()
+    }
+}
+
+/// Display name for a mint: the symbol of a configured token, otherwise the
+/// shortened mint, since unconfigured tokens can claim any symbol.
+pub fn symbol_for(mint: &str) -> String {
+    TokenMetadataCache::global()
+        .and_then(|c| c.trusted_symbol(mint))
+        .unwrap_or_else(|| short_address(mint))
+}
+
+/// Replaces configured token mints in `text` with their symbols and other cached
+/// mints with their shortened address. Addresses that are not cached mints, such
+/// as pool ids and signatures, are left untouched.
+pub fn label_mints(text: &str) -> String {
+    match TokenMetadataCache::global() {
+        Some(cache) => label_mints_in(cache, text),
+        None => text.to_string(),
+    }
+}
+
+fn label_mints_in(cache: &TokenMetadataCache, text: &str) -> String {
+    let pattern = MINT_PATTERN.get_or_init(|| Regex::new(r"[1-9A-HJ-NP-Za-km-z]{32,44}").unwrap());
+    pattern
+        .replace_all(text, |caps: &Captures| {
+            let mint = &caps[0];
+            match cache.trusted_symbol(mint) {
+                Some(symbol) => symbol,
+                None if cache.get(mint).is_some() => short_address(mint),
+                None => mint.to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Resolves a command argument to a mint. Accepts a mint address or the symbol
+/// of a configured token; "SOL" always means wrapped SOL.
+pub fn resolve_token(token: &str) -> Result<String, String> {
+    resolve_token_in(TokenMetadataCache::global(), token)
+}
+
+fn resolve_token_in(cache: Option<&TokenMetadataCache>, token: &str) -> Result<String, String> {
+    if token.eq_ignore_ascii_case("SOL") {
+        return Ok(SOL_MINT.to_string());
+    }
+    if Pubkey::from_str(token).is_ok() {
+        return Ok(token.to_string());
+    }
+    let mints = cache.map(|c| c.mints_for_symbol(token)).unwrap_or_default();
+    match mints.as_slice() {
+        [mint] => Ok(mint.clone()),
+        [] => Err(format!("Unknown token {}", token)),
+        _ => Err(format!("Symbol {} is ambiguous, use one of: {}", token, mints.join(", "))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BONK: &str = "DezXAZ8z7PnrnRJjz3wXBoRgixCa6xjnB7YaB1pPB263";
+    const FAKE_BONK: &str = "JUPyiwrYJFskUPiHa7hkeR8VUtAeFoSYbKedZNsDvCN";
+    const OTHER: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    fn borsh_string(data: &mut Vec<u8>, value: &str) {
+        data.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        data.extend_from_slice(value.as_bytes());
+    }
+
+    fn metaplex_account(name: &str, symbol: &str, creators: usize, is_mutable: bool) -> Vec<u8> {
+        let mut data = vec![4u8];
+        data.extend_from_slice(&[1u8; 64]);
+        borsh_string(&mut data, name);
+        borsh_string(&mut data, symbol);
+        borsh_string(&mut data, "https://example.com/token.json");
+        data.extend_from_slice(&500u16.to_le_bytes());
+        if creators > 0 {
+            data.push(1);
+            data.extend_from_slice(&(creators as u32).to_le_bytes());
+            data.extend(std::iter::repeat_n(2u8, creators * 34));
+        } else {
+            data.push(0);
+        }
+        data.push(1);
+        data.push(is_mutable as u8);
+        data
+    }
+
+    fn token_2022_mint(extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![0u8; 165];
+        data[44] = 9;
+        data.push(1);
+        for (ext_type, value) in extensions {
+            data.extend_from_slice(&ext_type.to_le_bytes());
+            data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            data.extend_from_slice(value);
+        }
+        data
+    }
+
+    fn metadata_extension(update_authority: u8, name: &str, symbol: &str) -> Vec<u8> {
+        let mut value = vec![update_authority; 32];
+        value.extend_from_slice(&[3u8; 32]);
+        borsh_string(&mut value, name);
+        borsh_string(&mut value, symbol);
+        borsh_string(&mut value, "");
+        value
+    }
+
+    fn metadata(mint: &str, symbol: &str) -> TokenMetadata {
+        TokenMetadata {
+            mint: mint.to_string(),
+            name: symbol.to_string(),
+            symbol: symbol.to_string(),
+            decimals: 5,
+            uri: String::new(),
+            is_mutable: false,
+        }
+    }
+
+    fn cache() -> TokenMetadataCache {
+        let cache = TokenMetadataCache::with_connection(Connection::open_in_memory().unwrap()).unwrap();
+        cache.insert(&metadata(BONK, "BONK")).unwrap();
+        cache.insert(&metadata(FAKE_BONK, "bonk")).unwrap();
+        cache.trust(BONK);
+        cache
+    }
+
+    #[test]
+    fn parses_metaplex_metadata() {
+        let data = metaplex_account("Bonk\0\0\0", "BONK\0", 0, true);
+        let metadata = parse_metaplex_metadata(BONK, &data).unwrap();
+        assert_eq!(metadata.name, "Bonk");
+        assert_eq!(metadata.symbol, "BONK");
+        assert_eq!(metadata.uri, "https://example.com/token.json");
+        assert!(metadata.is_mutable);
+        assert_eq!(metadata.decimals, 0);
+    }
+
+    #[test]
+    fn skips_metaplex_creators() {
+        let data = metaplex_account("Bonk", "BONK", 3, false);
+        assert!(!parse_metaplex_metadata(BONK, &data).unwrap().is_mutable);
+        assert!(parse_metaplex_metadata(BONK, &data[..data.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn parses_token_2022_metadata() {
+        let data = token_2022_mint(&[(3, vec![0u8; 8]), (EXT_TOKEN_METADATA, metadata_extension(7, "Token", "TKN"))]);
+        let metadata = parse_token_2022_metadata(OTHER, &data).unwrap();
+        assert_eq!(metadata.name, "Token");
+        assert_eq!(metadata.symbol, "TKN");
+        assert_eq!(metadata.decimals, 9);
+        assert!(metadata.is_mutable);
+
+        // A zero update authority means the metadata can no longer change
+        let data = token_2022_mint(&[(EXT_TOKEN_METADATA, metadata_extension(0, "Token", "TKN"))]);
+        assert!(!parse_token_2022_metadata(OTHER, &data).unwrap().is_mutable);
+    }
+
+    #[test]
+    fn token_2022_mints_without_metadata_have_none() {
+        assert!(parse_token_2022_metadata(OTHER, &[0u8; 82]).is_none());
+        assert!(parse_token_2022_metadata(OTHER, &token_2022_mint(&[(3, vec![0u8; 8])])).is_none());
+        // Extensions after the zero padding are not read
+        let data = token_2022_mint(&[(0, vec![]), (EXT_TOKEN_METADATA, metadata_extension(7, "Token", "TKN"))]);
+        assert!(parse_token_2022_metadata(OTHER, &data).is_none());
+    }
+
+    #[test]
+    fn labels_only_cached_mints() {
+        let cache = cache();
+        let text = format!("Bought {} and {} in pool {}", BONK, FAKE_BONK, OTHER);
+        assert_eq!(
+            label_mints_in(&cache, &text),
+            format!("Bought BONK and JUPy…DvCN in pool {}", OTHER)
+        );
+    }
+
+    #[test]
+    fn resolves_sol_mints_and_trusted_symbols() {
+        let cache = cache();
+        assert_eq!(resolve_token_in(Some(&cache), "sol").unwrap(), SOL_MINT);
+        assert_eq!(resolve_token_in(Some(&cache), OTHER).unwrap(), OTHER);
+        // The untrusted "bonk" token does not make the symbol ambiguous
+        assert_eq!(resolve_token_in(Some(&cache), "bonk").unwrap(), BONK);
+        assert!(resolve_token_in(Some(&cache), "WIF").is_err());
+        assert!(resolve_token_in(None, "BONK").is_err());
+    }
+
+    #[test]
+    fn rejects_ambiguous_symbols() {
+        let cache = cache();
+        cache.insert(&metadata(OTHER, "BONK")).unwrap();
+        cache.trust(OTHER);
+        assert!(resolve_token_in(Some(&cache), "BONK").unwrap_err().contains("ambiguous"));
+    }
+
+    #[test]
+    fn trusts_only_registered_symbols() {
+        let cache = cache();
+        assert_eq!(cache.get(BONK).unwrap().decimals, 5);
+        assert_eq!(cache.trusted_symbol(BONK).as_deref(), Some("BONK"));
+        assert!(cache.trusted_symbol(FAKE_BONK).is_none());
+    }
+}
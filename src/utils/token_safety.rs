@@ -0,0 +1,331 @@
+use crate::utils::rpc;
+use crate::utils::telegram::TelegramBot;
+use crate::utils::token_metadata::{ metadata_address, parse_metaplex_metadata };
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+
+const INCINERATOR: &str = "1nc1nerator11111111111111111111111111111111";
+
+/// Pool context for a token, available when the scan is triggered by a new pool.
+#[derive(Debug, Clone, Default)]
+pub struct PoolContext {
+    pub lp_mint: Option<String>,
+    /// LP tokens minted at pool creation, used to measure how much has been burned.
+    pub initial_lp_supply: Option<u64>,
+    pub creator: Option<String>,
+    /// Pool vaults, excluded from holder concentration.
+    pub vaults: Vec<String>,
+    /// Scanned as the pool is created, before the creator has had a chance to burn the LP.
+    pub just_created: bool,
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub struct SafetyReport {
+    pub mint: String,
+    /// 0 (certain rug) to 100 (no red flags found).
+    pub score: u32,
+    pub mint_authority_active: bool,
+    pub freeze_authority_active: bool,
+    /// Share of LP supply burned or sent to the incinerator, when an LP mint is known
+    /// and the pool is old enough for the LP to have been burned.
+    pub lp_burned_or_locked: Option<f64>,
+    /// Share of supply held by the ten largest non-pool accounts.
+    pub top_holder_concentration: f64,
+    pub creator_tx_count: Option<usize>,
+    pub metadata_mutable: Option<bool>,
+    pub reasons: Vec<String>,
+}
+
+impl SafetyReport {
+    /// Scores the findings from 100 down, recording a reason for each red flag.
+    fn score(&mut self) {
+        let mut score: i32 = 100;
+        self.reasons.clear();
+
+        if self.mint_authority_active {
+            score -= 30;
+            self.reasons.push("mint authority active".to_string());
+        }
+        if self.freeze_authority_active {
+            score -= 30;
+            self.reasons.push("freeze authority active".to_string());
+        }
+        if let Some(share) = self.lp_burned_or_locked && share < 0.9 {
+            score -= ((0.9 - share) / 0.9 * 25.0).round() as i32;
+            self.reasons.push(format!("only {:.0}% of LP burned or locked", share * 100.0));
+        }
+        if self.top_holder_concentration > 0.3 {
+            score -= (((self.top_holder_concentration - 0.3) / 0.7) * 20.0).round() as i32 + 5;
+            self.reasons.push(format!("top 10 holders own {:.0}%", self.top_holder_concentration * 100.0));
+        }
+        if let Some(count) = self.creator_tx_count && count < 10 {
+            score -= 10;
+            self.reasons.push(format!("creator wallet has only {} transactions", count));
+        }
+        if self.metadata_mutable == Some(true) {
+            score -= 10;
+            self.reasons.push("metadata is mutable".to_string());
+        }
+        self.score = score.max(0) as u32;
+    }
+
+    pub fn summary(&self) -> String {
+        if self.reasons.is_empty() {
+            format!("Safety score for {}: {}/100, no red flags", self.mint, self.score)
+        } else {
+            format!("Safety score for {}: {}/100 ({})", self.mint, self.score, self.reasons.join("; "))
+        }
+    }
+}
+
+/// Reads a `COption<Pubkey>` tag; the key itself is irrelevant for risk scoring.
+fn coption_is_some(data: &[u8], offset: usize) -> Option<bool> {
+    let tag = u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?);
+    Some(tag == 1)
+}
+
+struct MintInfo {
+    supply: u64,
+    mint_authority: bool,
+    freeze_authority: bool,
+}
+
+fn parse_mint(data: &[u8]) -> Option<MintInfo> {
+    Some(MintInfo {
+        mint_authority: coption_is_some(data, 0)?,
+        supply: u64::from_le_bytes(data.get(36..44)?.try_into().ok()?),
+        freeze_authority: coption_is_some(data, 46)?,
+    })
+}
+
+/// Share of the LP supply burned since creation. Without the initial supply only a
+/// fully burned LP is recognised.
+fn lp_burned_share(initial_supply: Option<u64>, supply: u64) -> f64 {
+    match initial_supply {
+        Some(initial) if initial > 0 => 1.0 - (supply as f64 / initial as f64).min(1.0),
+        _ if supply == 0 => 1.0,
+        _ => 0.0,
+    }
+}
+
+async fn largest_accounts(mint: &str) -> Result<Vec<(String, u64)>, Box<dyn std::error::Error>> {
+    let result = rpc::call("getTokenLargestAccounts", json!([mint, { "commitment": "confirmed" }])).await?;
+    Ok(result["value"]
+        .as_array()
+        .ok_or("Invalid getTokenLargestAccounts response")?
+        .iter()
+        .filter_map(|a| {
+            let address = a["address"].as_str()?.to_string();
+            let amount = a["amount"].as_str()?.parse().ok()?;
+            Some((address, amount))
+        })
+        .collect())
+}
+
+/// Scans a token for common rug-pull setups and returns a scored report.
+pub async fn scan_token(mint: &str, pool: &PoolContext) -> Result<SafetyReport, Box<dyn std::error::Error>> {
+    // A fresh pool's LP is still with its creator, so the burn check could only fail
+    let lp_mint = pool.lp_mint.as_ref().filter(|_| !pool.just_created);
+    let mut keys = vec![mint.to_string(), metadata_address(mint)?.to_string()];
+    if let Some(lp_mint) = lp_mint {
+        keys.push(lp_mint.clone());
+    }
+    let accounts = rpc::get_multiple_accounts(&keys).await?;
+    let mint_info = accounts[0]
+        .as_deref()
+        .and_then(parse_mint)
+        .ok_or_else(|| format!("Mint account {} not found", mint))?;
+    let metadata_mutable = accounts[1]
+        .as_deref()
+        .and_then(|data| parse_metaplex_metadata(mint, data))
+        .map(|metadata| metadata.is_mutable);
+
+    let lp_burned_or_locked = match (lp_mint, accounts.get(2).and_then(|a| a.as_deref()).and_then(parse_mint)) {
+        (Some(lp_mint), Some(lp_info)) => {
+            let burned = lp_burned_share(pool.initial_lp_supply, lp_info.supply);
+            let incinerated = if lp_info.supply > 0 {
+                let holders = largest_accounts(lp_mint).await?;
+                let owners = rpc::get_multiple_accounts(
+                    &holders.iter().map(|(a, _)| a.clone()).collect::<Vec<_>>()
+                ).await?;
+                let in_incinerator: u64 = holders
+                    .iter()
+                    .zip(owners.iter())
+                    .filter(|(_, account)| {
+                        account
+                            .as_deref()
+                            .and_then(|d| d.get(32..64))
+                            .map(|o| Pubkey::new_from_array(o.try_into().unwrap()).to_string() == INCINERATOR)
+                            .unwrap_or(false)
+                    })
+                    .map(|((_, amount), _)| amount)
+                    .sum();
+                (in_incinerator as f64 / lp_info.supply as f64) * (1.0 - burned)
+            } else {
+                0.0
+            };
+            Some(burned + incinerated)
+        }
+        _ => None,
+    };
+    let top_holder_concentration = if mint_info.supply > 0 {
+        let holders = largest_accounts(mint).await?;
+        let held: u64 = holders
+            .iter()
+            .filter(|(address, _)| !pool.vaults.contains(address))
+            .take(10)
+            .map(|(_, amount)| amount)
+            .sum();
+        held as f64 / mint_info.supply as f64
+    } else {
+        0.0
+    };
+    let creator_tx_count = match &pool.creator {
+        Some(creator) => {
+            let signatures = rpc::call(
+                "getSignaturesForAddress",
+                json!([creator, { "limit": 1000, "commitment": "confirmed" }])
+            ).await?;
+            Some(signatures.as_array().map(|s| s.len()).unwrap_or(0))
+        }
+        None => None,
+    };
+    let mut report = SafetyReport {
+        mint: mint.to_string(),
+        score: 0,
+        mint_authority_active: mint_info.mint_authority,
+        freeze_authority_active: mint_info.freeze_authority,
+        lp_burned_or_locked,
+        top_holder_concentration,
+        creator_tx_count,
+        metadata_mutable,
+        reasons: Vec::new(),
+    };
+    report.score();
+    Ok(report)
+}
+
+/// Scans a token and reports the result to Telegram. Returns `None` when the scan
+/// failed, e.g. on an RPC error, and the token was not checked.
+pub async fn check_token(
+    mint: &str,
+    pool: &PoolContext,
+    telegram: &TelegramBot
+) -> Result<Option<SafetyReport>, Box<dyn std::error::Error>> {
+    let report = scan_token(mint, pool).await.map_err(|e| e.to_string());
+    match report {
+        Ok(report) => {
+            telegram.send_message(&report.summary()).await?;
+            Ok(Some(report))
+        }
+        Err(e) => {
+            telegram.send_message(&format!("Safety check for {} not completed: {}", mint, e)).await?;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clean_report() -> SafetyReport {
+        SafetyReport {
+            mint: "MINT".to_string(),
+            score: 0,
+            mint_authority_active: false,
+            freeze_authority_active: false,
+            lp_burned_or_locked: Some(1.0),
+            top_holder_concentration: 0.1,
+            creator_tx_count: Some(500),
+            metadata_mutable: Some(false),
+            reasons: Vec::new(),
+        }
+    }
+
+    /// SPL mint layout: mint authority option, supply, decimals, initialized, freeze authority option.
+    fn mint_data(mint_authority: bool, supply: u64, freeze_authority: bool) -> Vec<u8> {
+        let mut data = vec![0u8; 82];
+        data[0..4].copy_from_slice(&(mint_authority as u32).to_le_bytes());
+        data[36..44].copy_from_slice(&supply.to_le_bytes());
+        data[44] = 6;
+        data[45] = 1;
+        data[46..50].copy_from_slice(&(freeze_authority as u32).to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn parses_mint_authorities_and_supply() {
+        let mint = parse_mint(&mint_data(true, 1_000_000, false)).unwrap();
+        assert!(mint.mint_authority);
+        assert!(!mint.freeze_authority);
+        assert_eq!(mint.supply, 1_000_000);
+
+        let mint = parse_mint(&mint_data(false, 0, true)).unwrap();
+        assert!(!mint.mint_authority);
+        assert!(mint.freeze_authority);
+        assert!(parse_mint(&[0u8; 40]).is_none());
+    }
+
+    #[test]
+    fn clean_tokens_keep_a_full_score() {
+        let mut report = clean_report();
+        report.score();
+        assert_eq!(report.score, 100);
+        assert!(report.reasons.is_empty());
+    }
+
+    #[test]
+    fn active_authorities_cost_thirty_each() {
+        let mut report = clean_report();
+        report.mint_authority_active = true;
+        report.freeze_authority_active = true;
+        report.score();
+        assert_eq!(report.score, 40);
+        assert_eq!(report.reasons.len(), 2);
+    }
+
+    #[test]
+    fn penalties_scale_with_lp_and_holder_risk() {
+        let mut report = clean_report();
+        report.lp_burned_or_locked = Some(0.0);
+        report.top_holder_concentration = 1.0;
+        report.score();
+        assert_eq!(report.score, 100 - 25 - 25);
+
+        let mut report = clean_report();
+        report.lp_burned_or_locked = Some(0.45);
+        report.top_holder_concentration = 0.65;
+        report.score();
+        // Half of the LP penalty (13, rounded) and half of the concentration penalty plus 5
+        assert_eq!(report.score, 100 - 13 - 15);
+        // Unknown LP state is not penalised
+        report.lp_burned_or_locked = None;
+        report.score();
+        assert_eq!(report.score, 100 - 15);
+    }
+
+    #[test]
+    fn scores_never_go_below_zero() {
+        let mut report = clean_report();
+        report.mint_authority_active = true;
+        report.freeze_authority_active = true;
+        report.lp_burned_or_locked = Some(0.0);
+        report.top_holder_concentration = 1.0;
+        report.creator_tx_count = Some(1);
+        report.metadata_mutable = Some(true);
+        report.score();
+        assert_eq!(report.score, 0);
+        assert_eq!(report.reasons.len(), 6);
+    }
+
+    #[test]
+    fn measures_lp_burned_since_creation() {
+        assert!((lp_burned_share(Some(1_000), 250) - 0.75).abs() < 1e-9);
+        assert_eq!(lp_burned_share(Some(1_000), 2_000), 0.0);
+        assert_eq!(lp_burned_share(None, 0), 1.0);
+        assert_eq!(lp_burned_share(None, 500), 0.0);
+        assert_eq!(lp_burned_share(Some(0), 500), 0.0);
+    }
+}
@@ -1,8 +1,24 @@
 use rusqlite::{ Connection, Result, params};
 use chrono::Utc;
+use std::sync::{ Arc, Mutex };
 
+/// Handle to the trade database. Clones share the same connection.
+#[derive(Clone)]
 pub struct TradeLog {
-    conn: Connection,
+    conn: Arc<Mutex<Connection>>,
+}
+
+/// A trade row: mint, action, price, amount, timestamp and the SOL/USD rate at the time.
+pub type TradeRow = (String, String, f64, f64, String, Option<f64>);
+
+/// Profit for a token, valued in SOL and in USD.
+/// The USD cost basis uses the SOL/USD rate recorded with each trade.
+#[derive(Debug, Clone, Copy)]
+pub struct Profit {
+    pub sol: f64,
+    pub sol_percentage: f64,
+    pub usd: f64,
+    pub usd_percentage: f64,
 }
 
 impl TradeLog {
@@ -15,17 +31,32 @@ impl TradeLog {
                 action TEXT NOT NULL,
                 price REAL NOT NULL,
                 amount REAL NOT NULL,
-                timestamp TEXT NOT NULL
+                timestamp TEXT NOT NULL,
+                sol_usd REAL
             )",
             []
         )?;
-        Ok(TradeLog { conn })
+        // Databases created before USD reporting lack the sol_usd column
+        let has_sol_usd = conn
+            .prepare("SELECT 1 FROM pragma_table_info('trades') WHERE name = 'sol_usd'")?
+            .exists([])?;
+        if !has_sol_usd {
+            conn.execute("ALTER TABLE trades ADD COLUMN sol_usd REAL", [])?;
+        }
+        Ok(TradeLog { conn: Arc::new(Mutex::new(conn)) })
     }
 
-    pub fn log_trade(&self, token_mint: &str, action: &str, price: f64, amount: f64) -> Result<()> {
-        self.conn.execute(
-            "INSERT INTO trades (token_mint, action, price, amount, timestamp) VALUES (?, ?, ?, ?, ?)",
-            [token_mint, action, &price.to_string(), &amount.to_string(), &Utc::now().to_rfc3339()]
+    pub fn log_trade(
+        &self,
+        token_mint: &str,
+        action: &str,
+        price: f64,
+        amount: f64,
+        sol_usd: Option<f64>
+    ) -> Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO trades (token_mint, action, price, amount, timestamp, sol_usd) VALUES (?, ?, ?, ?, ?, ?)",
+            params![token_mint, action, price, amount, Utc::now().to_rfc3339(), sol_usd]
         )?;
         Ok(())
     }
@@ -34,12 +65,13 @@ impl TradeLog {
         &self,
         token_mint: &str,
         limit: i64
-    ) -> Result<Vec<(String, String, f64, f64, String)>, rusqlite::Error> {
-        let mut stmt = self.conn.prepare(
-            "SELECT token_mint, action, price, amount, timestamp FROM trades WHERE token_mint = ? ORDER BY timestamp DESC LIMIT ?"
+    ) -> Result<Vec<TradeRow>, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT token_mint, action, price, amount, timestamp, sol_usd FROM trades WHERE token_mint = ? ORDER BY timestamp DESC LIMIT ?"
         )?;
         let rows = stmt.query_map(params![token_mint, limit], |row| {
-            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?))
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?))
         })?;
         let trades = rows.collect::<Result<Vec<_>, _>>()?;
         Ok(trades)
@@ -48,10 +80,12 @@ impl TradeLog {
     pub fn calculate_profit(
         &self,
         token_mint: &str,
-        current_price: f64
-    ) -> Result<(f64, f64), rusqlite::Error> {
-        let mut stmt = self.conn.prepare(
-            "SELECT action, price, amount FROM trades WHERE token_mint = ? ORDER BY timestamp"
+        current_price: f64,
+        current_sol_usd: f64
+    ) -> Result<Profit, rusqlite::Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT action, price, amount, sol_usd FROM trades WHERE token_mint = ? ORDER BY timestamp"
         )?;
         let trades = stmt
             .query_map(params![token_mint], |row| {
@@ -59,24 +93,37 @@ impl TradeLog {
                     row.get(0)?, // action (String)
                     row.get(1)?, // price (f64)
                     row.get(2)?, // amount (f64)
+                    row.get(3)?, // sol_usd (Option<f64>)
                 ))
             })?
-            .collect::<Result<Vec<(String, f64, f64)>, _>>()?;
+            .collect::<Result<Vec<(String, f64, f64, Option<f64>)>, _>>()?;
 
         let mut total_cost = 0.0;
+        let mut total_cost_usd = 0.0;
         let mut total_amount = 0.0;
-        for (action, price, amount) in trades {
+        for (action, price, amount, sol_usd) in trades {
+            // Trades logged before USD tracking fall back to today's rate
+            let rate = sol_usd.unwrap_or(current_sol_usd);
             if action == "buy" {
                 total_cost += price * amount;
+                total_cost_usd += price * amount * rate;
                 total_amount += amount;
             } else if action == "sell" {
                 total_cost -= price * amount;
+                total_cost_usd -= price * amount * rate;
                 total_amount -= amount;
             }
         }
         let current_value = total_amount * current_price;
         let profit = current_value - total_cost;
         let percentage = if total_cost > 0.0 { (profit / total_cost) * 100.0 } else { 0.0 };
-        Ok((profit, percentage))
+        let profit_usd = current_value * current_sol_usd - total_cost_usd;
+        let percentage_usd = if total_cost_usd > 0.0 { (profit_usd / total_cost_usd) * 100.0 } else { 0.0 };
+        Ok(Profit {
+            sol: profit,
+            sol_percentage: percentage,
+            usd: profit_usd,
+            usd_percentage: percentage_usd,
+        })
     }
 }
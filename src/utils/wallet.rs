@@ -1,18 +1,49 @@
 use solana_sdk::{
-    instruction::{ AccountMeta, Instruction },
-    pubkey::{ Pubkey, self },
-    system_program,
-    sysvar::rent,
+    instruction::Instruction,
+    pubkey::Pubkey,
     signature::{ Keypair, Signer },
     transaction::Transaction,
     commitment_config::CommitmentConfig,
 };
-use spl_associated_token_account::get_associated_token_address;
+use solana_sdk::account::Account;
+use solana_system_interface::instruction as system_instruction;
+use spl_associated_token_account::{
+    get_associated_token_address,
+    get_associated_token_address_with_program_id,
+    instruction::create_associated_token_account_idempotent,
+};
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::rpc_request::TokenAccountsFilter;
+use solana_client::rpc_config::{
+    RpcSendTransactionConfig,
+    RpcSimulateTransactionAccountsConfig,
+    RpcSimulateTransactionConfig,
+};
 use spl_token::ID as TOKEN_PROGRAM_ID;
 
 use solana_client::rpc_client::RpcClient;
 use std::env;
-use crate::utils::{ telegram::TelegramBot, trade_log::TradeLog };
+use std::str::FromStr;
+use log::warn;
+use tokio::time::{ sleep, Duration, Instant };
+use crate::utils::{ telegram::TelegramBot, trade_log::TradeLog, price_feed::{ get_sol_usd_price, get_pool_keys, Quote }, raydium::AmmV4SwapKeys };
+
+/// Outcome of a simulated buy-then-sell, balances in base units.
+pub struct RoundTripSimulation {
+    /// Error of the buy simulated on its own. The sell is not simulated when it fails.
+    pub buy_error: Option<String>,
+    pub sell_error: Option<String>,
+    pub token_balance_before: u64,
+    pub wsol_balance_before: u64,
+    pub token_balance_after_buy: Option<u64>,
+    pub token_balance_after: Option<u64>,
+    pub wsol_balance_after: Option<u64>,
+    /// Logs of the last simulation run.
+    pub logs: Vec<String>,
+}
+
+/// Error, logs and token amounts of the watched accounts after a simulation.
+type SimulationOutcome = (Option<String>, Vec<String>, Vec<Option<u64>>);
 
 pub struct Wallet {
     keypair: Keypair,
@@ -32,7 +63,7 @@ impl Wallet {
 
     pub async fn send_transaction(
         &self,
-        instruction: solana_sdk::instruction::Instruction,
+        instructions: &[Instruction],
         token_mint: &str,
         action: &str,
         price: f64,
@@ -40,33 +71,127 @@ impl Wallet {
     ) -> Result<String, Box<dyn std::error::Error>> {
         let recent_blockhash = self.client.get_latest_blockhash()?;
         let tx = Transaction::new_signed_with_payer(
-            &[instruction],
+            instructions,
             Some(&self.keypair.pubkey()),
             &[&self.keypair],
             recent_blockhash
         );
         let signature = self.client.send_and_confirm_transaction(&tx)?;
-        self.telegram.send_message(
-            &format!(
-                "{} {} {} tokens at {} SOL (Tx: {})",
-                action,
-                token_mint,
-                amount,
-                price,
-                signature
-            )
-        ).await?;
-        self.trade_log.log_trade(token_mint, action, price, amount)?;
+        self.record_trade(&signature.to_string(), token_mint, action, price, amount).await?;
         Ok(signature.to_string())
     }
 
+    /// Executes a checked `quote` for `token_mint` on its Raydium AMM v4 pool, accepting no
+    /// less than the quote's slippage threshold, and records the trade. The proceeds of a
+    /// sell stay wrapped in the wSOL account.
+    pub async fn swap(
+        &self,
+        quote: &Quote,
+        token_mint: &str,
+        action: &str,
+        price: f64,
+        amount: f64
+    ) -> Result<String, Box<dyn std::error::Error>> {
+        let pool_info = get_pool_keys(token_mint, "SOL", &self.telegram).await?;
+        let instructions = if action == "buy" {
+            self.buy_instructions(&pool_info, token_mint, quote.in_amount, quote.other_amount_threshold)?
+        } else {
+            self.sell_instructions(&pool_info, token_mint, quote.in_amount, quote.other_amount_threshold)?
+        };
+        self.send_transaction(&instructions, token_mint, action, price, amount).await
+    }
+
+    /// Signs `instructions` with a fresh blockhash so the transaction can be submitted
+    /// later without further RPC round trips. It must be sent within ~150 slots.
+    pub fn sign_transaction(&self, instructions: &[Instruction]) -> Result<Transaction, Box<dyn std::error::Error>> {
+        let recent_blockhash = self.client.get_latest_blockhash()?;
+        Ok(Transaction::new_signed_with_payer(
+            instructions,
+            Some(&self.keypair.pubkey()),
+            &[&self.keypair],
+            recent_blockhash
+        ))
+    }
+
+    /// Submits a pre-signed transaction, skipping preflight, and waits for it to land.
+    /// Returns the signature and the slot it landed in.
+    pub async fn submit_signed_transaction(
+        &self,
+        tx: &Transaction,
+        token_mint: &str,
+        action: &str,
+        price: f64,
+        amount: f64
+    ) -> Result<(String, u64), Box<dyn std::error::Error>> {
+        let config = RpcSendTransactionConfig {
+            skip_preflight: true,
+            ..RpcSendTransactionConfig::default()
+        };
+        let signature = self.client.send_transaction_with_config(tx, config)?;
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let landed_slot = loop {
+            if let Some(status) = self.client.get_signature_statuses(&[signature])?.value[0].clone() {
+                if let Some(err) = status.err {
+                    return Err(format!("Transaction {} failed: {}", signature, err).into());
+                }
+                break status.slot;
+            }
+            if Instant::now() > deadline {
+                return Err(format!("Transaction {} did not land", signature).into());
+            }
+            sleep(Duration::from_millis(200)).await;
+        };
+        self.record_trade(&signature.to_string(), token_mint, action, price, amount).await?;
+        Ok((signature.to_string(), landed_slot))
+    }
+
+    /// Notifies Telegram and writes the trade log for a landed transaction.
+    async fn record_trade(
+        &self,
+        signature: &str,
+        token_mint: &str,
+        action: &str,
+        price: f64,
+        amount: f64
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // Record the SOL/USD rate at trade time so historical PnL is not revalued at today's rate
+        let sol_usd = match get_sol_usd_price().await {
+            Ok(rate) => Some(rate),
+            Err(e) => {
+                warn!("SOL/USD quote unavailable for trade {}: {}", signature, e);
+                None
+            }
+        };
+        let usd_value = sol_usd
+            .map(|rate| format!(" (${:.2})", price * amount * rate))
+            .unwrap_or_default();
+        // The trade has landed, so a failed notification must not keep it out of the log
+        let message = format!(
+            "{} {} {} tokens at {} SOL{} (Tx: {})",
+            action,
+            token_mint,
+            amount,
+            price,
+            usd_value,
+            signature
+        );
+        if let Err(e) = self.telegram.send_message(&message).await.map_err(|e| e.to_string()) {
+            warn!("Failed to report trade {}: {}", signature, e);
+        }
+        self.trade_log.log_trade(token_mint, action, price, amount, sol_usd)?;
+        Ok(())
+    }
+
     pub async fn get_balance(&self, token_mint: &str) -> Result<f64, Box<dyn std::error::Error>> {
         let mint = Pubkey::from_str(token_mint)?;
-        let accounts = self.client.get_token_accounts_by_owner(&self.keypair.pubkey(), mint)?;
+        let accounts = self.client.get_token_accounts_by_owner(
+            &self.keypair.pubkey(),
+            TokenAccountsFilter::Mint(mint)
+        )?;
         let balance = if accounts.is_empty() {
             0.0
         } else {
-            let balance = self.client.get_token_account_balance(&accounts[0].pubkey)?;
+            let balance = self.client.get_token_account_balance(&Pubkey::from_str(&accounts[0].pubkey)?)?;
             balance.ui_amount.unwrap_or(0.0)
         };
         self.telegram.send_message(
@@ -76,100 +201,151 @@ impl Wallet {
     }
 
 
-    pub async fn ensure_ata(&self, token_mint: &Pubkey) -> Result<Pubkey, Box<dyn std::error::Error>> {
-    let ata = get_associated_token_address(&self.keypair.pubkey(), token_mint);
-    let account = self.client.get_account(&ata).await;
-    if account.is_err() {
-        let instruction = spl_associated_token_account::create_associated_token_account(
-            &self.keypair.pubkey(),
-            &self.keypair.pubkey(),
-            token_mint,
-            &TOKEN_PROGRAM_ID,
-        );
+    /// Simulates buying `token_mint` with `lamports_in`, first on its own and then
+    /// followed by a sell of `sell_amount` base units in the same transaction, so a
+    /// failing buy is not mistaken for a failing sell. Nothing is submitted.
+    pub fn simulate_round_trip(
+        &self,
+        pool_info: &super::price_feed::PoolInfo,
+        token_mint: &str,
+        lamports_in: u64,
+        sell_amount: u64
+    ) -> Result<RoundTripSimulation, Box<dyn std::error::Error>> {
+        let owner = self.keypair.pubkey();
+        let mint = Pubkey::from_str(token_mint)?;
+        let wsol_mint = Pubkey::from_str(super::price_feed::SOL_MINT)?;
+        let token_ata = get_associated_token_address_with_program_id(&owner, &mint, &self.token_program(&mint)?);
+        let wsol_ata = get_associated_token_address(&owner, &wsol_mint);
+        let balance_of = |ata: &Pubkey| {
+            self.client
+                .get_token_account_balance(ata)
+                .ok()
+                .and_then(|b| b.amount.parse::<u64>().ok())
+                .unwrap_or(0)
+        };
+        let token_balance_before = balance_of(&token_ata);
+        let wsol_balance_before = balance_of(&wsol_ata);
+
+        let mut instructions = self.buy_instructions(pool_info, token_mint, lamports_in, 0)?;
+        let (buy_error, buy_logs, after_buy) = self.simulate(&instructions, &[token_ata])?;
+        let mut simulation = RoundTripSimulation {
+            buy_error,
+            sell_error: None,
+            token_balance_before,
+            wsol_balance_before,
+            token_balance_after_buy: after_buy[0],
+            token_balance_after: None,
+            wsol_balance_after: None,
+            logs: buy_logs,
+        };
+        if simulation.buy_error.is_some() {
+            return Ok(simulation);
+        }
+
+        instructions.extend(self.sell_instructions(pool_info, token_mint, sell_amount, 0)?);
+        let (sell_error, logs, after) = self.simulate(&instructions, &[token_ata, wsol_ata])?;
+        simulation.sell_error = sell_error;
+        simulation.token_balance_after = after[0];
+        simulation.wsol_balance_after = after[1];
+        simulation.logs = logs;
+        Ok(simulation)
+    }
+
+    /// Simulates `instructions` and returns the error, the logs and the token amounts
+    /// held by `accounts` afterwards.
+    fn simulate(
+        &self,
+        instructions: &[Instruction],
+        accounts: &[Pubkey]
+    ) -> Result<SimulationOutcome, Box<dyn std::error::Error>> {
         let recent_blockhash = self.client.get_latest_blockhash()?;
         let tx = Transaction::new_signed_with_payer(
-            &[instruction],
+            instructions,
             Some(&self.keypair.pubkey()),
             &[&self.keypair],
-            recent_blockhash,
+            recent_blockhash
         );
-        self.client.send_and_confirm_transaction(&tx)?;
-        self.telegram
-            .send_message(&format!("Created ATA for mint {}", token_mint))
-            .await?;
+        let config = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            replace_recent_blockhash: true,
+            commitment: Some(CommitmentConfig::confirmed()),
+            accounts: Some(RpcSimulateTransactionAccountsConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                addresses: accounts.iter().map(|a| a.to_string()).collect(),
+            }),
+            ..RpcSimulateTransactionConfig::default()
+        };
+        let result = self.client.simulate_transaction_with_config(&tx, config)?.value;
+        let amounts = (0..accounts.len())
+            .map(|index| {
+                result.accounts
+                    .as_ref()
+                    .and_then(|accounts| accounts.get(index)?.as_ref()?.decode::<Account>())
+                    .and_then(|account| account.data.get(64..72).map(|b| u64::from_le_bytes(b.try_into().unwrap())))
+            })
+            .collect();
+        Ok((result.err.map(|e| e.to_string()), result.logs.unwrap_or_default(), amounts))
+    }
+
+    /// Program owning `mint`, SPL Token or Token-2022, which its token accounts must use.
+    fn token_program(&self, mint: &Pubkey) -> Result<Pubkey, Box<dyn std::error::Error>> {
+        Ok(self.client.get_account(mint)?.owner)
     }
-    Ok(ata)
-}
 
-    pub fn create_swap_instruction(
+    /// Swap keys of a Raydium AMM v4 pool and its market, read from chain.
+    fn swap_keys(&self, pool_info: &super::price_feed::PoolInfo) -> Result<AmmV4SwapKeys, Box<dyn std::error::Error>> {
+        let amm_id = Pubkey::from_str(&pool_info.id)?;
+        let amm = self.client.get_account_data(&amm_id)?;
+        let market_id = AmmV4SwapKeys::market_of(&amm)
+            .ok_or_else(|| format!("Pool {} is not a Raydium AMM v4 pool", pool_info.id))?;
+        let market = self.client.get_account_data(&market_id)?;
+        Ok(AmmV4SwapKeys::decode(amm_id, &amm, &market)
+            .ok_or_else(|| format!("Pool {} is not a Raydium AMM v4 pool", pool_info.id))?)
+    }
+
+    /// Instructions that wrap `lamports_in` SOL and swap it for `token_mint` on a Raydium AMM v4 pool.
+    pub fn buy_instructions(
         &self,
         pool_info: &super::price_feed::PoolInfo,
         token_mint: &str,
-        vs_token: &str,
-        amount_in: u64,
-        min_amount_out: u64,
-        is_base_to_quote: bool
-    ) -> Result<Instruction, Box<dyn std::error::Error>> {
-        let pool_id = Pubkey::from_str(&pool_info.id)?;
-        let base_mint = Pubkey::from_str(&pool_info.base_mint)?;
-        let quote_mint = Pubkey::from_str(&pool_info.quote_mint)?;
-        let base_vault = Pubkey::from_str(&pool_info.base_vault)?;
-        let quote_vault = Pubkey::from_str(&pool_info.quote_vault)?;
-        let market_id = Pubkey::from_str(&pool_info.market_id)?;
-        let raydium_program = Pubkey::from_str("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8")?;
-
-        let (input_mint, output_mint, input_vault, output_vault) = if is_base_to_quote {
-            (base_mint, quote_mint, base_vault, quote_vault)
-        } else {
-            (quote_mint, base_mint, quote_vault, base_vault)
-        };
-
-        let user_input_account = get_associated_token_address(&self.keypair.pubkey(), &input_mint);
-        let user_output_account = get_associated_token_address(
-            &self.keypair.pubkey(),
-            &output_mint
-        );
+        lamports_in: u64,
+        min_amount_out: u64
+    ) -> Result<Vec<Instruction>, Box<dyn std::error::Error>> {
+        let owner = self.keypair.pubkey();
+        let mint = Pubkey::from_str(token_mint)?;
+        let token_program = self.token_program(&mint)?;
+        let wsol_mint = Pubkey::from_str(super::price_feed::SOL_MINT)?;
+        let wsol_ata = get_associated_token_address(&owner, &wsol_mint);
+        let token_ata = get_associated_token_address_with_program_id(&owner, &mint, &token_program);
+        let keys = self.swap_keys(pool_info)?;
+        Ok(vec![
+            create_associated_token_account_idempotent(&owner, &owner, &wsol_mint, &TOKEN_PROGRAM_ID),
+            create_associated_token_account_idempotent(&owner, &owner, &mint, &token_program),
+            system_instruction::transfer(&owner, &wsol_ata, lamports_in),
+            spl_token::instruction::sync_native(&TOKEN_PROGRAM_ID, &wsol_ata)?,
+            keys.swap_base_in(&owner, &wsol_ata, &token_ata, lamports_in, min_amount_out),
+        ])
+    }
 
-        // Raydium swap instruction data: [instruction_id, amount_in, min_amount_out]
-        let data = vec![
-            9, // Swap instruction ID for Raydium AMM
-            amount_in.to_le_bytes().to_vec(),
-            min_amount_out.to_le_bytes().to_vec()
-        ].concat();
-
-        let accounts = vec![
-            AccountMeta::new_readonly(TOKEN_PROGRAM_ID, false),
-            AccountMeta::new(pool_id, false),
-            AccountMeta::new_readonly(
-                Pubkey::from_str("5Q544fKrFoe6tsEbD7S8EmxGTJYAKtTVhAW5Q5pge4j1")?,
-                false
-            ), // AMM authority
-            AccountMeta::new_readonly(market_id, false),
-            AccountMeta::new(user_input_account, false),
-            AccountMeta::new(user_output_account, false),
-            AccountMeta::new(input_vault, false),
-            AccountMeta::new(output_vault, false),
-            AccountMeta::new_readonly(
-                Pubkey::from_str("srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX")?,
-                false
-            ), // OpenBook program
-            AccountMeta::new_readonly(system_program::ID, false),
-            AccountMeta::new_readonly(rent::ID, false),
-            // Additional accounts (placeholder, adjust as per Raydium's IDL)
-            AccountMeta::new_readonly(Pubkey::from_str("11111111111111111111111111111111")?, false),
-            AccountMeta::new_readonly(Pubkey::from_str("11111111111111111111111111111111")?, false),
-            AccountMeta::new_readonly(Pubkey::from_str("11111111111111111111111111111111")?, false),
-            AccountMeta::new_readonly(Pubkey::from_str("11111111111111111111111111111111")?, false),
-            AccountMeta::new_readonly(Pubkey::from_str("11111111111111111111111111111111")?, false),
-            AccountMeta::new_readonly(Pubkey::from_str("11111111111111111111111111111111")?, false),
-            AccountMeta::new_readonly(Pubkey::from_str("11111111111111111111111111111111")?, false)
-        ];
-
-        Ok(Instruction {
-            program_id: raydium_program,
-            accounts,
-            data,
-        })
+    /// Instructions that swap `amount_in` base units of `token_mint` for wSOL on a Raydium
+    /// AMM v4 pool. The proceeds stay wrapped in the wSOL account.
+    pub fn sell_instructions(
+        &self,
+        pool_info: &super::price_feed::PoolInfo,
+        token_mint: &str,
+        amount_in: u64,
+        min_amount_out: u64
+    ) -> Result<Vec<Instruction>, Box<dyn std::error::Error>> {
+        let owner = self.keypair.pubkey();
+        let mint = Pubkey::from_str(token_mint)?;
+        let wsol_mint = Pubkey::from_str(super::price_feed::SOL_MINT)?;
+        let wsol_ata = get_associated_token_address(&owner, &wsol_mint);
+        let token_ata = get_associated_token_address_with_program_id(&owner, &mint, &self.token_program(&mint)?);
+        let keys = self.swap_keys(pool_info)?;
+        Ok(vec![
+            create_associated_token_account_idempotent(&owner, &owner, &wsol_mint, &TOKEN_PROGRAM_ID),
+            keys.swap_base_in(&owner, &token_ata, &wsol_ata, amount_in, min_amount_out),
+        ])
     }
 
     pub fn clone(&self) -> Self {
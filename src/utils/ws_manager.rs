@@ -0,0 +1,169 @@
+use futures_util::{ SinkExt, StreamExt };
+use log::{ info, warn };
+use serde_json::Value;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio::time::{ interval, sleep, Duration, Instant };
+use tokio_tungstenite::{ connect_async, tungstenite::Message };
+
+/// A named subscription. `request` builds the subscribe message for a JSON-RPC id.
+pub struct Subscription {
+    pub name: String,
+    pub request: fn(u64) -> String,
+}
+
+#[derive(Debug, Clone)]
+#[allow(dead_code)]
+pub enum WsEvent {
+    /// A notification for one of the active subscriptions. `result` is `params.result`.
+    Notification {
+        subscription: String,
+        subscription_id: u64,
+        slot: u64,
+        result: Value,
+    },
+    /// Slots were skipped across a reconnect. Notifications strictly between
+    /// `last_slot` and `resumed_slot` may have been missed.
+    Gap {
+        subscription: String,
+        last_slot: u64,
+        resumed_slot: u64,
+        /// Signature of the first notification after the gap, when the subscription carries one.
+        resumed_signature: Option<String>,
+    },
+}
+
+/// Keeps a websocket connection alive: pings, detects silent stalls, reconnects
+/// with exponential backoff, restores every subscription and reports slot gaps.
+pub struct WsManager {
+    url: String,
+    subscriptions: Vec<Subscription>,
+    ping_interval: Duration,
+    stall_timeout: Duration,
+    max_backoff: Duration,
+}
+
+impl WsManager {
+    pub fn new(url: String, subscriptions: Vec<Subscription>) -> Self {
+        WsManager {
+            url,
+            subscriptions,
+            ping_interval: Duration::from_secs(15),
+            stall_timeout: Duration::from_secs(45),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+
+    /// Runs until the event receiver is dropped.
+    pub async fn run(self, tx: mpsc::Sender<WsEvent>) {
+        let mut backoff = Duration::from_secs(1);
+        // Last slot seen per subscription name, kept across reconnects
+        let mut last_slots: HashMap<String, u64> = HashMap::new();
+        let mut reconnected = false;
+
+        loop {
+            match self.session(&tx, &mut last_slots, reconnected, &mut backoff).await {
+                Ok(()) => return,
+                Err(e) => warn!("Websocket {} disconnected: {}", self.url, e),
+            }
+            if tx.is_closed() {
+                return;
+            }
+            reconnected = true;
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(self.max_backoff);
+        }
+    }
+
+    /// One connection lifetime. Returns `Ok` only when the consumer has gone away.
+    async fn session(
+        &self,
+        tx: &mpsc::Sender<WsEvent>,
+        last_slots: &mut HashMap<String, u64>,
+        reconnected: bool,
+        backoff: &mut Duration
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (mut ws_stream, _) = connect_async(&self.url).await?;
+        for (i, subscription) in self.subscriptions.iter().enumerate() {
+            ws_stream.send(Message::Text((subscription.request)(i as u64 + 1).into())).await?;
+        }
+        info!("Websocket {} connected with {} subscriptions", self.url, self.subscriptions.len());
+
+        // Server-assigned subscription id -> index into self.subscriptions
+        let mut active: HashMap<u64, usize> = HashMap::new();
+        // Subscriptions still waiting for their first notification after a reconnect
+        let mut awaiting_resume: Vec<bool> = vec![reconnected; self.subscriptions.len()];
+        let mut last_seen = Instant::now();
+        let mut ping = interval(self.ping_interval);
+
+        loop {
+            tokio::select! {
+                _ = ping.tick() => {
+                    if last_seen.elapsed() > self.stall_timeout {
+                        return Err(format!("no traffic for {:?}", last_seen.elapsed()).into());
+                    }
+                    ws_stream.send(Message::Ping(Vec::new().into())).await?;
+                }
+                message = ws_stream.next() => {
+                    let message = message.ok_or("stream closed")??;
+                    last_seen = Instant::now();
+                    let data = match message {
+                        Message::Text(data) => data,
+                        Message::Close(frame) => return Err(format!("closed by server: {:?}", frame).into()),
+                        _ => continue,
+                    };
+                    let value: Value = match serde_json::from_str(&data) {
+                        Ok(value) => value,
+                        Err(_) => continue,
+                    };
+
+                    // Subscribe confirmation: {"id": <request id>, "result": <subscription id>}
+                    if let (Some(request_id), Some(subscription_id)) = (value["id"].as_u64(), value["result"].as_u64()) {
+                        if let Some(index) = request_id.checked_sub(1).map(|i| i as usize)
+                            && index < self.subscriptions.len()
+                        {
+                            active.insert(subscription_id, index);
+                            if active.len() == self.subscriptions.len() {
+                                *backoff = Duration::from_secs(1);
+                            }
+                        }
+                        continue;
+                    }
+
+                    let Some(subscription_id) = value["params"]["subscription"].as_u64() else { continue };
+                    let Some(&index) = active.get(&subscription_id) else { continue };
+                    let name = self.subscriptions[index].name.clone();
+                    let result = value["params"]["result"].clone();
+                    // slotSubscribe reports the slot directly rather than in a context
+                    let slot = result["context"]["slot"]
+                        .as_u64()
+                        .or_else(|| result["slot"].as_u64())
+                        .unwrap_or_default();
+
+                    if awaiting_resume[index] {
+                        awaiting_resume[index] = false;
+                        if let Some(&last_slot) = last_slots.get(&name) && slot > last_slot + 1 {
+                            let gap = WsEvent::Gap {
+                                subscription: name.clone(),
+                                last_slot,
+                                resumed_slot: slot,
+                                resumed_signature: result["value"]["signature"].as_str().map(String::from),
+                            };
+                            if tx.send(gap).await.is_err() {
+                                return Ok(());
+                            }
+                        }
+                    }
+                    if slot > 0 {
+                        last_slots.insert(name.clone(), slot);
+                    }
+
+                    let event = WsEvent::Notification { subscription: name, subscription_id, slot, result };
+                    if tx.send(event).await.is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+}